@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE post DROP COLUMN version;
+ALTER TABLE tag DROP COLUMN version;
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE post ADD COLUMN version INT NOT NULL DEFAULT 1;
+ALTER TABLE tag ADD COLUMN version INT NOT NULL DEFAULT 1;
@@ -0,0 +1,7 @@
+-- Your SQL goes here
+CREATE TABLE tag_alias (
+    name VARCHAR(100) NOT NULL,
+    tag_id INT NOT NULL,
+    PRIMARY KEY(name),
+    FOREIGN KEY(tag_id) REFERENCES tag(id) ON DELETE CASCADE
+);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE tag
+DROP FOREIGN KEY FK_tag_parent,
+DROP COLUMN parent_id;
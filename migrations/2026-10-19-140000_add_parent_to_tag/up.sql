@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE tag
+ADD COLUMN parent_id INT NULL,
+ADD CONSTRAINT FK_tag_parent FOREIGN KEY (parent_id) REFERENCES tag (id) ON DELETE SET NULL;
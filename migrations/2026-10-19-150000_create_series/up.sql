@@ -0,0 +1,17 @@
+-- Your SQL goes here
+CREATE TABLE series (
+    id INT NOT NULL AUTO_INCREMENT,
+    title VARCHAR(255) NOT NULL,
+    description TEXT NULL,
+    PRIMARY KEY(id)
+);
+
+CREATE TABLE series_posts (
+    series_id INT NOT NULL,
+    post_id INT NOT NULL,
+    position INT NOT NULL,
+    PRIMARY KEY(series_id, post_id),
+    CONSTRAINT UC_series_posts_post_id UNIQUE (post_id),
+    FOREIGN KEY(series_id) REFERENCES series(id) ON DELETE CASCADE,
+    FOREIGN KEY(post_id) REFERENCES post(id) ON DELETE CASCADE
+);
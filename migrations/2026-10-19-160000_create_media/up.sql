@@ -0,0 +1,21 @@
+-- Your SQL goes here
+CREATE TABLE media (
+    id INT NOT NULL AUTO_INCREMENT,
+    file_name VARCHAR(255) NOT NULL,
+    mime_type VARCHAR(100) NOT NULL,
+    size BIGINT NOT NULL,
+    checksum CHAR(64) NOT NULL,
+    uploader INT NULL,
+    created TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY(id),
+    INDEX IDX_media_checksum (checksum),
+    FOREIGN KEY(uploader) REFERENCES user(id) ON DELETE SET NULL
+);
+
+CREATE TABLE post_media (
+    post_id INT NOT NULL,
+    media_id INT NOT NULL,
+    PRIMARY KEY(post_id, media_id),
+    FOREIGN KEY(post_id) REFERENCES post(id) ON DELETE CASCADE,
+    FOREIGN KEY(media_id) REFERENCES media(id) ON DELETE CASCADE
+);
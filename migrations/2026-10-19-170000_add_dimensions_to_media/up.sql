@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE media
+    ADD COLUMN width INT NULL,
+    ADD COLUMN height INT NULL;
@@ -0,0 +1,19 @@
+-- Your SQL goes here
+CREATE TABLE audit_log (
+    id BIGINT NOT NULL AUTO_INCREMENT,
+    actor INT NULL,
+    action VARCHAR(10) NOT NULL,
+    resource VARCHAR(50) NOT NULL,
+    resource_id INT NULL,
+    method VARCHAR(10) NOT NULL,
+    path VARCHAR(255) NOT NULL,
+    status INT NOT NULL,
+    before_state MEDIUMTEXT NULL,
+    after_state MEDIUMTEXT NULL,
+    ip VARCHAR(45) NULL,
+    created TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY(id),
+    INDEX IDX_audit_log_actor (actor),
+    INDEX IDX_audit_log_resource (resource, resource_id),
+    INDEX IDX_audit_log_created (created)
+);
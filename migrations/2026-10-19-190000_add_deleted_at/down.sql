@@ -0,0 +1,12 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE user
+    DROP INDEX IDX_user_deleted_at,
+    DROP COLUMN deleted_at;
+
+ALTER TABLE tag
+    DROP INDEX IDX_tag_deleted_at,
+    DROP COLUMN deleted_at;
+
+ALTER TABLE post
+    DROP INDEX IDX_post_deleted_at,
+    DROP COLUMN deleted_at;
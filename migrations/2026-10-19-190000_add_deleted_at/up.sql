@@ -0,0 +1,12 @@
+-- Your SQL goes here
+ALTER TABLE post
+    ADD COLUMN deleted_at TIMESTAMP NULL,
+    ADD INDEX IDX_post_deleted_at (deleted_at);
+
+ALTER TABLE tag
+    ADD COLUMN deleted_at TIMESTAMP NULL,
+    ADD INDEX IDX_tag_deleted_at (deleted_at);
+
+ALTER TABLE user
+    ADD COLUMN deleted_at TIMESTAMP NULL,
+    ADD INDEX IDX_user_deleted_at (deleted_at);
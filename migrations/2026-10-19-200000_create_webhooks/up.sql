@@ -0,0 +1,34 @@
+-- Your SQL goes here
+CREATE TABLE webhook (
+    id INT PRIMARY KEY AUTO_INCREMENT,
+    url VARCHAR(255) NOT NULL,
+    secret VARCHAR(64) NOT NULL,
+    events VARCHAR(255) NOT NULL,
+    active BOOLEAN NOT NULL DEFAULT TRUE,
+    created TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE webhook_delivery (
+    id BIGINT PRIMARY KEY AUTO_INCREMENT,
+    webhook_id INT NOT NULL,
+    event VARCHAR(50) NOT NULL,
+    resource_id INT NULL,
+    payload MEDIUMTEXT NOT NULL,
+    status VARCHAR(10) NOT NULL DEFAULT 'pending',
+    attempts INT NOT NULL DEFAULT 0,
+    next_attempt TIMESTAMP NULL,
+    created TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    delivered TIMESTAMP NULL,
+    FOREIGN KEY(webhook_id) REFERENCES webhook(id) ON DELETE CASCADE,
+    INDEX IDX_webhook_delivery_due (status, next_attempt)
+);
+
+CREATE TABLE webhook_attempt (
+    id BIGINT PRIMARY KEY AUTO_INCREMENT,
+    delivery_id BIGINT NOT NULL,
+    attempted TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    response_status INT NULL,
+    error VARCHAR(255) NULL,
+    duration_ms INT NOT NULL,
+    FOREIGN KEY(delivery_id) REFERENCES webhook_delivery(id) ON DELETE CASCADE
+);
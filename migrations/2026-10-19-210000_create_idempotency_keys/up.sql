@@ -0,0 +1,14 @@
+-- Your SQL goes here
+CREATE TABLE idempotency_key (
+    id BIGINT PRIMARY KEY AUTO_INCREMENT,
+    idem_key VARCHAR(255) NOT NULL,
+    scope VARCHAR(64) NOT NULL,
+    fingerprint CHAR(64) NOT NULL,
+    status INT NULL,
+    content_type VARCHAR(255) NULL,
+    location VARCHAR(255) NULL,
+    body MEDIUMTEXT NULL,
+    created TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE INDEX UQ_idempotency_key (scope, idem_key),
+    INDEX IDX_idempotency_key_created (created)
+);
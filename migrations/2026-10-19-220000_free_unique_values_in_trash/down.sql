@@ -0,0 +1,8 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE user
+    DROP INDEX UC_email,
+    ADD CONSTRAINT UC_email UNIQUE (email);
+
+ALTER TABLE post
+    DROP INDEX UC_title_author,
+    ADD CONSTRAINT UC_title_author UNIQUE (title, author);
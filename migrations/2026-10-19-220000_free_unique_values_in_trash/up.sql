@@ -0,0 +1,10 @@
+-- Your SQL goes here
+-- A row in the trash drops out of the unique key: its last key part is NULL and NULLs never collide.
+-- Functional key parts need MySQL 8.0.13 or later.
+ALTER TABLE post
+    DROP INDEX UC_title_author,
+    ADD UNIQUE INDEX UC_title_author (title, author, (IF(deleted_at IS NULL, 1, NULL)));
+
+ALTER TABLE user
+    DROP INDEX UC_email,
+    ADD UNIQUE INDEX UC_email (email, (IF(deleted_at IS NULL, 1, NULL)));
@@ -1,8 +1,111 @@
-#[get("/")]
-pub async fn api_info() -> &'static str {
-    "
-    USAGE
+use std::collections::BTreeMap;
+use rocket::{Build, Rocket, State};
+use rocket::fairing;
+use rocket::serde::json::{Json, Value, json};
+use crate::openapi;
+use crate::version::ApiVersion;
+
+/*
+GET /api answers with a discovery document, built once at start up from rocket.routes().
+GET /api/v1 and GET /api/v2 answer with the one of their version, paths and links then carry the version's prefix.
+The v2 document leaves out the routes v2 does not serve, the v1 document marks them deprecated. See version.rs
+
+{
+  "name", "api_version",
+  "versions": [{"version", "href", "latest"}],
+  "build": {"version", "profile", "commit"},     commit is the GIT_COMMIT env value at compile time, null without it.
+  "_links": {"self", "openapi", "graphql", "<collection>": {"href"}},   One link per collection mounted under /api.
+  "routes": [{"path", "methods": [{"method", "access", "summary", "query", "formats", "filters", "deprecated", "successor"}]}]
+}
+
+access is public, session (needs the jwt cookie from POST /api/users/session) or admin, taken from the table in
+openapi.rs. filters is only there when the route takes a QParams: the operators, the columns they accept and the
+columns order accepts, per resource.
+Paths use the {param} form of the OpenAPI document at /openapi.json.
+*/
 
+pub struct Discovery(BTreeMap<ApiVersion, Value>);
 
-    "
-}
\ No newline at end of file
+//The QParams columns of the resource a path ends in, "/api/posts/{id}/tags" filters tags
+fn filters(path: &str) -> Value {
+    let resource = path.split('/').filter(|s| !s.starts_with('{')).last().unwrap_or("");
+    let (columns, order): (&[&str], &[&str]) = match resource {
+        "posts" => (&crate::post::routes::FILTER_COLUMNS[..], &crate::post::routes::ORDER_COLUMNS[..]),
+        "tags" => (&crate::tag::routes::FILTER_COLUMNS[..], &crate::tag::routes::ORDER_COLUMNS[..]),
+        _ => (&[], &[]),
+    };
+    json!({
+        "operators": ["eq", "like", "ge", "le", "between"],
+        "columns": columns,
+        "order": order,
+        "paging": ["start", "step"],
+    })
+}
+
+fn discovery(rocket: &Rocket<Build>, version: ApiVersion) -> Value {
+    //"/api/posts" -> "/api/v2/posts", /graphql is not versioned
+    let versioned = |path: &str| match path.strip_prefix("/api") {
+        Some(rest) => format!("{}{}", version.prefix(), rest),
+        None => path.to_string(),
+    };
+    let access = openapi::access();
+    let mut routes: BTreeMap<String, Vec<Value>> = BTreeMap::new();
+    let mut links = json!({
+        "self": {"href": version.prefix()},
+        "openapi": {"href": "/openapi.json"},
+        "graphql": {"href": "/graphql"},
+    });
+    for ((method, path), mounted) in openapi::mounted(rocket) {
+        if mounted.v1_only().is_some() && version != ApiVersion::V1 {
+            continue;
+        }
+        let (access, summary) = access.get(&(method.clone(), path.clone())).copied().unwrap_or(("unknown", ""));
+        let mut entry = json!({
+            "method": method,
+            "access": access,
+            "summary": summary,
+            "query": mounted.query,
+        });
+        if mounted.json_api {
+            entry["formats"] = json!(["application/json", "application/vnd.api+json"]);
+        }
+        if mounted.query_params {
+            entry["filters"] = filters(&path);
+        }
+        if let Some(successor) = mounted.v1_only() {
+            entry["deprecated"] = json!(true);
+            entry["successor"] = json!(successor);
+        }
+        //Collections are the first segment under /api, "/api/posts/{id}" links posts
+        if let Some(collection) = path.strip_prefix("/api/").and_then(|p| p.split('/').next()) {
+            links[collection] = json!({"href": versioned(&format!("/api/{}", collection))});
+        }
+        routes.entry(versioned(&path)).or_default().push(entry);
+    }
+    json!({
+        "name": "DennisMarwood.com",
+        "api_version": version.number(),
+        "versions": ApiVersion::ALL.iter().map(|v| json!({
+            "version": v.number(),
+            "href": v.prefix(),
+            "latest": *v == ApiVersion::LATEST,
+        })).collect::<Vec<Value>>(),
+        "build": {
+            "version": env!("CARGO_PKG_VERSION"),
+            "profile": if cfg!(debug_assertions) { "debug" } else { "release" },
+            "commit": option_env!("GIT_COMMIT"),
+        },
+        "_links": links,
+        "routes": routes.into_iter().map(|(path, methods)| json!({"path": path, "methods": methods})).collect::<Vec<Value>>(),
+    })
+}
+
+pub async fn attach(rocket: Rocket<Build>) -> fairing::Result {
+    let documents = ApiVersion::ALL.into_iter().map(|v| (v, discovery(&rocket, v))).collect();
+    Ok(rocket.manage(Discovery(documents)))
+}
+
+#[get("/")]
+pub async fn api_info(discovery: &State<Discovery>, version: ApiVersion) -> Json<Value> {
+    Json(discovery.0.get(&version).cloned().unwrap_or_default())
+}
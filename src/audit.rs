@@ -0,0 +1,404 @@
+use std::sync::{Arc, Mutex, PoisonError};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Method;
+use rocket::request::{FromRequest, Outcome};
+use rocket::serde::json::{Json, Value, json};
+use rocket::{Request, Response};
+use diesel::prelude::*;
+use crate::config::DbConn;
+use crate::error::ApiError;
+use crate::jwt::validate_jwt;
+use crate::models::{AResponse, AuditEntry, BlogEntry, EnvVariables, Media, Role, Series, Tag, User};
+use crate::schema::{audit_log, media, post, post_media, post_tags, role, series, series_posts, tag, user, user_tags};
+
+/*
+Every successful POST / PUT / PATCH / DELETE under /api leaves a row in audit_log. The AuditLog fairing writes it
+once the response is known. A request that failed changed nothing and is not logged.
+
+The actor is the user id in the jwt cookie, the ip is the client's as Rocket sees it (X-Real-IP unless ip_header is changed).
+
+Handlers tell the log which rows they change through the Trail guard:
+    trail.watch(&conn, Resource::Post, id).await?;      before changing a row, reads the before snapshot
+    trail.created(Resource::Post, id);                 after inserting a row
+    trail.watch_on(c, Resource::Post, id)?;             watch() inside conn.run
+The after snapshot is read by the fairing once the handler is done, a deleted row has none.
+The action follows from the snapshots. No before is a create, no after is a delete, both is an update.
+Posts, tags and users are only moved to the trash, deleted_at being set is a delete and it being cleared a restore.
+The purge job logs the rows it removes for good as purge, with no actor, method JOB and status 0. See trash.rs.
+Every entry for a post or tag is also announced to the webhooks that want it, see webhook.rs.
+Once the entries are committed, posts, tags and users go out on the live event stream, see events.rs.
+A request that watched nothing is still logged, with the resource and id taken from its path and no snapshots.
+Its action follows from the method, except for the session routes in SESSION_ACTIONS. Logging in and out is a
+login and logout of the resource session, not a change to users, and is not announced.
+POST /graphql is only logged when a mutation watched something, see graphql.rs.
+
+The snapshots are reads of their own, not part of the handler's transaction. A write that lands in between ends up
+in the wrong entry, but is not lost.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resource {
+    Post,
+    Tag,
+    User,
+    Role,
+    Series,
+    Media,
+}
+
+impl Resource {
+    //Named like the mount point, so logged paths and resources read the same
+    pub fn name(&self) -> &'static str {
+        match self {
+            Resource::Post => "posts",
+            Resource::Tag => "tags",
+            Resource::User => "users",
+            Resource::Role => "roles",
+            Resource::Series => "series",
+            Resource::Media => "media",
+        }
+    }
+}
+
+//The row as JSON, with the links that belong to it. None when there is no such row.
+pub fn snapshot(c: &mut MysqlConnection, resource: Resource, id: i32) -> QueryResult<Option<Value>> {
+    let found = match resource {
+        Resource::Post => post::table.filter(post::id.eq(id)).first::<BlogEntry>(c).optional()?.map(|p| json!(p)),
+        Resource::Tag => tag::table.filter(tag::id.eq(id)).first::<Tag>(c).optional()?.map(|t| json!(t)),
+        Resource::User => user::table.filter(user::id.eq(id)).select(User::as_select()).first::<User>(c).optional()?.map(|u| {
+            //Never keep a password hash around
+            let mut u = json!(u);
+            if let Some(fields) = u.as_object_mut() {
+                fields.remove("phc");
+            }
+            u
+        }),
+        Resource::Role => role::table.filter(role::id.eq(id)).first::<Role>(c).optional()?.map(|r| json!(r)),
+        Resource::Series => series::table.filter(series::id.eq(id)).first::<Series>(c).optional()?.map(|s| json!(s)),
+        Resource::Media => media::table.filter(media::id.eq(id)).first::<Media>(c).optional()?.map(|m| json!(m)),
+    };
+    let mut found = match found {
+        Some(found) => found,
+        None => return Ok(None),
+    };
+
+    match resource {
+        Resource::Post => {
+            found["tags"] = json!(post_tags::table.filter(post_tags::post_id.eq(id)).select(post_tags::tag_id).order(post_tags::tag_id.asc()).load::<i32>(c)?);
+        },
+        Resource::Tag => {
+            found["posts"] = json!(post_tags::table.filter(post_tags::tag_id.eq(id)).select(post_tags::post_id).order(post_tags::post_id.asc()).load::<i32>(c)?);
+            found["owners"] = json!(user_tags::table.filter(user_tags::tag_id.eq(id)).select(user_tags::user_id).order(user_tags::user_id.asc()).load::<i32>(c)?);
+        },
+        Resource::Series => {
+            found["posts"] = json!(series_posts::table.filter(series_posts::series_id.eq(id)).select(series_posts::post_id).order(series_posts::position.asc()).load::<i32>(c)?);
+        },
+        Resource::Media => {
+            found["posts"] = json!(post_media::table.filter(post_media::media_id.eq(id)).select(post_media::post_id).order(post_media::post_id.asc()).load::<i32>(c)?);
+        },
+        Resource::User | Resource::Role => (),
+    }
+    Ok(Some(found))
+}
+
+struct Watched {
+    resource: Resource,
+    id: i32,
+    before: Option<Value>,
+}
+
+//The rows a request changes. Lives in the request's local cache until the fairing takes it.
+//Clones share the rows, a GraphQL request hands one to its resolvers.
+#[derive(Default, Clone)]
+pub struct Trail {
+    watched: Arc<Mutex<Vec<Watched>>>,
+}
+
+impl Trail {
+    pub async fn watch(&self, conn: &DbConn, resource: Resource, id: i32) -> Result<(), ApiError> {
+        let before = conn.run(move |c| snapshot(c, resource, id)).await?;
+        self.push(resource, id, before);
+        Ok(())
+    }
+
+    //watch() for code that already holds a connection, such as the bulk routes
+    pub fn watch_on(&self, c: &mut MysqlConnection, resource: Resource, id: i32) -> Result<(), ApiError> {
+        let before = snapshot(c, resource, id)?;
+        self.push(resource, id, before);
+        Ok(())
+    }
+
+    pub fn created(&self, resource: Resource, id: i32) {
+        self.push(resource, id, None);
+    }
+
+    fn push(&self, resource: Resource, id: i32, before: Option<Value>) {
+        let mut watched = self.watched.lock().unwrap_or_else(PoisonError::into_inner);
+        //Watching a row twice keeps the first snapshot, it is the state before the request
+        if !watched.iter().any(|w| w.resource == resource && w.id == id) {
+            watched.push(Watched { resource, id, before });
+        }
+    }
+
+    //Keep the rows another trail watched. A GraphQL mutation watches into a trail of its own and hands it on once it succeeded.
+    pub fn absorb(&self, other: Trail) {
+        for w in other.take() {
+            self.push(w.resource, w.id, w.before);
+        }
+    }
+
+    fn take(&self) -> Vec<Watched> {
+        std::mem::take(&mut *self.watched.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest <'r> for &'r Trail {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<&'r Trail, ()> {
+        Outcome::Success(request.local_cache(Trail::default))
+    }
+}
+
+//What every entry of one request shares
+struct Context {
+    actor: Option<i32>,
+    method: String,
+    path: String,
+    status: i32,
+    ip: Option<String>,
+}
+
+//The user id in the jwt cookie, if any
+pub fn actor(request: &Request<'_>) -> Option<i32> {
+    let secret = request.rocket().state::<EnvVariables>()?.jwt_secret.clone();
+    let jwt = request.cookies().get("jwt")?;
+    validate_jwt(jwt.value(), secret.as_ref()).ok().map(|claims| claims.user_id)
+}
+
+//"/api/posts/5/tags" and "/api/v2/posts/5/tags" are posts 5
+fn from_path(path: &str) -> (String, Option<i32>) {
+    let path = crate::version::unversioned(path.split('?').next().unwrap_or(""));
+    let mut segments = path.split('/').filter(|s| !s.is_empty()).skip(1);
+    let resource = segments.next().unwrap_or("api").to_string();
+    (resource, segments.next().and_then(|id| id.parse::<i32>().ok()))
+}
+
+//Writes under /api/users that change no user, with the action they are logged as
+const SESSION_ACTIONS: [(&str, &str, &str); 3] = [
+    ("POST", "/api/users/session", "login"),
+    ("DELETE", "/api/users/session", "logout"),
+    ("POST", "/api/users/confirm_pw", "confirm_pw"),
+];
+
+fn session_action(method: &str, path: &str) -> Option<&'static str> {
+    let path = crate::version::unversioned(path.split('?').next().unwrap_or(""));
+    SESSION_ACTIONS.iter().find(|(m, p, _)| *m == method && *p == path).map(|(_, _, action)| *action)
+}
+
+fn insert(c: &mut MysqlConnection, context: &Context, action: &str, resource: &str, resource_id: Option<i32>, before: Option<Value>, after: Option<Value>) -> QueryResult<usize> {
+    diesel::insert_into(audit_log::table)
+        .values((
+            audit_log::actor.eq(context.actor),
+            audit_log::action.eq(action),
+            audit_log::resource.eq(resource),
+            audit_log::resource_id.eq(resource_id),
+            audit_log::method.eq(&context.method),
+            audit_log::path.eq(&context.path),
+            audit_log::status.eq(context.status),
+            audit_log::before_state.eq(before.map(|v| v.to_string())),
+            audit_log::after_state.eq(after.map(|v| v.to_string())),
+            audit_log::ip.eq(&context.ip),
+        ))
+        .execute(c)
+}
+
+fn trashed(state: &Value) -> bool {
+    state.get("deleted_at").is_some_and(|d| !d.is_null())
+}
+
+//Called by the purge job for a row it is about to remove, with the row's last snapshot
+pub fn purged(c: &mut MysqlConnection, resource: Resource, id: i32, before: Option<Value>) -> QueryResult<usize> {
+    let context = Context {
+        actor: None,
+        method: String::from("JOB"),
+        path: String::from("trash purge"),
+        status: 0,
+        ip: None,
+    };
+    insert(c, &context, "purge", resource.name(), Some(id), before, None)
+}
+
+//One change as the log recorded it
+pub struct Recorded {
+    pub resource: Resource,
+    pub id: i32,
+    pub action: &'static str,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+    //Database time of the change
+    pub at: chrono::NaiveDateTime,
+}
+
+fn write(c: &mut MysqlConnection, context: Context, watched: Vec<Watched>) -> QueryResult<Vec<Recorded>> {
+    if let Some(action) = session_action(&context.method, &context.path) {
+        insert(c, &context, action, "session", None, None, None)?;
+        return Ok(Vec::new());
+    }
+    if watched.is_empty() {
+        let action = match context.method.as_str() {
+            "POST" => "create",
+            "DELETE" => "delete",
+            _ => "update",
+        };
+        let (resource, id) = from_path(&context.path);
+        insert(c, &context, action, &resource, id, None, None)?;
+        return Ok(Vec::new());
+    }
+
+    c.transaction(|c| {
+        let at = crate::webhook::db_now(c)?;
+        let mut recorded = Vec::with_capacity(watched.len());
+        for w in watched {
+            let after = snapshot(c, w.resource, w.id)?;
+            let action = match (&w.before, &after) {
+                (None, _) => "create",
+                (Some(_), None) => "delete",
+                (Some(before), Some(after)) => match (trashed(before), trashed(after)) {
+                    (false, true) => "delete",
+                    (true, false) => "restore",
+                    _ => "update",
+                },
+            };
+            crate::webhook::announce(c, w.resource, w.id, action, w.before.as_ref(), after.as_ref(), at)?;
+            insert(c, &context, action, w.resource.name(), Some(w.id), w.before.clone(), after.clone())?;
+            recorded.push(Recorded { resource: w.resource, id: w.id, action, before: w.before, after, at });
+        }
+        Ok(recorded)
+    })
+}
+
+pub struct AuditLog;
+
+#[rocket::async_trait]
+impl Fairing for AuditLog {
+    fn info(&self) -> Info {
+        Info {
+            name: "Audit Log",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let changes = matches!(request.method(), Method::Post | Method::Put | Method::Patch | Method::Delete);
+        let path = request.uri().path();
+        let graphql = path.as_str() == "/graphql";
+        if !changes || !(path.as_str().starts_with("/api/") || graphql) || !response.status().class().is_success() {
+            return;
+        }
+
+        let context = Context {
+            actor: actor(request),
+            method: request.method().as_str().to_string(),
+            path: request.uri().to_string().chars().take(255).collect(),
+            status: response.status().code as i32,
+            ip: request.client_ip().map(|ip| ip.to_string()),
+        };
+        let watched = request.local_cache(Trail::default).take();
+        //Every GraphQL request is a POST, only mutations watch anything
+        if graphql && watched.is_empty() {
+            return;
+        }
+
+        //The change is already made, a missing entry can only be reported
+        let conn = match DbConn::get_one(request.rocket()).await {
+            Some(conn) => conn,
+            None => {
+                error!("Audit log: no database connection, {} {} was not logged.", context.method, context.path);
+                return;
+            },
+        };
+        match conn.run(move |c| write(c, context, watched)).await {
+            Ok(recorded) => {
+                if let Some(hub) = request.rocket().state::<crate::events::Hub>() {
+                    recorded.into_iter().for_each(|r| hub.publish(r));
+                }
+            },
+            Err(e) => error!("Audit log: could not write the entry: {:?}", e),
+        }
+    }
+}
+
+pub mod routes {
+    use super::*;
+    use crate::auth::Level1;
+    use chrono::{NaiveDate, NaiveDateTime};
+
+    //Accepts 2026-10-19 or 2026-10-19T12:00:00. A plain date as the upper bound covers the whole day.
+    fn parse_time(field: &str, value: &str, end_of_day: bool) -> Result<NaiveDateTime, ApiError> {
+        if let Ok(time) = NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S") {
+            return Ok(time);
+        }
+        let date = NaiveDate::parse_from_str(value, "%Y-%m-%d")
+            .map_err(|_| ApiError::bad_request(Some(format!("{} must be a date such as 2026-10-19 or a date and time such as 2026-10-19T12:00:00.", field))))?;
+        let time = match end_of_day {
+            true => date.and_hms_opt(23, 59, 59),
+            false => date.and_hms_opt(0, 0, 0),
+        };
+        time.ok_or_else(ApiError::internal)
+    }
+
+    fn entry_json(e: AuditEntry) -> Value {
+        let parse = |state: Option<String>| state.and_then(|s| serde_json::from_str::<Value>(&s).ok());
+        json!({
+            "id": e.id,
+            "actor": e.actor,
+            "action": e.action,
+            "resource": e.resource,
+            "resource_id": e.resource_id,
+            "method": e.method,
+            "path": e.path,
+            "status": e.status,
+            "before": parse(e.before_state),
+            "after": parse(e.after_state),
+            "ip": e.ip,
+            "created": e.created,
+        })
+    }
+
+    //Newest first
+    #[get("/?<actor>&<resource>&<resource_id>&<from>&<to>&<start>&<step>")]
+    pub async fn get_all(actor: Option<i32>, resource: Option<String>, resource_id: Option<i32>, from: Option<String>, to: Option<String>,
+        start: Option<i64>, step: Option<i64>, conn: DbConn, _x: Level1) -> Result<Json<AResponse>, ApiError> {
+        let from = from.map(|f| parse_time("from", &f, false)).transpose()?;
+        let to = to.map(|t| parse_time("to", &t, true)).transpose()?;
+
+        let entries = conn.run(move |c| {
+            let mut query = audit_log::table.into_boxed();
+            if let Some(actor) = actor {
+                query = query.filter(audit_log::actor.eq(actor));
+            }
+            if let Some(resource) = resource {
+                query = query.filter(audit_log::resource.eq(resource));
+            }
+            if let Some(resource_id) = resource_id {
+                query = query.filter(audit_log::resource_id.eq(resource_id));
+            }
+            if let Some(from) = from {
+                query = query.filter(audit_log::created.ge(from));
+            }
+            if let Some(to) = to {
+                query = query.filter(audit_log::created.le(to));
+            }
+            query
+                .order(audit_log::id.desc())
+                .offset(start.unwrap_or(0))
+                .limit(step.unwrap_or(100))
+                .load::<AuditEntry>(c)
+        }).await?;
+
+        let entries = entries.into_iter().map(entry_json).collect::<Vec<Value>>();
+        Ok(Json(AResponse::_200(Some(json!(entries)))))
+    }
+}
@@ -0,0 +1,192 @@
+use diesel::prelude::*;
+use rocket::serde::Deserialize;
+use rocket::serde::json::{Json, Value, json};
+use crate::audit::Trail;
+use crate::error::ApiError;
+use crate::models::AResponse;
+
+/*
+POST /api/posts/bulk and POST /api/tags/bulk run a list of writes in one request, for moving content in.
+
+{
+  "mode": "atomic",          atomic (the default) or per_item
+  "operations": [
+    {"op": "create", "post": {"title": "...", "content": "..."}},
+    {"op": "patch", "id": 3, "post": {"title": "..."}, "version": 4},
+    {"op": "delete", "id": 7},
+    {"op": "attach_tags", "id": 3, "tags": {"id": [1], "name": ["rust"]}, "replace": false}
+  ]
+}
+Tags take create, patch and delete with a "tag" in place of "post".
+
+Each operation is what its single route does, with the same validation, access and audit entries. version stands in
+for If-Match, without it the operation is refused when require_if_match is set. Tags named in attach_tags that do
+not exist are created for the caller. A bulk delete never purges media, use DELETE /api/posts/<id>?purge_media=true.
+
+atomic runs everything in one transaction. Either all of it is applied, 200 with a result per operation, or none of
+it, answered with the error of the first operation that failed and its index in errors.
+per_item runs each operation in a transaction of its own and answers 207 with a result per operation:
+    {"index": 0, "status": 201, "id": 12}
+    {"index": 1, "status": 422, "code": "INVALID_INPUT", "detail": "...", "errors": [{"field": "title", ...}]}
+
+[default.bulk]
+max_operations = 1000        Larger lists are refused with a 400.
+The json body limit in [default.limits] also bounds a bulk request.
+*/
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(crate = "rocket::serde")]
+pub struct BulkConfig {
+    #[serde(default = "BulkConfig::default_max_operations")]
+    pub max_operations: usize,
+}
+
+impl Default for BulkConfig {
+    fn default() -> Self {
+        BulkConfig {
+            max_operations: BulkConfig::default_max_operations(),
+        }
+    }
+}
+
+impl BulkConfig {
+    fn default_max_operations() -> usize {
+        1000
+    }
+}
+
+#[derive(serde::Deserialize, schemars::JsonSchema, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Mode {
+    #[default]
+    Atomic,
+    PerItem,
+}
+
+#[derive(serde::Deserialize, schemars::JsonSchema)]
+pub struct Bulk<Op> {
+    #[serde(default)]
+    pub mode: Mode,
+    pub operations: Vec<Op>,
+}
+
+impl<Op> Bulk<Op> {
+    pub fn check(&self, config: &BulkConfig) -> Result<(), ApiError> {
+        match self.operations.len() {
+            0 => Err(ApiError::bad_request(Some(String::from("No operations were sent.")))),
+            n if n > config.max_operations => Err(ApiError::bad_request(Some(format!("At most {} operations are accepted, {} were sent.", config.max_operations, n)))),
+            _ => Ok(()),
+        }
+    }
+}
+
+//What happened to one operation
+#[derive(serde::Serialize, schemars::JsonSchema)]
+pub struct ItemResult {
+    pub index: usize,
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub errors: Option<Value>,
+}
+
+//A successful operation, before it knows its index
+pub struct Done {
+    status: u16,
+    id: Option<i32>,
+    data: Option<Value>,
+}
+
+impl Done {
+    pub fn created(id: Option<i32>) -> Self {
+        Done { status: 201, id, data: None }
+    }
+
+    pub fn applied(id: i32) -> Self {
+        Done { status: 204, id: Some(id), data: None }
+    }
+
+    pub fn with(id: i32, data: Value) -> Self {
+        Done { status: 200, id: Some(id), data: Some(data) }
+    }
+
+    fn at(self, index: usize) -> ItemResult {
+        ItemResult { index, status: self.status, id: self.id, data: self.data, code: None, detail: None, errors: None }
+    }
+}
+
+fn failed(index: usize, e: ApiError) -> ItemResult {
+    ItemResult { index, status: e.status.code, id: None, data: None, code: Some(e.code.to_string()), detail: e.detail, errors: e.errors }
+}
+
+//Like the Level1 guard, for operations only admins may run
+pub fn admin_only(admin: bool) -> Result<(), ApiError> {
+    match admin {
+        true => Ok(()),
+        false => Err(ApiError::forbidden(Some(String::from("Only admins may run this operation.")))),
+    }
+}
+
+#[derive(Responder)]
+pub enum Outcome {
+    #[response(status = 200)]
+    Applied(Json<AResponse>),
+    #[response(status = 207)]
+    MultiStatus(Json<AResponse>),
+}
+
+//Runs the operations the way bulk.mode asks. Each operation watches into a trail of its own, the request's trail only
+//gets the ones that were kept.
+pub fn run<Op, F>(c: &mut MysqlConnection, bulk: Bulk<Op>, trail: &Trail, apply: F) -> Result<Outcome, ApiError>
+where
+    F: Fn(&mut MysqlConnection, Op, &Trail) -> Result<Done, ApiError>,
+{
+    match bulk.mode {
+        Mode::Atomic => {
+            let (results, trails) = c.transaction(|c| {
+                let mut results = Vec::new();
+                let mut trails = Vec::new();
+                for (index, op) in bulk.operations.into_iter().enumerate() {
+                    let item = Trail::default();
+                    match apply(c, op, &item) {
+                        Ok(done) => {
+                            results.push(done.at(index));
+                            trails.push(item);
+                        },
+                        Err(e) => {
+                            let status = e.status;
+                            let code = e.code;
+                            let failure = failed(index, e);
+                            return Err(ApiError::new(status, code, Some(format!("Operation {} failed, nothing was applied.", index)))
+                                .with_errors(json!([failure])));
+                        },
+                    }
+                }
+                Ok::<_, ApiError>((results, trails))
+            })?;
+            trails.into_iter().for_each(|item| trail.absorb(item));
+            Ok(Outcome::Applied(Json(AResponse::_200(Some(json!(results))))))
+        },
+        Mode::PerItem => {
+            let mut results = Vec::new();
+            for (index, op) in bulk.operations.into_iter().enumerate() {
+                let item = Trail::default();
+                match c.transaction(|c| apply(c, op, &item)) {
+                    Ok(done) => {
+                        results.push(done.at(index));
+                        trail.absorb(item);
+                    },
+                    Err(e) => results.push(failed(index, e)),
+                }
+            }
+            Ok(Outcome::MultiStatus(Json(AResponse::_200(Some(json!(results))))))
+        },
+    }
+}
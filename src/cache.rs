@@ -0,0 +1,186 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use rocket::http::{Header, Status};
+use rocket::request::{FromRequest, Outcome};
+use rocket::response::{self, Responder, Response};
+use rocket::serde::json::Json;
+use rocket::serde::Serialize;
+use rocket::Request;
+use crate::error::ApiError;
+use crate::models::EnvVariables;
+use crate::myjsonapi::Document;
+
+/*
+Conditional GET for the read endpoints.
+
+Every cached response carries a strong ETag, a hash of the exact body that is sent. Posts also carry a
+Last-Modified built from last_updated. A client that sends back either validator gets a 304 Not Modified
+with no body while the resource is unchanged.
+
+    GET /api/posts/3                        ->  200  ETag: "5f1c0e9a7b2d4c11"  Last-Modified: Tue, 01 Aug 2023 00:00:00 GMT
+    GET /api/posts/3
+    If-None-Match: "5f1c0e9a7b2d4c11"       ->  304
+
+If-None-Match wins when both are sent (RFC 9110 13.2.2).
+last_updated only has a date, so a post counts as modified at any point of that day. If-Modified-Since
+only produces a 304 once the whole day has passed.
+
+Anonymous responses may be stored by shared caches for PUBLIC_MAX_AGE seconds. Responses to a logged in
+user are private and must be revalidated every time.
+
+Optimistic concurrency
+Posts and tags have a version column that goes up by one on every write. A single post or tag is tagged
+"v<version>-<hash>". Writes compare If-Match against the version part only:
+
+    PATCH /api/posts/3
+    If-Match: "v4-5f1c0e9a7b2d4c11"         ->  204 when the post is still at version 4
+                                            ->  412 with the current version when someone else wrote first
+
+If-Match is optional unless require_if_match is set in Rocket.toml, then a write without it is a 428.
+*/
+
+const PUBLIC_MAX_AGE: u32 = 60;
+
+//Strong validator for a representation. The same body always hashes to the same tag.
+pub fn etag_of<T: Serialize>(representation: &T) -> String {
+    let mut hasher = DefaultHasher::new();
+    match serde_json::to_vec(representation) {
+        Ok(bytes) => bytes.hash(&mut hasher),
+        Err(_) => return String::from("\"0\""),
+    }
+    format!("\"{:016x}\"", hasher.finish())
+}
+
+//"v4-5f1c0e9a7b2d4c11", "v4" and "4" all name version 4.
+fn version_in(tag: &str) -> Option<i32> {
+    let tag = tag.trim().trim_start_matches("W/").trim_matches('"');
+    let tag = tag.strip_prefix('v').unwrap_or(tag);
+    tag.split('-').next().and_then(|v| v.parse::<i32>().ok())
+}
+
+pub struct Cached<R> {
+    inner: R,
+    etag: String,
+    last_modified: Option<chrono::NaiveDate>,
+}
+
+impl<R> Cached<R> {
+    pub fn new(inner: R, etag: String) -> Self {
+        Cached { inner, etag, last_modified: None }
+    }
+
+    pub fn last_modified(mut self, date: Option<chrono::NaiveDate>) -> Self {
+        self.last_modified = date;
+        self
+    }
+
+    //A single post or tag. Writes only look at the version part, the hash still changes with the body.
+    pub fn versioned(mut self, version: i32) -> Self {
+        self.etag = format!("\"v{}-{}", version, self.etag.trim_start_matches('"'));
+        self
+    }
+}
+
+impl<T: Serialize> Cached<Json<T>> {
+    pub fn json(value: T) -> Self {
+        let etag = etag_of(&value);
+        Cached::new(Json(value), etag)
+    }
+}
+
+impl Cached<Document> {
+    pub fn document(document: Document) -> Self {
+        let etag = etag_of(&document.body);
+        Cached::new(document, etag)
+    }
+}
+
+//The If-Match header of a write, if any. Check it against the stored version before changing anything.
+#[derive(Clone)]
+pub struct IfMatch {
+    versions: Option<Vec<String>>,
+    required: bool,
+}
+
+impl IfMatch {
+    //For writes that carry the version some other way than the header, such as a GraphQL argument
+    pub fn from_version(version: Option<i32>, required: bool) -> Self {
+        IfMatch { versions: version.map(|v| vec![v.to_string()]), required }
+    }
+
+    pub fn check(&self, current_version: i32) -> Result<(), ApiError> {
+        match &self.versions {
+            None if self.required => Err(ApiError::precondition_required()),
+            None => Ok(()),
+            Some(tags) if tags.iter().any(|t| t == "*" || version_in(t) == Some(current_version)) => Ok(()),
+            Some(_) => Err(ApiError::precondition_failed(current_version)),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest <'r> for IfMatch {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<IfMatch, ()> {
+        let required = match request.rocket().state::<EnvVariables>() {
+            Some(env) => env.require_if_match,
+            None => false,
+        };
+        let versions = request.headers().get_one("If-Match")
+            .map(|h| h.split(',').map(|t| t.trim().to_string()).collect());
+        Outcome::Success(IfMatch { versions, required })
+    }
+}
+
+//Weak comparison, as required for If-None-Match. W/"x" matches "x".
+fn etag_matches(header: &str, etag: &str) -> bool {
+    header.split(',')
+        .map(|t| t.trim())
+        .any(|t| t == "*" || t.trim_start_matches("W/") == etag)
+}
+
+fn not_modified(req: &Request<'_>, etag: &str, last_modified: Option<chrono::NaiveDate>) -> bool {
+    if let Some(if_none_match) = req.headers().get_one("If-None-Match") {
+        return etag_matches(if_none_match, etag);
+    }
+
+    match (req.headers().get_one("If-Modified-Since"), last_modified) {
+        (Some(since), Some(date)) => match chrono::DateTime::parse_from_rfc2822(since) {
+            Ok(since) => match date.succ_opt().and_then(|d| d.and_hms_opt(0, 0, 0)) {
+                Some(end_of_day) => since.naive_utc() >= end_of_day,
+                None => false,
+            },
+            Err(_) => false, //Invalid dates are ignored
+        },
+        _ => false,
+    }
+}
+
+impl<'r, R: Responder<'r, 'static>> Responder<'r, 'static> for Cached<R> {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = match not_modified(req, &self.etag, self.last_modified) {
+            true => Response::build().status(Status::NotModified).finalize(),
+            false => self.inner.respond_to(req)?,
+        };
+
+        //Errors and other statuses are never cached.
+        if response.status() != Status::Ok && response.status() != Status::NotModified {
+            return Ok(response);
+        }
+
+        response.set_header(Header::new("ETag", self.etag));
+        if let Some(date) = self.last_modified.and_then(|d| d.and_hms_opt(0, 0, 0)) {
+            response.set_header(Header::new("Last-Modified", date.format("%a, %d %b %Y %H:%M:%S GMT").to_string()));
+        }
+
+        let cache_control = match req.cookies().get("jwt") {
+            Some(_) => String::from("private, no-cache"),
+            None => format!("public, max-age={}", PUBLIC_MAX_AGE),
+        };
+        response.set_header(Header::new("Cache-Control", cache_control));
+        //JSON:API and plain clients get different bodies from the same url.
+        response.set_header(Header::new("Vary", "Accept, Cookie"));
+        Ok(response)
+    }
+}
@@ -0,0 +1,309 @@
+use rocket::{Build, Data, Request, Response, Rocket};
+use rocket::fairing::{self, Fairing, Info, Kind};
+use rocket::http::Method;
+use rocket::serde::Deserialize;
+use regex::Regex;
+use rocket_cors::{AllowedHeaders, AllowedOrigins, Cors, CorsOptions};
+use crate::models::EnvVariables;
+
+/*
+The CORS policy is read from the active profile in Rocket.toml (or ROCKET_CORS_* env values), next to jwt_secret.
+
+[default.cors]
+allowed_origins = ["^vscode-webview://(.+)"]    Entries starting with "^" are regexes, "*" allows every origin.
+allowed_methods = ["GET"]
+allowed_headers = ["Authorization", "Accept"]
+expose_headers = ["ETag"]                       Response headers scripts may read. Optional.
+allow_credentials = true
+max_age = 3600
+
+[default.cors.public]                           Optional. Looser policy for anonymous reads.
+mounts = ["/api/posts", "/api/tags"]
+allowed_origins = ["*"]
+
+The public policy only ever applies to GET / HEAD requests (and their preflights) under one of its mounts.
+Anything else under those mounts, such as a PATCH to a post, still has to satisfy the main policy.
+A request from an origin of the main policy, or one carrying a Cookie or Authorization header, is served by the main
+policy everywhere, so a logged in admin reading drafts on the site still gets a credentialed answer.
+*/
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(crate = "rocket::serde")]
+pub struct CorsConfig {
+    #[serde(default = "CorsConfig::default_origins")]
+    pub allowed_origins: Vec<String>,
+    #[serde(default = "CorsConfig::default_methods")]
+    pub allowed_methods: Vec<String>,
+    #[serde(default = "CorsConfig::default_headers")]
+    pub allowed_headers: Vec<String>,
+    #[serde(default)]
+    pub expose_headers: Vec<String>,
+    #[serde(default = "CorsConfig::default_credentials")]
+    pub allow_credentials: bool,
+    #[serde(default)]
+    pub max_age: Option<usize>,
+    #[serde(default)]
+    pub public: Option<PublicCorsConfig>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(crate = "rocket::serde")]
+pub struct PublicCorsConfig {
+    pub mounts: Vec<String>,
+    #[serde(default = "PublicCorsConfig::default_origins")]
+    pub allowed_origins: Vec<String>,
+    #[serde(default = "CorsConfig::default_headers")]
+    pub allowed_headers: Vec<String>,
+    #[serde(default)]
+    pub max_age: Option<usize>,
+}
+
+impl Default for CorsConfig {
+    //Matches the policy that used to be hard coded in main.rs.
+    fn default() -> Self {
+        CorsConfig {
+            allowed_origins: CorsConfig::default_origins(),
+            allowed_methods: CorsConfig::default_methods(),
+            allowed_headers: CorsConfig::default_headers(),
+            expose_headers: Vec::new(),
+            allow_credentials: CorsConfig::default_credentials(),
+            max_age: None,
+            public: None,
+        }
+    }
+}
+
+impl CorsConfig {
+    fn default_origins() -> Vec<String> {
+        vec![String::from("^vscode-webview://(.+)")]
+    }
+
+    fn default_methods() -> Vec<String> {
+        vec![String::from("GET")]
+    }
+
+    fn default_headers() -> Vec<String> {
+        vec![String::from("Authorization"), String::from("Accept")]
+    }
+
+    fn default_credentials() -> bool {
+        true
+    }
+
+    fn to_cors(&self) -> Result<Cors, String> {
+        let mut allowed_methods = Vec::with_capacity(self.allowed_methods.len());
+        for m in &self.allowed_methods {
+            match m.to_uppercase().parse::<rocket_cors::Method>() {
+                Ok(method) => allowed_methods.push(method),
+                Err(_) => return Err(format!("cors.allowed_methods: '{}' is not an http method.", m)),
+            }
+        }
+
+        build_cors(
+            "cors",
+            &self.allowed_origins,
+            allowed_methods,
+            &self.allowed_headers,
+            &self.expose_headers,
+            self.allow_credentials,
+            self.max_age,
+        )
+    }
+}
+
+impl PublicCorsConfig {
+    fn default_origins() -> Vec<String> {
+        vec![String::from("*")]
+    }
+
+    fn to_cors(&self) -> Result<Cors, String> {
+        if self.mounts.is_empty() {
+            return Err(String::from("cors.public.mounts: At least one mount point is required."));
+        }
+        if let Some(m) = self.mounts.iter().find(|m| !m.starts_with('/')) {
+            return Err(format!("cors.public.mounts: '{}' must start with '/'.", m));
+        }
+
+        //Read only. Never send credentials on the public policy, the main policy handles logged in users.
+        build_cors(
+            "cors.public",
+            &self.allowed_origins,
+            vec![Method::Get.into(), Method::Head.into()],
+            &self.allowed_headers,
+            &[String::from("ETag"), String::from("Last-Modified")],
+            false,
+            self.max_age,
+        )
+    }
+
+    fn covers(&self, request: &Request<'_>) -> bool {
+        //"/api/v2/posts" is under the "/api/posts" mount
+        let path = crate::version::unversioned(request.uri().path().as_str());
+        let mounted = self.mounts.iter().any(|m| {
+            let m = m.trim_end_matches('/');
+            path == m || path.starts_with(&format!("{}/", m))
+        });
+        if !mounted {
+            return false;
+        }
+
+        //A preflight is judged by the method the browser intends to use, not by OPTIONS itself.
+        let method = match request.method() {
+            Method::Options => request
+                .headers()
+                .get_one("Access-Control-Request-Method")
+                .and_then(|m| m.parse::<Method>().ok()),
+            m => Some(m),
+        };
+        matches!(method, Some(Method::Get) | Some(Method::Head))
+    }
+}
+
+fn build_cors(
+    section: &str,
+    origins: &[String],
+    allowed_methods: Vec<rocket_cors::Method>,
+    headers: &[String],
+    expose_headers: &[String],
+    allow_credentials: bool,
+    max_age: Option<usize>,
+) -> Result<Cors, String> {
+    if origins.is_empty() {
+        return Err(format!("{}.allowed_origins: At least one origin is required.", section));
+    }
+
+    let allowed_origins = if origins.iter().any(|o| o == "*") {
+        //Browsers refuse credentialed responses that allow every origin.
+        if allow_credentials {
+            return Err(format!("{}: allow_credentials can not be used with the '*' origin.", section));
+        }
+        AllowedOrigins::all()
+    } else {
+        let (regex, exact): (Vec<&String>, Vec<&String>) = origins.iter().partition(|o| o.starts_with('^'));
+        AllowedOrigins::some(&exact, &regex)
+    };
+
+    let allowed_headers = match headers.iter().any(|h| h == "*") {
+        true => AllowedHeaders::all(),
+        false => AllowedHeaders::some(&headers.iter().map(|h| h.as_str()).collect::<Vec<&str>>()),
+    };
+
+    let options = CorsOptions {
+        allowed_origins,
+        allowed_methods: allowed_methods.into_iter().collect(),
+        allowed_headers,
+        expose_headers: expose_headers.iter().cloned().collect(),
+        allow_credentials,
+        max_age,
+        ..Default::default()
+    };
+
+    options.to_cors().map_err(|e| format!("{}: {}", section, e))
+}
+
+//Remembers which policy validated the request so the response is finished by the same one.
+struct PublicPolicy(bool);
+
+//The origins of the main policy, matched the way rocket_cors matches them
+struct Origins {
+    any: bool,
+    exact: Vec<String>,
+    patterns: Vec<Regex>,
+}
+
+impl Origins {
+    fn new(origins: &[String]) -> Result<Self, String> {
+        let mut patterns = Vec::new();
+        for o in origins.iter().filter(|o| o.starts_with('^')) {
+            patterns.push(Regex::new(o).map_err(|e| format!("cors.allowed_origins: '{}' is not a regex. {}", o, e))?);
+        }
+        Ok(Origins {
+            any: origins.iter().any(|o| o == "*"),
+            exact: origins.iter().filter(|o| !o.starts_with('^')).cloned().collect(),
+            patterns,
+        })
+    }
+
+    fn matches(&self, origin: &str) -> bool {
+        self.any || self.exact.iter().any(|o| o == origin) || self.patterns.iter().any(|p| p.is_match(origin))
+    }
+}
+
+pub struct CorsPolicies {
+    default: Cors,
+    origins: Origins,
+    public: Option<(PublicCorsConfig, Cors)>,
+}
+
+impl CorsPolicies {
+    pub fn from_config(config: &CorsConfig) -> Result<Self, String> {
+        let default = config.to_cors()?;
+        let origins = Origins::new(&config.allowed_origins)?;
+        let public = match &config.public {
+            Some(p) => Some((p.clone(), p.to_cors()?)),
+            None => None,
+        };
+        Ok(CorsPolicies { default, origins, public })
+    }
+
+    //Requests the public policy may answer, anonymous reads from origins the main policy does not know
+    fn public_covers(&self, request: &Request<'_>) -> bool {
+        let Some((config, _)) = &self.public else { return false };
+        let headers = request.headers();
+        let credentialed = headers.contains("Cookie") || headers.contains("Authorization");
+        let known = headers.get_one("Origin").is_some_and(|o| self.origins.matches(o));
+        config.covers(request) && !credentialed && !known
+    }
+
+    fn policy(&self, public: bool) -> &Cors {
+        match (&self.public, public) {
+            (Some((_, cors)), true) => cors,
+            _ => &self.default,
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for CorsPolicies {
+    fn info(&self) -> Info {
+        Info {
+            name: "CORS Policies",
+            kind: Kind::Ignite | Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        //Both policies share the default error route, only mount it once.
+        self.default.on_ignite(rocket).await
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, data: &mut Data<'_>) {
+        let public = self.public_covers(request);
+        request.local_cache(|| PublicPolicy(public));
+        self.policy(public).on_request(request, data).await
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let public = request.local_cache(|| PublicPolicy(false)).0;
+        self.policy(public).on_response(request, response).await
+    }
+}
+
+//Extract the policy from the active profile and refuse to launch if it is invalid.
+pub async fn attach_from_config(rocket: Rocket<Build>) -> fairing::Result {
+    let config = match rocket.figment().extract::<EnvVariables>() {
+        Ok(env) => env.cors,
+        Err(e) => {
+            error!("Could not read the cors configuration: {}", e);
+            return Err(rocket);
+        }
+    };
+
+    match CorsPolicies::from_config(&config) {
+        Ok(policies) => Ok(rocket.attach(policies)),
+        Err(e) => {
+            error!("Invalid cors configuration. {}", e);
+            Err(rocket)
+        }
+    }
+}
@@ -0,0 +1,206 @@
+use std::io::Cursor;
+use diesel::result::DatabaseErrorKind::{UniqueViolation, ForeignKeyViolation, NotNullViolation, CheckViolation};
+use diesel::result::Error::{DatabaseError, NotFound, QueryBuilderError, RollbackErrorOnCommit};
+use rocket::http::{ContentType, Status};
+use rocket::response::{self, Responder, Response};
+use rocket::serde::json::{Value, json};
+use rocket::Request;
+use crate::myjsonapi::{self, JSONAPIError};
+
+/*
+Every error leaving the api is an ApiError. It is rendered as an RFC 7807 "problem details" document:
+
+    HTTP/1.1 409 Conflict
+    Content-Type: application/problem+json
+
+    {
+        "type": "about:blank",
+        "title": "Conflict",
+        "status": 409,
+        "code": "UNIQUE_VIOLATION",
+        "detail": "A resource with the same unique value already exists.",
+        "instance": "/api/tags"
+    }
+
+"code" is stable and is what clients should match on, "detail" is for humans and may change.
+Field level problems (bad input) are listed under "errors" as [{"field": ..., "message": ...}].
+Anything the database or a library reports is logged here and never sent to the client.
+JSON:API clients get the same information as a {"errors": [...]} document instead, see myjsonapi.rs.
+*/
+
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: Status,
+    pub code: &'static str,
+    pub detail: Option<String>,
+    pub errors: Option<Value>,
+}
+
+impl ApiError {
+    pub fn new(status: Status, code: &'static str, detail: Option<String>) -> Self {
+        ApiError { status, code, detail, errors: None }
+    }
+
+    pub fn bad_request(detail: Option<String>) -> Self {
+        ApiError::new(Status::BadRequest, "INVALID_USER_INPUT", detail)
+    }
+
+    pub fn unauthorized(detail: Option<String>) -> Self {
+        ApiError::new(Status::Unauthorized, "UNAUTHORIZED", detail)
+    }
+
+    pub fn forbidden(detail: Option<String>) -> Self {
+        ApiError::new(Status::Forbidden, "FORBIDDEN", detail)
+    }
+
+    pub fn not_found(detail: Option<String>) -> Self {
+        ApiError::new(Status::NotFound, "NOT_FOUND", detail)
+    }
+
+    pub fn conflict(detail: Option<String>) -> Self {
+        ApiError::new(Status::Conflict, "CONFLICT", detail)
+    }
+
+    //If-Match named a version that is no longer current. The client should re-read the resource and retry.
+    pub fn precondition_failed(current_version: i32) -> Self {
+        ApiError {
+            status: Status::PreconditionFailed,
+            code: "STALE_VERSION",
+            detail: Some(format!("The resource was changed by someone else. The current version is {}.", current_version)),
+            errors: Some(json!([{"field": "If-Match", "message": "Does not match the current version.", "current_version": current_version}])),
+        }
+    }
+
+    pub fn precondition_required() -> Self {
+        ApiError::new(Status::PreconditionRequired, "PRECONDITION_REQUIRED", Some(String::from("Send the resource's ETag in an If-Match header.")))
+    }
+
+    //Input failed validation. errors holds one {"field", "message"} entry per problem.
+    pub fn invalid_input(errors: Value) -> Self {
+        ApiError {
+            status: Status::UnprocessableEntity,
+            code: "INVALID_INPUT",
+            detail: Some(String::from("Correct input and try again.")),
+            errors: Some(errors),
+        }
+    }
+
+    pub fn internal() -> Self {
+        ApiError::new(Status::InternalServerError, "INTERNAL_SERVER_ERROR", Some(String::from("Our apologies, something went wrong.")))
+    }
+
+    //Log the real cause server side, hand the client a generic 500.
+    pub fn internal_from<E: std::fmt::Debug>(e: E) -> Self {
+        error!("Internal error: {:?}", e);
+        ApiError::internal()
+    }
+
+    //A status with no further context. Used by the catchers when a guard or data parser fails.
+    pub fn from_status(status: Status) -> Self {
+        match status.code {
+            400 => ApiError::bad_request(Some(String::from("The request could not be understood."))),
+            401 => ApiError::unauthorized(Some(String::from("The JWT is not present or is no longer valid."))),
+            403 => ApiError::forbidden(Some(String::from("Your role does not allow this action."))),
+            404 => ApiError::not_found(Some(String::from("The requested resource could not be found."))),
+            412 => ApiError::new(status, "STALE_VERSION", None),
+            415 => ApiError::new(status, "UNSUPPORTED_MEDIA_TYPE", Some(String::from("Send the request body as application/json."))),
+            422 => ApiError::new(status, "UNPROCESSABLE_ENTITY", Some(String::from("The request body could not be parsed. Check the field names and types."))),
+            500..=599 => ApiError::internal(),
+            _ => ApiError::new(status, "UNEXPECTED_ERROR_TYPE", None),
+        }
+    }
+
+    pub fn with_errors(mut self, errors: Value) -> Self {
+        self.errors = Some(errors);
+        self
+    }
+
+    fn to_problem(&self, instance: String) -> Value {
+        let mut problem = json!({
+            "type": "about:blank",
+            "title": self.status.reason().unwrap_or("Unknown"),
+            "status": self.status.code,
+            "code": self.code,
+            "instance": instance,
+        });
+        if let Some(detail) = &self.detail {
+            problem["detail"] = json!(detail);
+        }
+        if let Some(errors) = &self.errors {
+            problem["errors"] = errors.clone();
+        }
+        problem
+    }
+
+    fn to_json_api(&self) -> Value {
+        myjsonapi::Document::errors(vec![JSONAPIError {
+            status: self.status.code.to_string(),
+            code: String::from(self.code),
+            title: String::from(self.status.reason().unwrap_or("Unknown")),
+            detail: self.detail.clone(),
+            meta: self.errors.clone().map(|errors| json!({"errors": errors})),
+        }])
+    }
+}
+
+impl From<diesel::result::Error> for ApiError {
+    fn from(e: diesel::result::Error) -> Self {
+        match e {
+            NotFound =>
+                ApiError::not_found(Some(String::from("The requested resource could not be found."))),
+
+            DatabaseError(UniqueViolation, d) => {
+                info!("Unique violation: {}", d.message());
+                ApiError::new(Status::Conflict, "UNIQUE_VIOLATION", Some(String::from("A resource with the same unique value already exists.")))
+            },
+
+            DatabaseError(ForeignKeyViolation, d) => {
+                info!("Foreign key violation: {}", d.message());
+                ApiError::new(Status::UnprocessableEntity, "FOREIGN_KEY_VIOLATION", Some(String::from("A referenced resource does not exist or is still in use.")))
+            },
+
+            DatabaseError(NotNullViolation, d) => {
+                info!("Not null violation: {}", d.message());
+                ApiError::new(Status::UnprocessableEntity, "NOT_NULL_VIOLATION", Some(String::from("A required field was missing.")))
+            },
+
+            DatabaseError(CheckViolation, d) => {
+                info!("Check violation: {}", d.message());
+                ApiError::new(Status::UnprocessableEntity, "CHECK_VIOLATION", Some(String::from("A field holds a value that is not allowed.")))
+            },
+
+            //Diesel reports an empty changeset this way.
+            QueryBuilderError(_) =>
+                ApiError::bad_request(Some(String::from("The body of your request did not contain any fields to update."))),
+
+            RollbackErrorOnCommit { rollback_error, commit_error } => {
+                error!("Rollback failed after commit error. rollback: {:?} commit: {:?}", rollback_error, commit_error);
+                ApiError::new(Status::InternalServerError, "ROLL_BACK_ERROR", Some(String::from("The change could not be saved.")))
+            },
+
+            e => ApiError::internal_from(e),
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for ApiError {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        if myjsonapi::wants_json_api(req) {
+            let status = self.status;
+            return myjsonapi::Document { status, body: self.to_json_api(), location: None }.respond_to(req);
+        }
+
+        let body = self.to_problem(req.uri().to_string()).to_string();
+        Response::build()
+            .status(self.status)
+            .header(ContentType::new("application", "problem+json"))
+            .sized_body(body.len(), Cursor::new(body))
+            .ok()
+    }
+}
+
+//Anything that falls through without a handler (guard failures, bad json, no route) still gets a problem document.
+#[catch(default)]
+pub fn default_catcher(status: Status, _req: &Request) -> ApiError {
+    ApiError::from_status(status)
+}
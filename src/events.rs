@@ -0,0 +1,241 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, PoisonError};
+use rocket::request::{FromRequest, Outcome};
+use rocket::serde::json::{Value, json};
+use rocket::tokio::sync::broadcast;
+use rocket::Request;
+use crate::audit::{Recorded, Resource};
+
+/*
+GET /api/events is a Server-Sent Events stream of changes to posts, tags and users, so the dashboard does not have
+to poll. Every change the audit log records goes out once it is committed, see audit.rs.
+
+    id: 1760896800000
+    event: post.updated
+    data: {"resource": "posts", "id": 3, "action": "update", "data": {...the post after the change...}}
+
+Events are named like the webhook events, see webhook.rs, with user.created, user.updated, user.deleted and
+user.restored added. A deleted item carries its last state.
+
+Who sees what:
+    admins               everything
+    any other session    tags, published posts and their own user
+A post counts when it was published before or after the change, an unpublished draft stays hidden.
+
+The stream ends when the session's jwt expires, the client logs in again and reconnects.
+
+Browsers reconnect on their own and send Last-Event-ID. The last REPLAY events are kept in memory, the ones after
+that id are sent first. When the id is older than what is kept, or comes from before a restart, the stream starts
+with a reset event instead and the client should load what it shows again. The same happens when a slow client
+falls so far behind that events were dropped.
+Ids count up from the time the server started, in milliseconds, so ids from before a restart are always older.
+*/
+
+//Events kept for Last-Event-ID
+const REPLAY: usize = 200;
+//Events a subscriber may fall behind before it gets a reset
+const CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Audience {
+    Everyone,
+    Admins,
+    //Admins and this user
+    User(i32),
+}
+
+#[derive(Debug)]
+pub struct ChangeEvent {
+    pub id: u64,
+    pub name: String,
+    pub data: Value,
+    audience: Audience,
+}
+
+impl ChangeEvent {
+    pub fn visible_to(&self, viewer: &Viewer) -> bool {
+        match self.audience {
+            Audience::Everyone => true,
+            Audience::Admins => viewer.admin,
+            Audience::User(id) => viewer.admin || viewer.user_id == id,
+        }
+    }
+}
+
+pub struct Viewer {
+    pub user_id: i32,
+    pub admin: bool,
+}
+
+//What a new subscriber gets first
+pub enum Replay {
+    Events(Vec<Arc<ChangeEvent>>),
+    //Events were missed, start over
+    Reset,
+}
+
+struct Buffer {
+    next_id: u64,
+    events: VecDeque<Arc<ChangeEvent>>,
+}
+
+pub struct Hub {
+    sender: broadcast::Sender<Arc<ChangeEvent>>,
+    buffer: Mutex<Buffer>,
+}
+
+impl Default for Hub {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(CAPACITY);
+        Hub {
+            sender,
+            buffer: Mutex::new(Buffer {
+                next_id: chrono::Utc::now().timestamp_millis().max(1) as u64,
+                events: VecDeque::with_capacity(REPLAY),
+            }),
+        }
+    }
+}
+
+fn singular(resource: Resource) -> Option<&'static str> {
+    match resource {
+        Resource::Post => Some("post"),
+        Resource::Tag => Some("tag"),
+        Resource::User => Some("user"),
+        _ => None,
+    }
+}
+
+impl Hub {
+    pub fn publish(&self, recorded: Recorded) {
+        let prefix = match singular(recorded.resource) {
+            Some(prefix) => prefix,
+            None => return,
+        };
+        let audience = match recorded.resource {
+            Resource::Post => {
+                let published = [&recorded.before, &recorded.after].iter().any(|s| s.as_ref().is_some_and(|s| crate::webhook::published(s, recorded.at)));
+                if published { Audience::Everyone } else { Audience::Admins }
+            },
+            Resource::User => Audience::User(recorded.id),
+            _ => Audience::Everyone,
+        };
+        let data = json!({
+            "resource": recorded.resource.name(),
+            "id": recorded.id,
+            "action": recorded.action,
+            "data": recorded.after.or(recorded.before),
+        });
+
+        //Sending under the lock keeps the buffer and the channel in the same order, see subscribe
+        let mut buffer = self.buffer.lock().unwrap_or_else(PoisonError::into_inner);
+        let event = Arc::new(ChangeEvent { id: buffer.next_id, name: format!("{}.{}", prefix, recorded.action), data, audience });
+        buffer.next_id += 1;
+        if buffer.events.len() == REPLAY {
+            buffer.events.pop_front();
+        }
+        buffer.events.push_back(event.clone());
+        //No receivers is not an error, nobody is listening
+        let _ = self.sender.send(event);
+    }
+
+    //Every event after last_id is either in the replay or comes through the receiver, never both
+    pub fn subscribe(&self, last_id: Option<u64>) -> (broadcast::Receiver<Arc<ChangeEvent>>, Replay) {
+        let buffer = self.buffer.lock().unwrap_or_else(PoisonError::into_inner);
+        let receiver = self.sender.subscribe();
+        let last_id = match last_id {
+            Some(last_id) => last_id,
+            None => return (receiver, Replay::Events(Vec::new())),
+        };
+
+        let oldest = buffer.events.front().map_or(buffer.next_id, |e| e.id);
+        let replay = if last_id + 1 < oldest || last_id >= buffer.next_id {
+            Replay::Reset
+        } else {
+            Replay::Events(buffer.events.iter().filter(|e| e.id > last_id).cloned().collect())
+        };
+        (receiver, replay)
+    }
+}
+
+//The Last-Event-ID header a reconnecting browser sends
+pub struct LastEventId(pub Option<u64>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest <'r> for LastEventId {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<LastEventId, ()> {
+        Outcome::Success(LastEventId(request.headers().get_one("Last-Event-ID").and_then(|id| id.trim().parse::<u64>().ok())))
+    }
+}
+
+pub mod routes {
+    use super::*;
+    use std::time::Duration;
+    use rocket::http::CookieJar;
+    use rocket::response::stream::{Event, EventStream};
+    use rocket::tokio::select;
+    use rocket::tokio::sync::broadcast::error::RecvError;
+    use rocket::tokio::time::sleep;
+    use rocket::{Shutdown, State};
+    use crate::auth::{AdminUser, ValidSession};
+    use crate::jwt::validate_jwt;
+    use crate::models::EnvVariables;
+
+    fn event(e: &ChangeEvent) -> Event {
+        Event::json(&e.data).id(e.id.to_string()).event(e.name.clone())
+    }
+
+    fn reset() -> Event {
+        Event::json(&json!({"message": "Events were missed, load the current state again."})).event("reset")
+    }
+
+    #[get("/")]
+    pub fn stream(user: ValidSession, admin: Option<AdminUser>, last: LastEventId, jar: &CookieJar<'_>, env: &State<EnvVariables>,
+        hub: &State<Hub>, mut end: Shutdown) -> EventStream![] {
+        let viewer = Viewer { user_id: user.id, admin: admin.is_some() };
+        //The guard already checked the jwt, only its expiry is needed here
+        let expires = jar.get("jwt")
+            .and_then(|jwt| validate_jwt(jwt.value(), env.jwt_secret.as_ref()).ok())
+            .map_or(0, |claims| claims.exp as i64);
+        let left = Duration::from_secs((expires - chrono::Utc::now().timestamp()).max(0) as u64);
+        let (mut receiver, replay) = hub.subscribe(last.0);
+
+        EventStream! {
+            let mut last_sent = 0;
+            match replay {
+                Replay::Reset => {
+                    yield reset();
+                },
+                Replay::Events(events) => for e in events {
+                    last_sent = e.id;
+                    if e.visible_to(&viewer) {
+                        yield event(&e);
+                    }
+                },
+            }
+
+            let expired = sleep(left);
+            rocket::tokio::pin!(expired);
+            loop {
+                let received = select! {
+                    received = receiver.recv() => received,
+                    _ = &mut expired => break,
+                    _ = &mut end => break,
+                };
+                match received {
+                    Ok(e) if e.id > last_sent && e.visible_to(&viewer) => {
+                        last_sent = e.id;
+                        yield event(&e);
+                    },
+                    Ok(_) => (),
+                    Err(RecvError::Lagged(_)) => {
+                        yield reset();
+                    },
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,735 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Arc;
+use async_graphql::dataloader::{DataLoader, Loader};
+use async_graphql::{ComplexObject, Context, EmptySubscription, Error, ErrorExtensions, InputObject, Object, Result, Schema, SimpleObject};
+use chrono::{NaiveDate, NaiveDateTime};
+use diesel::prelude::*;
+use rocket::fairing;
+use rocket::serde::Deserialize;
+use rocket::{Build, Rocket};
+use crate::audit::Trail;
+use crate::cache::IfMatch;
+use crate::config::DbConn;
+use crate::error::ApiError;
+use crate::idempotency::Keyed;
+use crate::models::{BlogEntry, EnvVariables, Filters, QParams, Role, Tag};
+use crate::schema::{post, post_tags, role, tag, user, user_tags};
+use crate::user::routes::UserWithoutPHC;
+
+/*
+POST /graphql answers GraphQL queries over posts, tags, users and roles, so a page needs one request instead of
+several. GET /graphql/playground is an in-browser editor for it, unless playground is turned off.
+
+    query {
+        posts(step: 5, order: ["-created"]) {
+            title
+            author { firstName lastName }
+            tags { name parent { name } }
+        }
+    }
+
+The relations follow the joinable! declarations in schema.rs:
+    Post.tags, Tag.posts        through post_tags
+    Tag.owners, User.tags       through user_tags
+    User.role, Role.users       through user.role
+Tag.parent and Tag.children follow parent_id, Post.author the user id the post was written by.
+Relations are loaded in batches, the tags of twenty posts are one query and not twenty. See Db.
+
+posts and tags take the paging, order and filters of GET /api/posts and GET /api/tags:
+    posts(start: 10, step: 10, order: ["title"], filter: {like: ["title=%rust%"]})
+
+Access is the same as over REST:
+    posts, tags              anyone
+    users' names             anyone, they are shown as authors
+    the rest of a user       admins and the user themselves
+    users, roles             admins
+Mutations need the same session or admin role as the REST route doing the same write, and are audited, announced
+to webhooks and sent on the event stream like it. Posts and tags take the version the client last saw instead of an
+If-Match header: updatePost(id: 3, title: "...", version: 4). Without a version the write is refused when
+require_if_match is set.
+
+Errors carry the code and status the REST route would have answered with:
+    {"message": "...", "extensions": {"code": "PRECONDITION_FAILED", "status": 412}}
+
+Only application/json is accepted, a plain html form cannot post a query with the user's cookie.
+
+[default.graphql]
+max_depth = 10               Deepest nesting of fields a query may have.
+max_complexity = 1000        Every field counts one, a list of posts or tags counts its step times its fields.
+                             A step is cut down to QParams::MAX_STEP (1000), like on GET /api/posts and /api/tags.
+playground = true            Serve GET /graphql/playground.
+*/
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(crate = "rocket::serde")]
+pub struct GraphQLConfig {
+    #[serde(default = "GraphQLConfig::default_depth")]
+    pub max_depth: usize,
+    #[serde(default = "GraphQLConfig::default_complexity")]
+    pub max_complexity: usize,
+    #[serde(default = "GraphQLConfig::default_playground")]
+    pub playground: bool,
+}
+
+impl Default for GraphQLConfig {
+    fn default() -> Self {
+        GraphQLConfig {
+            max_depth: GraphQLConfig::default_depth(),
+            max_complexity: GraphQLConfig::default_complexity(),
+            playground: GraphQLConfig::default_playground(),
+        }
+    }
+}
+
+impl GraphQLConfig {
+    fn default_depth() -> usize {
+        10
+    }
+
+    fn default_complexity() -> usize {
+        1000
+    }
+
+    fn default_playground() -> bool {
+        true
+    }
+}
+
+pub type BlogSchema = Schema<Query, Mutation, EmptySubscription>;
+
+pub async fn attach(rocket: Rocket<Build>) -> fairing::Result {
+    let config = match rocket.figment().extract::<EnvVariables>() {
+        Ok(env) => env.graphql,
+        Err(e) => {
+            error!("Could not read the graphql configuration: {}", e);
+            return Err(rocket);
+        }
+    };
+
+    let schema = Schema::build(Query, Mutation, EmptySubscription)
+        .limit_depth(config.max_depth)
+        .limit_complexity(config.max_complexity)
+        .finish();
+    Ok(rocket.manage(schema))
+}
+
+//Errors carry the same code and status the REST route would answer with
+fn gql(e: ApiError) -> Error {
+    let message = e.detail.clone().unwrap_or_else(|| e.status.reason().unwrap_or("Error").to_string());
+    Error::new(message).extend_with(|_, ext| {
+        ext.set("code", e.code);
+        ext.set("status", e.status.code as i32);
+        if let Some(errors) = &e.errors {
+            ext.set("errors", async_graphql::Value::from_json(errors.clone()).unwrap_or(async_graphql::Value::Null));
+        }
+    })
+}
+
+fn db(e: diesel::result::Error) -> Error {
+    gql(ApiError::from(e))
+}
+
+//Who is asking, and what the resolvers share for one request
+struct Caller {
+    conn: Arc<DbConn>,
+    session: Option<i32>,
+    admin: bool,
+    require_if_match: bool,
+    trail: Trail,
+}
+
+impl Caller {
+    //Like the ValidSession guard
+    fn session(&self) -> Result<crate::auth::ValidSession> {
+        match self.session {
+            Some(id) => Ok(crate::auth::ValidSession { id }),
+            None => Err(gql(ApiError::unauthorized(None))),
+        }
+    }
+
+    //Like the Level1 guard
+    fn level1(&self) -> Result<()> {
+        self.session()?;
+        match self.admin {
+            true => Ok(()),
+            false => Err(gql(ApiError::forbidden(None))),
+        }
+    }
+
+    fn admin_or_self(&self, id: i32) -> Result<()> {
+        let session = self.session()?;
+        match self.admin || session.id == id {
+            true => Ok(()),
+            false => Err(gql(ApiError::forbidden(None))),
+        }
+    }
+
+    fn if_match(&self, version: Option<i32>) -> IfMatch {
+        IfMatch::from_version(version, self.require_if_match)
+    }
+
+    //A mutation watches into a trail of its own, the audit log only gets it when the mutation succeeded
+    fn keep<T>(&self, trail: Trail, result: Result<T, ApiError>) -> Result<T> {
+        let value = result.map_err(gql)?;
+        self.trail.absorb(trail);
+        Ok(value)
+    }
+
+    async fn post(&self, id: i32) -> Result<Option<PostNode>> {
+        let found = self.conn.run(move |c| {
+            post::table.filter(post::id.eq(id)).filter(post::deleted_at.is_null()).first::<BlogEntry>(c).optional()
+        }).await.map_err(db)?;
+        Ok(found.map(PostNode::from))
+    }
+
+    async fn tag(&self, id: i32) -> Result<Option<TagNode>> {
+        let found = self.conn.run(move |c| {
+            tag::table.filter(tag::id.eq(id)).filter(tag::deleted_at.is_null()).select(Tag::as_select()).first::<Tag>(c).optional()
+        }).await.map_err(db)?;
+        Ok(found.map(TagNode::from))
+    }
+}
+
+fn caller<'a>(ctx: &Context<'a>) -> &'a Caller {
+    ctx.data_unchecked::<Caller>()
+}
+
+fn loader<'a>(ctx: &Context<'a>) -> &'a DataLoader<Db> {
+    ctx.data_unchecked::<DataLoader<Db>>()
+}
+
+//Batches the lookups of one request. Every key type below is one query for all keys asked for at the same time.
+struct Db(Arc<DbConn>);
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct UserId(i32);
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct TagId(i32);
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct RoleId(i32);
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct TagsOfPost(i32);
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct PostsOfTag(i32);
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct ChildrenOfTag(i32);
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct OwnersOfTag(i32);
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct TagsOfUser(i32);
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct UsersOfRole(i32);
+
+fn grouped<K: Hash + Eq, V, N: From<V>>(rows: Vec<(i32, V)>, key: fn(i32) -> K) -> HashMap<K, Vec<N>> {
+    let mut groups: HashMap<K, Vec<N>> = HashMap::new();
+    for (id, row) in rows {
+        groups.entry(key(id)).or_default().push(N::from(row));
+    }
+    groups
+}
+
+#[rocket::async_trait]
+impl Loader<UserId> for Db {
+    type Value = UserNode;
+    type Error = Error;
+
+    async fn load(&self, keys: &[UserId]) -> Result<HashMap<UserId, UserNode>> {
+        let ids: Vec<i32> = keys.iter().map(|k| k.0).collect();
+        let users = self.0.run(move |c| {
+            user::table.filter(user::id.eq_any(ids)).filter(user::deleted_at.is_null()).select(UserWithoutPHC::as_select()).load(c)
+        }).await.map_err(db)?;
+        Ok(users.into_iter().map(|u| (UserId(u.id), UserNode::from(u))).collect())
+    }
+}
+
+#[rocket::async_trait]
+impl Loader<TagId> for Db {
+    type Value = TagNode;
+    type Error = Error;
+
+    async fn load(&self, keys: &[TagId]) -> Result<HashMap<TagId, TagNode>> {
+        let ids: Vec<i32> = keys.iter().map(|k| k.0).collect();
+        let tags = self.0.run(move |c| {
+            tag::table.filter(tag::id.eq_any(ids)).filter(tag::deleted_at.is_null()).select(Tag::as_select()).load(c)
+        }).await.map_err(db)?;
+        Ok(tags.into_iter().map(|t| (TagId(t.id), TagNode::from(t))).collect())
+    }
+}
+
+#[rocket::async_trait]
+impl Loader<RoleId> for Db {
+    type Value = RoleNode;
+    type Error = Error;
+
+    async fn load(&self, keys: &[RoleId]) -> Result<HashMap<RoleId, RoleNode>> {
+        let ids: Vec<i32> = keys.iter().map(|k| k.0).collect();
+        let roles = self.0.run(move |c| role::table.filter(role::id.eq_any(ids)).load::<Role>(c)).await.map_err(db)?;
+        Ok(roles.into_iter().map(|r| (RoleId(r.id), RoleNode::from(r))).collect())
+    }
+}
+
+#[rocket::async_trait]
+impl Loader<TagsOfPost> for Db {
+    type Value = Vec<TagNode>;
+    type Error = Error;
+
+    async fn load(&self, keys: &[TagsOfPost]) -> Result<HashMap<TagsOfPost, Vec<TagNode>>> {
+        let ids: Vec<i32> = keys.iter().map(|k| k.0).collect();
+        let rows = self.0.run(move |c| {
+            post_tags::table
+                .inner_join(tag::table)
+                .filter(post_tags::post_id.eq_any(ids))
+                .filter(tag::deleted_at.is_null())
+                .order(tag::name.asc())
+                .select((post_tags::post_id, Tag::as_select()))
+                .load::<(i32, Tag)>(c)
+        }).await.map_err(db)?;
+        Ok(grouped(rows, TagsOfPost))
+    }
+}
+
+#[rocket::async_trait]
+impl Loader<PostsOfTag> for Db {
+    type Value = Vec<PostNode>;
+    type Error = Error;
+
+    async fn load(&self, keys: &[PostsOfTag]) -> Result<HashMap<PostsOfTag, Vec<PostNode>>> {
+        let ids: Vec<i32> = keys.iter().map(|k| k.0).collect();
+        let rows = self.0.run(move |c| {
+            post_tags::table
+                .inner_join(post::table)
+                .filter(post_tags::tag_id.eq_any(ids))
+                .filter(post::deleted_at.is_null())
+                .order(post::created.desc())
+                .select((post_tags::tag_id, post::all_columns))
+                .load::<(i32, BlogEntry)>(c)
+        }).await.map_err(db)?;
+        Ok(grouped(rows, PostsOfTag))
+    }
+}
+
+#[rocket::async_trait]
+impl Loader<ChildrenOfTag> for Db {
+    type Value = Vec<TagNode>;
+    type Error = Error;
+
+    async fn load(&self, keys: &[ChildrenOfTag]) -> Result<HashMap<ChildrenOfTag, Vec<TagNode>>> {
+        let ids: Vec<i32> = keys.iter().map(|k| k.0).collect();
+        let children = self.0.run(move |c| {
+            tag::table
+                .filter(tag::parent_id.eq_any(ids))
+                .filter(tag::deleted_at.is_null())
+                .order(tag::name.asc())
+                .select(Tag::as_select())
+                .load::<Tag>(c)
+        }).await.map_err(db)?;
+        let rows = children.into_iter().filter_map(|t| t.parent_id.map(|parent| (parent, t))).collect();
+        Ok(grouped(rows, ChildrenOfTag))
+    }
+}
+
+#[rocket::async_trait]
+impl Loader<OwnersOfTag> for Db {
+    type Value = Vec<UserNode>;
+    type Error = Error;
+
+    async fn load(&self, keys: &[OwnersOfTag]) -> Result<HashMap<OwnersOfTag, Vec<UserNode>>> {
+        let ids: Vec<i32> = keys.iter().map(|k| k.0).collect();
+        let rows = self.0.run(move |c| {
+            user_tags::table
+                .inner_join(user::table)
+                .filter(user_tags::tag_id.eq_any(ids))
+                .filter(user::deleted_at.is_null())
+                .select((user_tags::tag_id, UserWithoutPHC::as_select()))
+                .load::<(i32, UserWithoutPHC)>(c)
+        }).await.map_err(db)?;
+        Ok(grouped(rows, OwnersOfTag))
+    }
+}
+
+#[rocket::async_trait]
+impl Loader<TagsOfUser> for Db {
+    type Value = Vec<TagNode>;
+    type Error = Error;
+
+    async fn load(&self, keys: &[TagsOfUser]) -> Result<HashMap<TagsOfUser, Vec<TagNode>>> {
+        let ids: Vec<i32> = keys.iter().map(|k| k.0).collect();
+        let rows = self.0.run(move |c| {
+            user_tags::table
+                .inner_join(tag::table)
+                .filter(user_tags::user_id.eq_any(ids))
+                .filter(tag::deleted_at.is_null())
+                .order(tag::name.asc())
+                .select((user_tags::user_id, Tag::as_select()))
+                .load::<(i32, Tag)>(c)
+        }).await.map_err(db)?;
+        Ok(grouped(rows, TagsOfUser))
+    }
+}
+
+#[rocket::async_trait]
+impl Loader<UsersOfRole> for Db {
+    type Value = Vec<UserNode>;
+    type Error = Error;
+
+    async fn load(&self, keys: &[UsersOfRole]) -> Result<HashMap<UsersOfRole, Vec<UserNode>>> {
+        let ids: Vec<i32> = keys.iter().map(|k| k.0).collect();
+        let users = self.0.run(move |c| {
+            user::table
+                .filter(user::role.eq_any(ids))
+                .filter(user::deleted_at.is_null())
+                .select(UserWithoutPHC::as_select())
+                .load::<UserWithoutPHC>(c)
+        }).await.map_err(db)?;
+        let rows = users.into_iter().map(|u| (u.role, u)).collect();
+        Ok(grouped(rows, UsersOfRole))
+    }
+}
+
+#[derive(SimpleObject, Clone)]
+#[graphql(complex, name = "Post")]
+pub struct PostNode {
+    id: i32,
+    title: String,
+    created: Option<NaiveDateTime>,
+    last_updated: Option<NaiveDate>,
+    content: Option<String>,
+    version: i32,
+    #[graphql(skip)]
+    author: String,
+}
+
+impl From<BlogEntry> for PostNode {
+    fn from(p: BlogEntry) -> Self {
+        PostNode { id: p.id, title: p.title, created: p.created, last_updated: p.last_updated, content: p.content, version: p.version, author: p.author }
+    }
+}
+
+#[ComplexObject]
+impl PostNode {
+    async fn author(&self, ctx: &Context<'_>) -> Result<Option<UserNode>> {
+        match self.author.parse::<i32>() {
+            Ok(id) => loader(ctx).load_one(UserId(id)).await,
+            Err(_) => Ok(None),
+        }
+    }
+
+    async fn tags(&self, ctx: &Context<'_>) -> Result<Vec<TagNode>> {
+        Ok(loader(ctx).load_one(TagsOfPost(self.id)).await?.unwrap_or_default())
+    }
+}
+
+#[derive(SimpleObject, Clone)]
+#[graphql(complex, name = "Tag")]
+pub struct TagNode {
+    id: i32,
+    name: String,
+    version: i32,
+    parent_id: Option<i32>,
+}
+
+impl From<Tag> for TagNode {
+    fn from(t: Tag) -> Self {
+        TagNode { id: t.id, name: t.name, version: t.version, parent_id: t.parent_id }
+    }
+}
+
+#[ComplexObject]
+impl TagNode {
+    async fn parent(&self, ctx: &Context<'_>) -> Result<Option<TagNode>> {
+        match self.parent_id {
+            Some(id) => loader(ctx).load_one(TagId(id)).await,
+            None => Ok(None),
+        }
+    }
+
+    async fn children(&self, ctx: &Context<'_>) -> Result<Vec<TagNode>> {
+        Ok(loader(ctx).load_one(ChildrenOfTag(self.id)).await?.unwrap_or_default())
+    }
+
+    //Newest first
+    async fn posts(&self, ctx: &Context<'_>) -> Result<Vec<PostNode>> {
+        Ok(loader(ctx).load_one(PostsOfTag(self.id)).await?.unwrap_or_default())
+    }
+
+    async fn owners(&self, ctx: &Context<'_>) -> Result<Vec<UserNode>> {
+        Ok(loader(ctx).load_one(OwnersOfTag(self.id)).await?.unwrap_or_default())
+    }
+}
+
+//Names are public, like the authors in GET /api/posts. The other fields are for admins and the user themselves.
+#[derive(SimpleObject, Clone)]
+#[graphql(complex, name = "User")]
+pub struct UserNode {
+    id: i32,
+    first_name: Option<String>,
+    last_name: Option<String>,
+    #[graphql(skip)]
+    email: String,
+    #[graphql(skip)]
+    created: Option<NaiveDateTime>,
+    #[graphql(skip)]
+    role: i32,
+    #[graphql(skip)]
+    active: Option<bool>,
+    #[graphql(skip)]
+    last_access: Option<NaiveDate>,
+}
+
+impl From<UserWithoutPHC> for UserNode {
+    fn from(u: UserWithoutPHC) -> Self {
+        UserNode { id: u.id, first_name: u.first_name, last_name: u.last_name, email: u.email, created: u.created, role: u.role,
+            active: u.active, last_access: u.last_access }
+    }
+}
+
+#[ComplexObject]
+impl UserNode {
+    async fn email(&self, ctx: &Context<'_>) -> Result<String> {
+        caller(ctx).admin_or_self(self.id)?;
+        Ok(self.email.clone())
+    }
+
+    async fn created(&self, ctx: &Context<'_>) -> Result<Option<NaiveDateTime>> {
+        caller(ctx).admin_or_self(self.id)?;
+        Ok(self.created)
+    }
+
+    async fn active(&self, ctx: &Context<'_>) -> Result<Option<bool>> {
+        caller(ctx).admin_or_self(self.id)?;
+        Ok(self.active)
+    }
+
+    async fn last_access(&self, ctx: &Context<'_>) -> Result<Option<NaiveDate>> {
+        caller(ctx).admin_or_self(self.id)?;
+        Ok(self.last_access)
+    }
+
+    async fn role(&self, ctx: &Context<'_>) -> Result<Option<RoleNode>> {
+        caller(ctx).admin_or_self(self.id)?;
+        loader(ctx).load_one(RoleId(self.role)).await
+    }
+
+    //Who owns a tag is public, see GET /api/tags?filter.eq=owner=<id>
+    async fn tags(&self, ctx: &Context<'_>) -> Result<Vec<TagNode>> {
+        Ok(loader(ctx).load_one(TagsOfUser(self.id)).await?.unwrap_or_default())
+    }
+}
+
+#[derive(SimpleObject, Clone)]
+#[graphql(complex, name = "Role")]
+pub struct RoleNode {
+    id: i32,
+    user_role: String,
+}
+
+impl From<Role> for RoleNode {
+    fn from(r: Role) -> Self {
+        RoleNode { id: r.id, user_role: r.user_role }
+    }
+}
+
+#[ComplexObject]
+impl RoleNode {
+    async fn users(&self, ctx: &Context<'_>) -> Result<Vec<UserNode>> {
+        caller(ctx).level1()?;
+        Ok(loader(ctx).load_one(UsersOfRole(self.id)).await?.unwrap_or_default())
+    }
+}
+
+//The filter query parameters of the REST listings, e.g. {eq: ["id=3"], like: ["title=%rust%"]}
+#[derive(InputObject, Default)]
+pub struct Filter {
+    #[graphql(default)]
+    eq: Vec<String>,
+    #[graphql(default)]
+    like: Vec<String>,
+    #[graphql(default)]
+    ge: Vec<String>,
+    #[graphql(default)]
+    le: Vec<String>,
+    #[graphql(default)]
+    between: Vec<String>,
+}
+
+fn params(start: Option<i64>, step: Option<i64>, order: Vec<String>, filter: Filter) -> QParams {
+    QParams {
+        start,
+        step,
+        order,
+        filter: Filters { eq: filter.eq, like: filter.like, ge: filter.ge, le: filter.le, between: filter.between },
+    }
+}
+
+pub struct Query;
+
+#[Object]
+impl Query {
+    //Same paging as GET /api/posts, 10 unless step is given
+    #[graphql(complexity = "(step.unwrap_or(10).clamp(1, QParams::MAX_STEP) as usize).saturating_mul(child_complexity)")]
+    async fn posts(&self, ctx: &Context<'_>, start: Option<i64>, step: Option<i64>, #[graphql(default)] order: Vec<String>,
+        #[graphql(default)] filter: Filter) -> Result<Vec<PostNode>> {
+        let posts = crate::post::routes::parse_and_query(params(start, step, order, filter), &caller(ctx).conn).await.map_err(db)?;
+        Ok(posts.into_iter().map(PostNode::from).collect())
+    }
+
+    async fn post(&self, ctx: &Context<'_>, id: i32) -> Result<Option<PostNode>> {
+        caller(ctx).post(id).await
+    }
+
+    //Same paging as GET /api/tags, 100 unless step is given
+    #[graphql(complexity = "(step.unwrap_or(100).clamp(1, QParams::MAX_STEP) as usize).saturating_mul(child_complexity)")]
+    async fn tags(&self, ctx: &Context<'_>, start: Option<i64>, step: Option<i64>, #[graphql(default)] order: Vec<String>,
+        #[graphql(default)] filter: Filter) -> Result<Vec<TagNode>> {
+        let tags = crate::tag::routes::parse_and_query(params(start, step, order, filter), &caller(ctx).conn).await.map_err(db)?;
+        Ok(tags.into_iter().map(TagNode::from).collect())
+    }
+
+    async fn tag(&self, ctx: &Context<'_>, id: i32) -> Result<Option<TagNode>> {
+        caller(ctx).tag(id).await
+    }
+
+    //The logged in user
+    async fn me(&self, ctx: &Context<'_>) -> Result<Option<UserNode>> {
+        let session = caller(ctx).session()?;
+        loader(ctx).load_one(UserId(session.id)).await
+    }
+
+    async fn user(&self, ctx: &Context<'_>, id: i32) -> Result<Option<UserNode>> {
+        caller(ctx).admin_or_self(id)?;
+        loader(ctx).load_one(UserId(id)).await
+    }
+
+    async fn users(&self, ctx: &Context<'_>) -> Result<Vec<UserNode>> {
+        let caller = caller(ctx);
+        caller.level1()?;
+        let users = caller.conn.run(|c| {
+            user::table.filter(user::deleted_at.is_null()).select(UserWithoutPHC::as_select()).load::<UserWithoutPHC>(c)
+        }).await.map_err(db)?;
+        Ok(users.into_iter().map(UserNode::from).collect())
+    }
+
+    async fn role(&self, ctx: &Context<'_>, id: i32) -> Result<Option<RoleNode>> {
+        caller(ctx).level1()?;
+        loader(ctx).load_one(RoleId(id)).await
+    }
+
+    async fn roles(&self, ctx: &Context<'_>) -> Result<Vec<RoleNode>> {
+        let caller = caller(ctx);
+        caller.level1()?;
+        let roles = caller.conn.run(|c| role::table.load::<Role>(c)).await.map_err(db)?;
+        Ok(roles.into_iter().map(RoleNode::from).collect())
+    }
+}
+
+fn missing(what: &str) -> Error {
+    gql(ApiError::internal_from(format!("The {} was written but could not be read back.", what)))
+}
+
+//Each mutation runs the function behind the REST route, with the same checks
+pub struct Mutation;
+
+#[Object]
+impl Mutation {
+    //POST /api/posts
+    async fn create_post(&self, ctx: &Context<'_>, title: String, content: Option<String>, created: Option<NaiveDateTime>) -> Result<PostNode> {
+        let caller = caller(ctx);
+        let user = caller.session()?;
+        let new_post = crate::post::routes::NewPost { title, author: None, created, last_updated: None, content };
+
+        let trail = Trail::default();
+        let result = crate::post::routes::create_post(&caller.conn, new_post, &user, &trail).await;
+        let id = caller.keep(trail, result)?.ok_or_else(|| missing("post"))?;
+        caller.post(id).await?.ok_or_else(|| missing("post"))
+    }
+
+    //PATCH /api/posts/<id>
+    async fn update_post(&self, ctx: &Context<'_>, id: i32, title: String, content: Option<String>, version: Option<i32>) -> Result<PostNode> {
+        let caller = caller(ctx);
+        let user = caller.session()?;
+        let new_post = crate::post::routes::NewPost { title, author: None, created: None, last_updated: None, content };
+
+        let trail = Trail::default();
+        let result = crate::post::routes::update_post(&caller.conn, id, new_post, &user, caller.if_match(version), &trail).await;
+        caller.keep(trail, result)?;
+        caller.post(id).await?.ok_or_else(|| missing("post"))
+    }
+
+    //DELETE /api/posts/<id>, the post goes to the trash. Its media are kept.
+    async fn delete_post(&self, ctx: &Context<'_>, id: i32, version: Option<i32>) -> Result<bool> {
+        let caller = caller(ctx);
+        caller.level1()?;
+
+        let trail = Trail::default();
+        let result = crate::post::routes::delete_post(&caller.conn, id, false, caller.if_match(version), &trail).await;
+        caller.keep(trail, result)?;
+        Ok(true)
+    }
+
+    //POST /api/tags, an existing tag with the name is returned with the user added as an owner
+    async fn create_tag(&self, ctx: &Context<'_>, name: String) -> Result<TagNode> {
+        let caller = caller(ctx);
+        let user = caller.session()?;
+
+        let trail = Trail::default();
+        let result = crate::tag::routes::create_tag(&caller.conn, crate::tag::routes::NewTag { name }, &user, &trail).await;
+        let id = caller.keep(trail, result)?;
+        caller.tag(id).await?.ok_or_else(|| missing("tag"))
+    }
+
+    //PATCH /api/tags/<id>, only by the tag's owners and admins
+    async fn update_tag(&self, ctx: &Context<'_>, id: i32, name: String, version: Option<i32>) -> Result<TagNode> {
+        let caller = caller(ctx);
+        let user = caller.session()?;
+
+        let trail = Trail::default();
+        let result = crate::tag::routes::update_tag(&caller.conn, id, crate::tag::routes::NewTag { name }, &user, &caller.if_match(version), &trail).await;
+        caller.keep(trail, result)?;
+        caller.tag(id).await?.ok_or_else(|| missing("tag"))
+    }
+
+    //DELETE /api/tags/<id>, the tag goes to the trash
+    async fn delete_tag(&self, ctx: &Context<'_>, id: i32, version: Option<i32>) -> Result<bool> {
+        let caller = caller(ctx);
+        caller.session()?;
+
+        let trail = Trail::default();
+        let result = crate::tag::routes::delete_tag(&caller.conn, id, &caller.if_match(version), &trail).await;
+        caller.keep(trail, result)?;
+        Ok(true)
+    }
+}
+
+pub mod routes {
+    use super::*;
+    use async_graphql::http::{playground_source, GraphQLPlaygroundConfig};
+    use async_graphql_rocket::{GraphQLRequest, GraphQLResponse};
+    use rocket::response::content::RawHtml;
+    use rocket::State;
+    use crate::auth::{AdminUser, ValidSession};
+
+    //Read by Keyed, so Idempotency-Key judges the whole query, see idempotency.rs
+    #[post("/", format = "json", data = "<request>")]
+    pub async fn execute(request: Keyed<async_graphql::Request>, schema: &State<BlogSchema>, conn: DbConn, session: Option<ValidSession>, admin: Option<AdminUser>,
+        env: &State<EnvVariables>, trail: &Trail) -> GraphQLResponse {
+        let conn = Arc::new(conn);
+        let caller = Caller {
+            conn: conn.clone(),
+            session: session.map(|s| s.id),
+            admin: admin.is_some(),
+            require_if_match: env.require_if_match,
+            trail: trail.clone(),
+        };
+        GraphQLRequest(request.into_inner())
+            .data(caller)
+            .data(DataLoader::new(Db(conn), rocket::tokio::spawn))
+            .execute(schema.inner())
+            .await
+    }
+
+    #[get("/playground")]
+    pub fn playground(env: &State<EnvVariables>) -> Option<RawHtml<String>> {
+        env.graphql.playground.then(|| RawHtml(playground_source(GraphQLPlaygroundConfig::new("/graphql"))))
+    }
+}
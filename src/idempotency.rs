@@ -0,0 +1,634 @@
+use std::io::Cursor;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+use rocket::{Build, Data, Orbit, Request, Response, Rocket};
+use rocket::data::{self, FromData, Limits};
+use rocket::form::{Errors, Form, FromForm};
+use rocket::fairing::{self, Fairing, Info, Kind};
+use rocket::http::{Method, Status};
+use rocket::http::uri::Origin;
+use rocket::request::{FromRequest, Outcome};
+use rocket::response::{self, Responder};
+use rocket::serde::Deserialize;
+use serde::de::DeserializeOwned;
+use diesel::prelude::*;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use sha2::{Digest, Sha256};
+use crate::config::DbConn;
+use crate::error::ApiError;
+use crate::models::EnvVariables;
+use crate::schema::idempotency_key;
+use crate::version::{is_api, unversioned};
+
+/*
+A POST under /api or to /graphql that sends
+    Idempotency-Key: <1 to 255 visible ascii characters>
+is run once. Its answer is stored and a retry with the same key gets it back, with Idempotent-Replayed: true,
+without the handler running again. Keys belong to the user in the jwt cookie, or to the client ip without one.
+
+    The same key with a different request        409 CONFLICT
+    The same key while the first is still running 409 CONFLICT, retry later
+    A key that is empty, too long or not ascii    400 BAD_REQUEST
+    An answer of 5xx is not stored, the key can be used again.
+
+An answer with Cache-Control: no-store (NoStore), one that holds a secret, is stored without its body. A retry gets
+its status and Location only.
+
+POST /api/users/session is left out, a key on it is ignored. Its answer is the jwt cookie, which is not stored, and
+signing in twice does no harm.
+
+A request is the method, the uri, Content-Type and the whole body, hashed with sha256.
+Rocket lets a fairing see the first 512 bytes of a body only. Shorter bodies are judged by the Idempotency fairing
+before routing. A longer json body is read by the Keyed data guard, which every POST route with a json body takes in
+place of Json, and judged there before the handler runs. A multipart body, a media upload, is judged by KeyedForm
+in place of Form on what the parsed form holds (Fingerprint), its raw body changes with the boundary a client picks
+for each retry. A longer body that is neither is refused with a 400 when it carries a key.
+
+[default.idempotency]
+window_hours = 24            How long a key and its answer are kept.
+in_flight_seconds = 120      How long a key waits for its answer. A request that died, or whose answer could not be
+                             stored, leaves its key without one, past this a retry is run again instead of a 409.
+purge_every_minutes = 60     How often the cleanup job removes expired keys. A key past the window is not replayed
+                             even before the job has removed it.
+Times are the database's, like those of the trash.
+
+The key is reserved before the handler runs and the answer stored once it is known. Replays, conflicts and refusals
+found by the fairing are routed to POST /idempotency/replay, which answers from what the fairing found. Those found by
+Keyed or KeyedForm fail the data guard, the fairing then puts the answer in place of the catcher's. Neither is audited.
+*/
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(crate = "rocket::serde")]
+pub struct IdempotencyConfig {
+    #[serde(default = "IdempotencyConfig::default_window")]
+    pub window_hours: i64,
+    #[serde(default = "IdempotencyConfig::default_in_flight")]
+    pub in_flight_seconds: i64,
+    #[serde(default = "IdempotencyConfig::default_interval")]
+    pub purge_every_minutes: u64,
+}
+
+impl Default for IdempotencyConfig {
+    fn default() -> Self {
+        IdempotencyConfig {
+            window_hours: IdempotencyConfig::default_window(),
+            in_flight_seconds: IdempotencyConfig::default_in_flight(),
+            purge_every_minutes: IdempotencyConfig::default_interval(),
+        }
+    }
+}
+
+impl IdempotencyConfig {
+    fn default_window() -> i64 {
+        24
+    }
+
+    fn default_in_flight() -> i64 {
+        120
+    }
+
+    fn default_interval() -> u64 {
+        60
+    }
+}
+
+const REPLAY_PATH: &str = "/idempotency/replay";
+
+//Unversioned paths whose POST a key does not apply to
+const UNKEYED: &[&str] = &["/api/users/session"];
+
+//A stored answer
+#[derive(Clone)]
+pub struct Stored {
+    status: i32,
+    content_type: Option<String>,
+    location: Option<String>,
+    body: String,
+}
+
+impl<'r> Responder<'r, 'static> for Stored {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = Response::build();
+        response
+            .status(Status::from_code(self.status as u16).unwrap_or(Status::Ok))
+            .raw_header("Idempotent-Replayed", "true");
+        if let Some(content_type) = self.content_type {
+            response.raw_header("Content-Type", content_type);
+        }
+        if let Some(location) = self.location {
+            response.raw_header("Location", location);
+        }
+        response.sized_body(self.body.len(), Cursor::new(self.body)).ok()
+    }
+}
+
+//What became of a request's key, kept in its local cache
+enum Decision {
+    //No key, or not a POST the keys apply to
+    Untracked,
+    //The body is longer than the fairing can see, or a form, Keyed or KeyedForm reads it and decides
+    Unread { scope: String, key: String, head: String, config: IdempotencyConfig },
+    //The first request with this key, the row its answer goes into
+    Fresh(i64),
+    Replay(Stored),
+    //The key was already used with a different request
+    Mismatch,
+    //The first request with this key has not been answered yet
+    InFlight,
+    Invalid,
+    //A body too long to fingerprint in the fairing that Keyed and KeyedForm do not read
+    Unsupported,
+    //The key could not be looked up
+    Failed,
+}
+
+fn decision<'a>(request: &'a Request<'_>) -> MutexGuard<'a, Decision> {
+    request.local_cache(|| Mutex::new(Decision::Untracked)).lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+//The answer for a request whose handler is not run, None when it is
+fn answer(decision: &Decision) -> Option<Result<Stored, ApiError>> {
+    match decision {
+        Decision::Replay(stored) => Some(Ok(stored.clone())),
+        Decision::Mismatch => Some(Err(ApiError::conflict(Some(String::from("This Idempotency-Key was already used with a different request."))))),
+        Decision::InFlight => Some(Err(ApiError::conflict(Some(String::from("A request with this Idempotency-Key is still being processed, retry later."))))),
+        Decision::Invalid => Some(Err(ApiError::bad_request(Some(String::from("Idempotency-Key must be 1 to 255 visible ascii characters."))))),
+        Decision::Unsupported => Some(Err(ApiError::bad_request(Some(String::from("Idempotency-Key can only be sent with a json or multipart body, or a body of at most 512 bytes."))))),
+        Decision::Failed => Some(Err(ApiError::internal())),
+        Decision::Untracked | Decision::Unread { .. } | Decision::Fresh(_) => None,
+    }
+}
+
+fn valid(key: &str) -> bool {
+    (1..=255).contains(&key.len()) && key.bytes().all(|b| b.is_ascii_graphic())
+}
+
+fn scope(request: &Request<'_>) -> String {
+    match crate::audit::actor(request) {
+        Some(user_id) => format!("user:{}", user_id),
+        None => format!("ip:{}", request.client_ip().map(|ip| ip.to_string()).unwrap_or_default()),
+    }
+}
+
+//Everything but the body that makes a request. A form's boundary is left out, it is not the same on a retry.
+fn head(request: &Request<'_>) -> String {
+    let content_type = match request.content_type() {
+        Some(ct) if ct.is_form_data() => format!("{}/{}", ct.top(), ct.sub()),
+        _ => request.headers().get_one("Content-Type").unwrap_or("").to_string(),
+    };
+    format!("{}\n{}\n{}\n", request.method().as_str(), request.uri(), content_type)
+}
+
+fn fingerprint(head: &str, body: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(head);
+    hasher.update(body);
+    format!("{:x}", hasher.finalize())
+}
+
+fn form(request: &Request<'_>) -> bool {
+    request.content_type().is_some_and(|ct| ct.is_form_data())
+}
+
+fn json(request: &Request<'_>) -> bool {
+    request.content_type().is_some_and(|ct| ct.is_json() || ct.sub().as_str().ends_with("+json"))
+}
+
+async fn look_up(request: &Request<'_>, scope: String, key: String, fingerprint: String, config: IdempotencyConfig) -> Decision {
+    let Some(conn) = DbConn::get_one(request.rocket()).await else {
+        error!("Idempotency: no database connection.");
+        return Decision::Failed;
+    };
+    match conn.run(move |c| reserve(c, scope, key, fingerprint, &config)).await {
+        Ok(decision) => decision,
+        Err(e) => {
+            error!("Idempotency: could not look up the key: {:?}", e);
+            Decision::Failed
+        },
+    }
+}
+
+fn expiry(c: &mut MysqlConnection, window_hours: i64) -> QueryResult<chrono::NaiveDateTime> {
+    Ok(crate::webhook::db_now(c)? - chrono::Duration::hours(window_hours))
+}
+
+//Looks the key up, or reserves it when it is new
+fn reserve(c: &mut MysqlConnection, scope: String, key: String, fingerprint: String, config: &IdempotencyConfig) -> QueryResult<Decision> {
+    let found = idempotency_key::table
+        .filter(idempotency_key::scope.eq(&scope))
+        .filter(idempotency_key::idem_key.eq(&key))
+        .select((idempotency_key::id, idempotency_key::status, idempotency_key::created))
+        .first::<(i64, Option<i32>, chrono::NaiveDateTime)>(c)
+        .optional()?;
+    //Past the window the key is new again, the cleanup job may not have come by yet.
+    //A key still without an answer past in_flight_seconds was left behind, created is when it was reserved.
+    if let Some((id, status, created)) = found {
+        let now = crate::webhook::db_now(c)?;
+        let expired = created < now - chrono::Duration::hours(config.window_hours);
+        let abandoned = status.is_none() && created < now - chrono::Duration::seconds(config.in_flight_seconds);
+        if expired || abandoned {
+            diesel::delete(idempotency_key::table.find(id)).execute(c)?;
+        }
+    }
+
+    let found = idempotency_key::table
+        .filter(idempotency_key::scope.eq(&scope))
+        .filter(idempotency_key::idem_key.eq(&key))
+        .select((idempotency_key::fingerprint, idempotency_key::status, idempotency_key::content_type, idempotency_key::location, idempotency_key::body))
+        .first::<(String, Option<i32>, Option<String>, Option<String>, Option<String>)>(c)
+        .optional()?;
+    match found {
+        Some((stored, _, _, _, _)) if stored != fingerprint => Ok(Decision::Mismatch),
+        Some((_, None, _, _, _)) => Ok(Decision::InFlight),
+        Some((_, Some(status), content_type, location, body)) => {
+            Ok(Decision::Replay(Stored { status, content_type, location, body: body.unwrap_or_default() }))
+        },
+        None => {
+            let inserted = diesel::insert_into(idempotency_key::table)
+                .values((
+                    idempotency_key::idem_key.eq(&key),
+                    idempotency_key::scope.eq(&scope),
+                    idempotency_key::fingerprint.eq(&fingerprint),
+                ))
+                .execute(c);
+            match inserted {
+                Ok(_) => {
+                    let id = idempotency_key::table
+                        .filter(idempotency_key::scope.eq(&scope))
+                        .filter(idempotency_key::idem_key.eq(&key))
+                        .select(idempotency_key::id)
+                        .first::<i64>(c)?;
+                    Ok(Decision::Fresh(id))
+                },
+                //Another request with the key got in first
+                Err(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => Ok(Decision::InFlight),
+                Err(e) => Err(e),
+            }
+        },
+    }
+}
+
+pub struct Idempotency {
+    config: IdempotencyConfig,
+}
+
+#[rocket::async_trait]
+impl Fairing for Idempotency {
+    fn info(&self) -> Info {
+        Info {
+            name: "Idempotency keys",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, data: &mut Data<'_>) {
+        let path = request.uri().path().as_str();
+        if request.method() != Method::Post || !(is_api(path) || path == "/graphql") || UNKEYED.contains(&unversioned(path).as_str()) {
+            return;
+        }
+        let Some(key) = request.headers().get_one("Idempotency-Key").map(str::to_string) else { return };
+
+        let decision = match valid(&key) {
+            false => Decision::Invalid,
+            true => {
+                let (scope, head, config) = (scope(request), head(request), self.config.clone());
+                if form(request) {
+                    Decision::Unread { scope, key, head, config }
+                } else {
+                    let body = data.peek(512).await.to_vec();
+                    match (data.peek_complete(), json(request)) {
+                        (true, _) => look_up(request, scope, key, fingerprint(&head, &body), config).await,
+                        (false, true) => Decision::Unread { scope, key, head, config },
+                        (false, false) => Decision::Unsupported,
+                    }
+                }
+            },
+        };
+
+        let answered_here = answer(&decision).is_some();
+        request.local_cache(|| Mutex::new(decision));
+        if answered_here {
+            match Origin::parse(REPLAY_PATH) {
+                Ok(uri) => request.set_uri(uri),
+                Err(e) => error!("Idempotency: could not route to {}: {}", REPLAY_PATH, e),
+            }
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let (fresh, answered) = match &*decision(request) {
+            Decision::Fresh(id) => (Some(*id), None),
+            other => (None, answer(other)),
+        };
+        //Keyed or KeyedForm turned the request down, the catcher answered in its place
+        if let Some(answered) = answered {
+            if request.uri().path() != REPLAY_PATH {
+                match answered.respond_to(request) {
+                    Ok(answered) => {
+                        response.remove_header("Content-Type");
+                        response.merge(answered);
+                    },
+                    Err(status) => response.set_status(status),
+                }
+            }
+            return;
+        }
+        let Some(id) = fresh else { return };
+        let Some(conn) = DbConn::get_one(request.rocket()).await else {
+            error!("Idempotency: no database connection, the answer for key {} was not stored.", id);
+            return;
+        };
+
+        //Nothing was done, the client may try again with the same key
+        if response.status().class().is_server_error() {
+            release(&conn, id).await;
+            return;
+        }
+
+        let bytes = match response.body_mut().to_bytes().await {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                error!("Idempotency: could not read the answer for key {}: {}", id, e);
+                release(&conn, id).await;
+                return;
+            },
+        };
+        let no_store = response.headers().get("Cache-Control").any(|v| v.split(',').any(|d| d.trim().eq_ignore_ascii_case("no-store")));
+        let stored = Stored {
+            status: response.status().code as i32,
+            content_type: response.content_type().filter(|_| !no_store).map(|c| c.to_string()),
+            location: response.headers().get_one("Location").map(str::to_string),
+            body: match no_store {
+                true => String::new(),
+                false => String::from_utf8_lossy(&bytes).into_owned(),
+            },
+        };
+        response.set_sized_body(bytes.len(), Cursor::new(bytes));
+
+        let saved = conn.run(move |c| {
+            diesel::update(idempotency_key::table.find(id))
+                .set((
+                    idempotency_key::status.eq(stored.status),
+                    idempotency_key::content_type.eq(stored.content_type),
+                    idempotency_key::location.eq(stored.location),
+                    idempotency_key::body.eq(stored.body),
+                ))
+                .execute(c)
+        }).await;
+        if let Err(e) = saved {
+            error!("Idempotency: could not store the answer for key {}: {:?}", id, e);
+            release(&conn, id).await;
+        }
+    }
+}
+
+//Frees a key whose answer is not stored, a retry runs the request again
+async fn release(conn: &DbConn, id: i64) {
+    if let Err(e) = conn.run(move |c| diesel::delete(idempotency_key::table.find(id)).execute(c)).await {
+        error!("Idempotency: could not release key {}: {:?}", id, e);
+    }
+}
+
+//Reads [<profile>.idempotency] and refuses to launch on a window or in-flight time that is not positive.
+pub async fn attach_from_config(rocket: Rocket<Build>) -> fairing::Result {
+    let config = match rocket.figment().extract::<EnvVariables>() {
+        Ok(env) => env.idempotency,
+        Err(e) => {
+            error!("Could not read the idempotency configuration: {}", e);
+            return Err(rocket);
+        }
+    };
+    if config.window_hours < 1 {
+        error!("Invalid idempotency configuration. window_hours must be at least 1, it is {}.", config.window_hours);
+        return Err(rocket);
+    }
+    if config.in_flight_seconds < 1 {
+        error!("Invalid idempotency configuration. in_flight_seconds must be at least 1, it is {}.", config.in_flight_seconds);
+        return Err(rocket);
+    }
+    Ok(rocket.attach(Idempotency { config }))
+}
+
+//Started once the server is up, removes keys past the window until it shuts down
+pub async fn start_cleanup_job(rocket: &Rocket<Orbit>) {
+    let config = match rocket.state::<EnvVariables>() {
+        Some(env) => env.idempotency.clone(),
+        None => return,
+    };
+    let pool = match DbConn::pool(rocket) {
+        Some(pool) => pool.clone(),
+        None => {
+            error!("Idempotency: no database pool, expired keys are not removed.");
+            return;
+        },
+    };
+
+    rocket::tokio::spawn(async move {
+        let mut interval = rocket::tokio::time::interval(Duration::from_secs(config.purge_every_minutes.max(1) * 60));
+        loop {
+            interval.tick().await;
+            let conn = match pool.get().await {
+                Some(conn) => conn,
+                None => {
+                    error!("Idempotency: no database connection, the cleanup is skipped.");
+                    continue;
+                },
+            };
+            let window_hours = config.window_hours;
+            let removed = conn.run(move |c| {
+                let expiry = expiry(c, window_hours)?;
+                diesel::delete(idempotency_key::table.filter(idempotency_key::created.lt(expiry))).execute(c)
+            }).await;
+            match removed {
+                Ok(0) => (),
+                Ok(n) => info!("Idempotency: removed {} expired keys.", n),
+                Err(e) => error!("Idempotency: cleanup failed: {:?}", e),
+            }
+        }
+    });
+}
+
+//An answer that holds a secret. Cache-Control: no-store keeps it out of caches and its body out of the stored answers.
+pub struct NoStore<R>(pub R);
+
+impl<'r, 'o: 'r, R: Responder<'r, 'o>> Responder<'r, 'o> for NoStore<R> {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'o> {
+        Response::build_from(self.0.respond_to(request)?)
+            .raw_header("Cache-Control", "no-store")
+            .ok()
+    }
+}
+
+//What POST /idempotency/replay answers with
+pub struct Answer(Result<Stored, ApiError>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Answer {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        //Only reached through the fairing, a direct POST finds no decision
+        let answer = answer(&decision(request)).unwrap_or_else(|| Err(ApiError::not_found(None)));
+        Outcome::Success(Answer(answer))
+    }
+}
+
+//Json for POST routes. A body too long for the fairing is read here in full, and the request's key judged on it,
+//before the handler runs. A key that is turned down fails the guard with a 409, the fairing answers in its place.
+#[derive(Debug)]
+pub struct Keyed<T>(pub T);
+
+impl<T> Keyed<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for Keyed<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Keyed<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+#[rocket::async_trait]
+impl<'r, T: DeserializeOwned + Send> FromData<'r> for Keyed<T> {
+    type Error = ();
+
+    async fn from_data(request: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        let limit = request.limits().get("json").unwrap_or(Limits::JSON);
+        let body = match data.open(limit).into_bytes().await {
+            Ok(body) if body.is_complete() => body.into_inner(),
+            Ok(_) => return data::Outcome::Failure((Status::PayloadTooLarge, ())),
+            Err(_) => return data::Outcome::Failure((Status::BadRequest, ())),
+        };
+
+        if let Some((scope, key, head, config)) = unread(request) {
+            if !judge(request, scope, key, fingerprint(&head, &body), config).await {
+                return data::Outcome::Failure((Status::Conflict, ()));
+            }
+        }
+
+        match serde_json::from_slice::<T>(&body) {
+            Ok(value) => data::Outcome::Success(Keyed(value)),
+            Err(e) if e.classify() == serde_json::error::Category::Data => data::Outcome::Failure((Status::UnprocessableEntity, ())),
+            Err(_) => data::Outcome::Failure((Status::BadRequest, ())),
+        }
+    }
+}
+
+//What a form holds, hashed by KeyedForm after the head in place of the raw body
+#[rocket::async_trait]
+pub trait Fingerprint {
+    async fn digest(&self, hasher: &mut Sha256) -> std::io::Result<()>;
+}
+
+//Form for POST routes with a multipart body. The key is judged on the parsed form before the handler runs, like Keyed.
+pub struct KeyedForm<T>(pub T);
+
+impl<T> KeyedForm<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for KeyedForm<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+#[rocket::async_trait]
+impl<'r, T: FromForm<'r> + Fingerprint + Send + Sync> FromData<'r> for KeyedForm<T> {
+    type Error = Errors<'r>;
+
+    async fn from_data(request: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        let form = match <Form<T> as FromData<'r>>::from_data(request, data).await {
+            data::Outcome::Success(form) => form.into_inner(),
+            data::Outcome::Failure(failure) => return data::Outcome::Failure(failure),
+            data::Outcome::Forward(data) => return data::Outcome::Forward(data),
+        };
+
+        if let Some((scope, key, head, config)) = unread(request) {
+            let mut hasher = Sha256::new();
+            hasher.update(&head);
+            if let Err(e) = form.digest(&mut hasher).await {
+                error!("Idempotency: could not read the form for key {}: {}", key, e);
+                *decision(request) = Decision::Failed;
+                return data::Outcome::Failure((Status::InternalServerError, Errors::new()));
+            }
+            if !judge(request, scope, key, format!("{:x}", hasher.finalize()), config).await {
+                return data::Outcome::Failure((Status::Conflict, Errors::new()));
+            }
+        }
+        data::Outcome::Success(KeyedForm(form))
+    }
+}
+
+//Takes what the fairing left to a body guard, None when it judged the request itself
+fn unread(request: &Request<'_>) -> Option<(String, String, String, IdempotencyConfig)> {
+    let taken = std::mem::replace(&mut *decision(request), Decision::Untracked);
+    match taken {
+        Decision::Unread { scope, key, head, config } => Some((scope, key, head, config)),
+        other => {
+            *decision(request) = other;
+            None
+        },
+    }
+}
+
+//Judges the key on the whole body's fingerprint, false when the request is turned down
+async fn judge(request: &Request<'_>, scope: String, key: String, fingerprint: String, config: IdempotencyConfig) -> bool {
+    let found = look_up(request, scope, key, fingerprint, config).await;
+    let turned_down = answer(&found).is_some();
+    *decision(request) = found;
+    !turned_down
+}
+
+pub mod routes {
+    use super::*;
+
+    #[post("/replay")]
+    pub async fn replay(answer: Answer) -> Result<Stored, ApiError> {
+        answer.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::http::{Cookie, CookieJar, Header};
+    use rocket::local::asynchronous::Client;
+
+    #[post("/session")]
+    fn sign_in(jar: &CookieJar<'_>) -> Status {
+        jar.add(Cookie::new("jwt", "signed in"));
+        Status::Ok
+    }
+
+    //A retried login runs again and sets the cookie again, it is never answered without one
+    #[rocket::async_test]
+    async fn retried_login_is_not_replayed() {
+        let rocket = rocket::build()
+            .attach(Idempotency { config: IdempotencyConfig::default() })
+            .mount("/api/users", routes![sign_in])
+            .mount("/api/v1/users", routes![sign_in]);
+        let client = Client::tracked(rocket).await.unwrap();
+        for path in ["/api/users/session", "/api/users/session", "/api/v1/users/session"] {
+            let response = client.post(path).header(Header::new("Idempotency-Key", "login-1")).dispatch().await;
+            assert_eq!(response.status(), Status::Ok);
+            assert!(response.headers().get_one("Idempotent-Replayed").is_none());
+            assert!(response.headers().get_one("Set-Cookie").is_some_and(|c| c.starts_with("jwt=")));
+        }
+    }
+}
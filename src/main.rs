@@ -1,9 +1,7 @@
 #[macro_use] extern crate rocket;
 #[macro_use] extern crate diesel;
 use rocket::fairing::AdHoc;
-use rocket_cors::{AllowedHeaders, AllowedOrigins};
-use rocket::http::Method;
-use rocket::fs::{FileServer, NamedFile, relative};
+use rocket::fs::{FileServer, relative};
 
 //use rocket_contrib::serve::StaticFiles;
 mod models;
@@ -13,6 +11,12 @@ mod pw;
 mod jwt;
 mod post_tags;
 mod myjsonapi;
+mod cors;
+mod error;
+mod cache;
+mod audit;
+mod bulk;
+mod idempotency;
 
 mod api;
 use api::*;
@@ -35,18 +39,23 @@ use user::routes::*;
 mod role;
 use role::routes::*;
 
+mod series;
+
+mod media;
+mod variants;
+mod trash;
+mod webhook;
+mod events;
+mod graphql;
+mod version;
+use version::MountVersions;
+mod openapi;
+
 mod session;
 use session::routes::*;
 
 use models::EnvVariables;
 
-#[get("/openapi_yml")]
-async fn openapi_yml() -> Option<NamedFile> {
-    //https://github.com/swagger-api/swagger-ui/blob/master/docs/usage/installation.md#plain-old-htmlcssjs-standalone
-    //url: "http://localhost:8001/openapi_yml",
-    NamedFile::open("src/homepage.yml").await.ok()
-}
-
 /* #[get("/openapi_index")]
 async fn openapi_index() -> Option<NamedFile> {
     NamedFile::open("static/3rd_party/swagger-ui-4.18.1/dist/index.html").await.ok()
@@ -54,27 +63,17 @@ async fn openapi_index() -> Option<NamedFile> {
 
 #[launch]
 fn rocket() -> _ {
-    //let allowed_origins = AllowedOrigins::all();
-    let allowed_origins = AllowedOrigins::some_regex(&["^vscode-webview://(.+)"]);
-    //let allowed_origins = AllowedOrigins::some_null();
-    let cors = rocket_cors::CorsOptions {
-        allowed_origins,
-        allowed_methods: vec![Method::Get].into_iter().map(From::from).collect(),
-        allowed_headers: AllowedHeaders::some(&["Authorization", "Accept"]),
-        allow_credentials: true,
-        ..Default::default()
-    }
-    .to_cors().unwrap();
-
-
+    //Every .mount_versions() is served under /api, /api/v1 and /api/v2, see version.rs
     rocket::build()
         //.mount("/my_path", StaticFiles::from("/www/public"))
         //.mount("/openapi", FileServer::from("/static/3rd_party/swagger-ui-4.18.1/dist"))
         //https://github.com/swagger-api/swagger-ui/releases
+        //url: "http://localhost:8001/openapi.json", see openapi.rs
         .mount("/openapi", FileServer::from(relative!("/static/3rd_party/swagger-ui-4.19.0/dist")))
-        .mount("/", routes![home, openapi_yml])
-        .mount("/api", routes![api_info])
-        .mount("/api/tags", routes![
+        .mount("/", routes![home, openapi::routes::openapi_json])
+            .register("/", catchers![error::default_catcher])
+        .mount_versions("", routes![api_info])
+        .mount_versions("/tags", routes![
             tag::routes::get,
             get_users_tags,
             //get_tags_admin_user,
@@ -82,35 +81,100 @@ fn rocket() -> _ {
             tag::routes::patch,
             tag::routes::post,
             tag::routes::delete,
-            tag::routes::get_posts
+            tag::routes::merge,
+            tag::routes::bulk,
+            tag::routes::get_stats,
+            tag::routes::get_children,
+            tag::routes::get_ancestors,
+            tag::routes::put_parent,
+            tag::routes::get_posts,
+            tag::routes::get_tags_json_api,
+            tag::routes::get_json_api,
+            tag::routes::get_posts_json_api,
+            tag::routes::post_json_api,
+            tag::routes::patch_json_api
         ])
-        .mount("/api/posts", routes![
+        .mount_versions("/posts", routes![
             post::routes::get_posts,
             post::routes::get,
+            post::routes::get_related,
             post::routes::post_,
             post::routes::patch,
             post::routes::delete,
+            post::routes::bulk,
             put_post_tag,
             patch_post_tags,
             patch_post_tags_form,
             put_post_tags_form,
-            delete_post_tag
+            delete_post_tag,
+            post::routes::get_posts_json_api,
+            post::routes::get_json_api,
+            post::routes::post_json_api,
+            post::routes::patch_json_api
+        ])
+        .mount_versions("/series", routes![
+            series::routes::get_all,
+            series::routes::get,
+            series::routes::post_,
+            series::routes::patch,
+            series::routes::put_posts,
+            series::routes::delete
+        ])
+        .mount_versions("/media", routes![
+            media::routes::upload,
+            media::routes::get_all,
+            media::routes::get_orphans,
+            media::routes::get,
+            media::routes::get_file,
+            media::routes::get_variant,
+            media::routes::put_post,
+            media::routes::delete_post,
+            media::routes::delete
+        ])
+        .mount_versions("/audit", routes![
+            audit::routes::get_all
+        ])
+        .mount_versions("/events", routes![
+            events::routes::stream
+        ])
+        .mount_versions("/webhooks", routes![
+            webhook::routes::get_all,
+            webhook::routes::get,
+            webhook::routes::post_,
+            webhook::routes::patch,
+            webhook::routes::delete,
+            webhook::routes::ping,
+            webhook::routes::get_deliveries,
+            webhook::routes::get_delivery,
+            webhook::routes::redeliver
+        ])
+        //Answers retried POSTs that carry an Idempotency-Key, see idempotency.rs
+        .mount("/idempotency", routes![idempotency::routes::replay])
+        .mount("/graphql", routes![
+            graphql::routes::execute,
+            graphql::routes::playground
+        ])
+        .mount_versions("/trash", routes![
+            trash::routes::get_all,
+            trash::routes::restore
         ])
-        .mount("/api/roles", routes![
+        .mount_versions("/roles", routes![
             get_roles,
             get_role,
             new_role,
             update_role,
-            delete_role
+            delete_role,
+            patch_role,
+            remove_role
         ])
         /* .mount("/session", routes![
             create_session, 
             destroy_session
         ]) */
-            .register("/api/session", catchers![
+            .register_versions("/session", catchers![
                 email_or_pw_incorrect
             ])
-        .mount("/api/users", routes![
+        .mount_versions("/users", routes![
             add_user, 
             delete_user,
             delete_user_forbidden,
@@ -131,13 +195,37 @@ fn rocket() -> _ {
             get_user_by_id,
             get_user_by_id_unauthorized,
             get_user_by_id_forbidden,
+            get_user_json_api,
+            get_user_by_id_json_api,
+            list_of_all_users_json_api,
             //patch_user
         ])
-            .register("/user", catchers![
+            .register_versions("/users", catchers![
                 dup_entry,
                 catch_all
             ])
         .attach(DbConn::fairing())
         .attach(AdHoc::config::<EnvVariables>())
-        .attach(cors)
+        //CORS policy comes from the [<profile>.cors] section of Rocket.toml, see cors.rs
+        .attach(AdHoc::try_on_ignite("CORS", cors::attach_from_config))
+        //Routes Accept: application/vnd.blog.v2+json to /api/v2 and marks v1 only routes deprecated, see version.rs
+        .attach(AdHoc::try_on_ignite("Versioning", version::attach_from_config))
+        //Stores the answer to a POST sent with an Idempotency-Key and replays it on retries, see idempotency.rs
+        .attach(AdHoc::try_on_ignite("Idempotency", idempotency::attach_from_config))
+        //Logs every successful write under /api, see audit.rs
+        .attach(audit::AuditLog)
+        //Schema for POST /graphql with the limits from [<profile>.graphql], see graphql.rs
+        .attach(AdHoc::try_on_ignite("GraphQL", graphql::attach))
+        //GET /openapi.json, built from the mounted routes. Refuses to launch when routes and docs disagree, see openapi.rs
+        .attach(AdHoc::try_on_ignite("OpenAPI", openapi::attach))
+        //GET /api, the discovery document built from the mounted routes, see api.rs
+        .attach(AdHoc::try_on_ignite("Discovery", api::attach))
+        //Live changes for GET /api/events, fed by the audit log, see events.rs
+        .manage(events::Hub::default())
+        //Removes what has been in the trash longer than [<profile>.trash] retention_days, see trash.rs
+        .attach(AdHoc::on_liftoff("Trash purge", |rocket| Box::pin(trash::start_purge_job(rocket))))
+        //Removes Idempotency-Key answers older than [<profile>.idempotency] window_hours, see idempotency.rs
+        .attach(AdHoc::on_liftoff("Idempotency cleanup", |rocket| Box::pin(idempotency::start_cleanup_job(rocket))))
+        //Sends queued webhook deliveries, see webhook.rs
+        .attach(AdHoc::on_liftoff("Webhook dispatcher", |rocket| Box::pin(webhook::start_dispatcher(rocket))))
 }
\ No newline at end of file
@@ -0,0 +1,618 @@
+use std::io::{self, Cursor, SeekFrom};
+use std::path::PathBuf;
+use rocket::fs::{NamedFile, TempFile};
+use rocket::http::{ContentType, Header, Status};
+use rocket::request::{FromRequest, Outcome};
+use rocket::response::{self, status, Responder, Response};
+use rocket::serde::Deserialize;
+use rocket::serde::json::{Json, Value, json};
+use rocket::tokio::fs::{self, File};
+use rocket::tokio::io::{AsyncReadExt, AsyncSeekExt};
+use rocket::{Request, State};
+use diesel::prelude::*;
+use sha2::{Digest, Sha256};
+use crate::cache::Cached;
+use crate::config::DbConn;
+use crate::error::ApiError;
+use crate::idempotency::{Fingerprint, KeyedForm};
+use crate::models::{AResponse, EnvVariables, LastInsertId, Media};
+use crate::schema::{media, post, post_media, user};
+use crate::variants::{self, Format};
+
+/*
+Uploaded files are kept in a local directory, set in the [<profile>.media] section of Rocket.toml.
+
+[default.media]
+dir = "media"                                   Created on the first upload. Relative to the working directory.
+allowed_types = ["image/png", "image/jpeg"]     Any other Content-Type is a 415.
+
+The upload size is capped by Rocket's own limits, file and data-form in [<profile>.limits].
+
+Files are stored under the sha256 of their content. Uploading the same file twice adds a second media row but
+keeps one copy on disk. The copy is removed with the last row that uses it.
+
+EXIF is stripped from images before they are stored, resized variants are described in variants.rs.
+
+Media are linked to posts through post_media. Deleting a post reports the media that no other post uses, and
+removes them with ?purge_media=true.
+*/
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(crate = "rocket::serde")]
+pub struct MediaConfig {
+    #[serde(default = "MediaConfig::default_dir")]
+    pub dir: String,
+    #[serde(default = "MediaConfig::default_types")]
+    pub allowed_types: Vec<String>,
+    #[serde(default = "MediaConfig::default_widths")]
+    pub variant_widths: Vec<u32>,
+    #[serde(default = "MediaConfig::default_formats")]
+    pub variant_formats: Vec<String>,
+    #[serde(default = "MediaConfig::default_quality")]
+    pub jpeg_quality: u8,
+    #[serde(default)]
+    pub eager_variants: bool,
+}
+
+impl Default for MediaConfig {
+    fn default() -> Self {
+        MediaConfig {
+            dir: MediaConfig::default_dir(),
+            allowed_types: MediaConfig::default_types(),
+            variant_widths: MediaConfig::default_widths(),
+            variant_formats: MediaConfig::default_formats(),
+            jpeg_quality: MediaConfig::default_quality(),
+            eager_variants: false,
+        }
+    }
+}
+
+impl MediaConfig {
+    fn default_dir() -> String {
+        String::from("media")
+    }
+
+    fn default_types() -> Vec<String> {
+        ["image/png", "image/jpeg", "image/gif", "image/webp"].iter().map(|t| t.to_string()).collect()
+    }
+
+    fn default_widths() -> Vec<u32> {
+        vec![320, 640, 1280]
+    }
+
+    fn default_formats() -> Vec<String> {
+        vec![String::from("webp"), String::from("jpeg")]
+    }
+
+    fn default_quality() -> u8 {
+        80
+    }
+}
+
+//The local directory backend
+pub struct LocalStore {
+    root: PathBuf,
+}
+
+impl LocalStore {
+    pub fn new(config: &MediaConfig) -> Self {
+        LocalStore { root: PathBuf::from(&config.dir) }
+    }
+
+    pub fn path(&self, checksum: &str) -> PathBuf {
+        self.root.join(checksum)
+    }
+
+    pub fn variant_path(&self, checksum: &str, width: u32, format: Format) -> PathBuf {
+        variants::path(&self.root, checksum, width, format)
+    }
+
+    pub async fn save(&self, bytes: &[u8], checksum: &str) -> io::Result<()> {
+        fs::create_dir_all(&self.root).await?;
+        let dest = self.path(checksum);
+        //Same content is already stored
+        if fs::metadata(&dest).await.is_ok() {
+            return Ok(());
+        }
+        fs::write(dest, bytes).await
+    }
+
+    //The original and every variant rendered from it
+    pub async fn remove(&self, checksum: &str) -> io::Result<()> {
+        match fs::remove_file(self.path(checksum)).await {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+            result => result?,
+        }
+        let mut dir = match fs::read_dir(self.root.join("variants")).await {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            dir => dir?,
+        };
+        let prefix = format!("{}-", checksum);
+        while let Some(entry) = dir.next_entry().await? {
+            if entry.file_name().to_string_lossy().starts_with(&prefix) {
+                fs::remove_file(entry.path()).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+//What the upload handler stores, worked out off the async threads
+struct Prepared {
+    bytes: Vec<u8>,
+    checksum: String,
+    dimensions: Option<(u32, u32)>,
+}
+
+fn prepare(mime_type: &str, bytes: Vec<u8>) -> Prepared {
+    let bytes = variants::strip_metadata(mime_type, bytes);
+    let checksum = format!("{:x}", Sha256::digest(&bytes));
+    let dimensions = match mime_type.starts_with("image/") {
+        true => variants::dimensions(&bytes),
+        false => None,
+    };
+    Prepared { bytes, checksum, dimensions }
+}
+
+//Render every variant of a new upload. Failures are only logged, the variant is tried again when it is requested.
+async fn render_all(store: LocalStore, m: Media, config: MediaConfig) {
+    for width in variants::widths_for(&m, &config) {
+        for format in variants::formats(&config) {
+            let dest = store.variant_path(&m.checksum, width, format);
+            if let Err(e) = variants::ensure(store.path(&m.checksum), dest, width, format, config.jpeg_quality).await {
+                error!("Could not render variant {}.{} of media {}: {:?}", width, format.extension(), m.id, e);
+            }
+        }
+    }
+}
+
+//Media as the api returns them
+pub fn media_json(m: &Media, posts: Vec<i32>, config: &MediaConfig) -> Value {
+    let variant_list = variants::widths_for(m, config).into_iter()
+        .flat_map(|width| variants::formats(config).into_iter()
+            .map(move |format| json!({"width": width, "format": format.extension(), "url": variants::url(m, width, format)})))
+        .collect::<Vec<Value>>();
+    json!({
+        "id": m.id,
+        "file_name": m.file_name,
+        "mime_type": m.mime_type,
+        "size": m.size,
+        "checksum": m.checksum,
+        "uploader": m.uploader,
+        "created": m.created,
+        "width": m.width,
+        "height": m.height,
+        "url": format!("/api/media/{}/file", m.id),
+        "variants": variant_list,
+        "srcset": variants::srcset(m, config),
+        "posts": posts,
+    })
+}
+
+fn with_posts(c: &mut MysqlConnection, found: Vec<Media>, config: &MediaConfig) -> QueryResult<Vec<Value>> {
+    let ids = found.iter().map(|m| m.id).collect::<Vec<i32>>();
+    let pairs = post_media::table
+        .filter(post_media::media_id.eq_any(&ids))
+        .select((post_media::media_id, post_media::post_id))
+        .load::<(i32, i32)>(c)?;
+    Ok(found.iter()
+        .map(|m| media_json(m, pairs.iter().filter(|(media_id, _)| *media_id == m.id).map(|(_, post_id)| *post_id).collect(), config))
+        .collect())
+}
+
+//Media linked to this post and to no other
+pub fn orphaned_by(c: &mut MysqlConnection, post_id: i32) -> QueryResult<Vec<i32>> {
+    let linked = post_media::table
+        .filter(post_media::post_id.eq(post_id))
+        .select(post_media::media_id)
+        .load::<i32>(c)?;
+    let shared = post_media::table
+        .filter(post_media::media_id.eq_any(&linked))
+        .filter(post_media::post_id.ne(post_id))
+        .select(post_media::media_id)
+        .load::<i32>(c)?;
+    Ok(linked.into_iter().filter(|m| !shared.contains(m)).collect())
+}
+
+//Delete media rows. Returns the checksums no other row uses, remove those files once the transaction commits.
+pub fn delete_rows(c: &mut MysqlConnection, ids: &[i32]) -> QueryResult<Vec<String>> {
+    let mut checksums = media::table
+        .filter(media::id.eq_any(ids))
+        .select(media::checksum)
+        .load::<String>(c)?;
+    checksums.sort_unstable();
+    checksums.dedup();
+
+    diesel::delete(media::table.filter(media::id.eq_any(ids))).execute(c)?;
+
+    let still_used = media::table
+        .filter(media::checksum.eq_any(&checksums))
+        .select(media::checksum)
+        .load::<String>(c)?;
+    Ok(checksums.into_iter().filter(|sum| !still_used.contains(sum)).collect())
+}
+
+//A file that could not be removed is only logged. The rows are already gone and a stray file harms nothing.
+pub async fn remove_files(store: &LocalStore, checksums: Vec<String>) {
+    for checksum in checksums {
+        if let Err(e) = store.remove(&checksum).await {
+            error!("Could not remove media file {}: {:?}", checksum, e);
+        }
+    }
+}
+
+//Range and If-Range of a file download
+pub struct RangeRequest {
+    range: Option<String>,
+    if_range: Option<String>,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest <'r> for RangeRequest {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<RangeRequest, ()> {
+        Outcome::Success(RangeRequest {
+            range: request.headers().get_one("Range").map(String::from),
+            if_range: request.headers().get_one("If-Range").map(String::from),
+        })
+    }
+}
+
+enum Ranged {
+    Full,
+    Part(u64, u64),
+    Unsatisfiable,
+}
+
+impl RangeRequest {
+    //Only a single byte range is served. Several ranges, other units or a stale If-Range get the whole file.
+    fn bytes(&self, len: u64, etag: &str) -> Ranged {
+        let range = match (&self.range, &self.if_range) {
+            (None, _) => return Ranged::Full,
+            (Some(_), Some(if_range)) if if_range != etag => return Ranged::Full,
+            (Some(range), _) => range,
+        };
+        let spec = match range.trim().strip_prefix("bytes=") {
+            Some(spec) if !spec.contains(',') => spec.trim(),
+            _ => return Ranged::Full,
+        };
+        let (start, end) = match spec.split_once('-') {
+            Some(bounds) => bounds,
+            None => return Ranged::Full,
+        };
+        let bounds = match (start.parse::<u64>().ok(), end.parse::<u64>().ok()) {
+            //bytes=500-999
+            (Some(start), Some(end)) if start <= end => (start, end.min(len.saturating_sub(1))),
+            //bytes=500-
+            (Some(start), None) if end.is_empty() => (start, len.saturating_sub(1)),
+            //bytes=-500, the last 500 bytes
+            (None, Some(suffix)) if start.is_empty() && suffix > 0 => (len.saturating_sub(suffix), len.saturating_sub(1)),
+            _ => return Ranged::Full,
+        };
+        match bounds.0 < len {
+            true => Ranged::Part(bounds.0, bounds.1),
+            false => Ranged::Unsatisfiable,
+        }
+    }
+}
+
+enum FileBody {
+    Full(File),
+    Part(Vec<u8>, u64, u64),
+    Unsatisfiable,
+}
+
+pub struct MediaFile {
+    body: FileBody,
+    len: u64,
+    mime_type: String,
+    etag: String,
+}
+
+impl<'r> Responder<'r, 'static> for MediaFile {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        let content_type = ContentType::parse_flexible(&self.mime_type).unwrap_or(ContentType::Binary);
+        let mut response = Response::build();
+        response
+            .header(Header::new("Accept-Ranges", "bytes"))
+            .header(Header::new("ETag", self.etag));
+
+        match self.body {
+            FileBody::Full(file) => {
+                response.header(content_type).sized_body(self.len as usize, file);
+            },
+            FileBody::Part(bytes, start, end) => {
+                response
+                    .status(Status::PartialContent)
+                    .header(content_type)
+                    .header(Header::new("Content-Range", format!("bytes {}-{}/{}", start, end, self.len)))
+                    .sized_body(bytes.len(), Cursor::new(bytes));
+            },
+            FileBody::Unsatisfiable => {
+                response
+                    .status(Status::RangeNotSatisfiable)
+                    .header(Header::new("Content-Range", format!("bytes */{}", self.len)));
+            },
+        }
+        response.ok()
+    }
+}
+
+pub mod routes {
+    use super::*;
+    use crate::auth::{Level1, ValidSession};
+    use crate::audit::{Resource, Trail};
+    use diesel::result::Error::NotFound;
+
+    #[derive(FromForm)]
+    pub struct Upload<'r> {
+        file: TempFile<'r>,
+        //Link the upload to this post right away
+        post: Option<i32>,
+    }
+
+    //The file's name, type and content and the post, an upload's Idempotency-Key is judged on these
+    #[rocket::async_trait]
+    impl<'r> Fingerprint for Upload<'r> {
+        async fn digest(&self, hasher: &mut Sha256) -> io::Result<()> {
+            let content_type = self.file.content_type().map(|ct| ct.to_string()).unwrap_or_default();
+            hasher.update(format!("{}\n{}\n{:?}\n", file_name(&self.file), content_type, self.post));
+            let reader = self.file.open().await?;
+            rocket::tokio::pin!(reader);
+            let mut buf = vec![0u8; 64 * 1024];
+            loop {
+                match reader.read(&mut buf).await? {
+                    0 => return Ok(()),
+                    n => hasher.update(&buf[..n]),
+                }
+            }
+        }
+    }
+
+    fn not_found() -> ApiError {
+        ApiError::not_found(Some(String::from("Could not locate media with provided id.")))
+    }
+
+    fn load_one(c: &mut MysqlConnection, id: i32) -> Result<Media, ApiError> {
+        media::table.filter(media::id.eq(id)).first::<Media>(c).map_err(|e| match e {
+            NotFound => not_found(),
+            e => ApiError::from(e),
+        })
+    }
+
+    //The uploader or an admin may change a media entry
+    fn check_owner(c: &mut MysqlConnection, found: &Media, user_id: i32) -> Result<(), ApiError> {
+        if found.uploader == Some(user_id) {
+            return Ok(());
+        }
+        let role = user::table.filter(user::id.eq(user_id)).select(user::role).first::<i32>(c)?;
+        match role {
+            1 => Ok(()),
+            _ => Err(ApiError::forbidden(Some(String::from("Only the uploader or an admin can change this media.")))),
+        }
+    }
+
+    //Only the last part of the name the client sent, never a path
+    fn file_name(file: &TempFile<'_>) -> String {
+        let raw = file.raw_name().map(|n| n.dangerous_unsafe_unsanitized_raw().as_str()).unwrap_or("");
+        let name = raw.rsplit(|c| c == '/' || c == '\\').next().unwrap_or("").trim();
+        match name.is_empty() {
+            true => String::from("upload"),
+            false => name.chars().take(255).collect(),
+        }
+    }
+
+    #[post("/", data = "<upload>")]
+    pub async fn upload(upload: KeyedForm<Upload<'_>>, conn: DbConn, user: ValidSession, env: &State<EnvVariables>, trail: &Trail) -> Result<status::Created<String>, ApiError> {
+        let upload = upload.into_inner();
+
+        let mime_type = upload.file.content_type()
+            .map(|ct| format!("{}/{}", ct.top(), ct.sub()).to_lowercase())
+            .unwrap_or_default();
+        if !env.media.allowed_types.iter().any(|t| t.eq_ignore_ascii_case(&mime_type)) {
+            return Err(ApiError::new(Status::UnsupportedMediaType, "UNSUPPORTED_MEDIA_TYPE",
+                Some(format!("Uploads of type '{}' are not accepted. Allowed: {}.", mime_type, env.media.allowed_types.join(", ")))));
+        }
+        if upload.file.len() == 0 {
+            return Err(ApiError::invalid_input(json!([{"field": "file", "message": "The file is empty."}])));
+        }
+
+        if let Some(post_id) = upload.post {
+            conn.run(move |c| post::table.filter(post::id.eq(post_id)).filter(post::deleted_at.is_null()).select(post::id).first::<i32>(c)).await
+                .map_err(|e| match e {
+                    NotFound => ApiError::invalid_input(json!([{"field": "post", "message": format!("No post with id {}.", post_id)}])),
+                    e => ApiError::from(e),
+                })?;
+        }
+
+        let mut bytes = Vec::with_capacity(upload.file.len() as usize);
+        let reader = upload.file.open().await.map_err(ApiError::internal_from)?;
+        rocket::tokio::pin!(reader);
+        reader.read_to_end(&mut bytes).await.map_err(ApiError::internal_from)?;
+
+        let prepare_type = mime_type.clone();
+        let prepared = rocket::tokio::task::spawn_blocking(move || prepare(&prepare_type, bytes)).await.map_err(ApiError::internal_from)?;
+        let store = LocalStore::new(&env.media);
+        store.save(&prepared.bytes, &prepared.checksum).await.map_err(ApiError::internal_from)?;
+
+        let (file_name, size, uploader, post_id) = (file_name(&upload.file), prepared.bytes.len() as i64, user.id, upload.post);
+        let (width, height) = match prepared.dimensions {
+            Some((width, height)) => (Some(width as i32), Some(height as i32)),
+            None => (None, None),
+        };
+        let checksum = prepared.checksum;
+        let created = conn.run(move |c| {
+            c.transaction(|c| {
+                diesel::insert_into(media::table)
+                    .values((
+                        media::file_name.eq(file_name),
+                        media::mime_type.eq(mime_type),
+                        media::size.eq(size),
+                        media::checksum.eq(checksum),
+                        media::uploader.eq(uploader),
+                        media::width.eq(width),
+                        media::height.eq(height),
+                    ))
+                    .execute(c)?;
+                let id = diesel::sql_query("SELECT LAST_INSERT_ID() AS id").get_result::<LastInsertId>(c)?.id as i32;
+                if let Some(post_id) = post_id {
+                    diesel::insert_into(post_media::table)
+                        .values((post_media::post_id.eq(post_id), post_media::media_id.eq(id)))
+                        .execute(c)?;
+                }
+                media::table.filter(media::id.eq(id)).first::<Media>(c)
+            })
+        }).await?;
+
+        let id = created.id;
+        trail.created(Resource::Media, id);
+        if env.media.eager_variants && variants::has_variants(&created.mime_type) {
+            rocket::tokio::spawn(render_all(store, created, env.media.clone()));
+        }
+
+        let uri = uri!("/api/media/", get(id)).to_string();
+        let body = json!(AResponse::_201(Some(uri.clone()))).to_string();
+        Ok(status::Created::new(uri).body(body))
+    }
+
+    #[get("/?<post>&<start>&<step>")]
+    pub async fn get_all(post: Option<i32>, start: Option<i64>, step: Option<i64>, conn: DbConn, env: &State<EnvVariables>) -> Result<Cached<Json<AResponse>>, ApiError> {
+        let config = env.media.clone();
+        let found = conn.run(move |c| {
+            let mut query = media::table.into_boxed();
+            if let Some(post_id) = post {
+                query = query.filter(media::id.eq_any(post_media::table.filter(post_media::post_id.eq(post_id)).select(post_media::media_id)));
+            }
+            let found = query
+                .order(media::id.asc())
+                .offset(start.unwrap_or(0))
+                .limit(step.unwrap_or(100))
+                .load::<Media>(c)?;
+            with_posts(c, found, &config)
+        }).await?;
+        Ok(Cached::json(AResponse::_200(Some(json!(found)))))
+    }
+
+    //Media no post links to, candidates for clean up
+    #[get("/orphans")]
+    pub async fn get_orphans(conn: DbConn, _x: Level1, env: &State<EnvVariables>) -> Result<Json<AResponse>, ApiError> {
+        let config = env.media.clone();
+        let found = conn.run(move |c| {
+            let found = media::table
+                .filter(diesel::dsl::not(media::id.eq_any(post_media::table.select(post_media::media_id))))
+                .order(media::id.asc())
+                .load::<Media>(c)?;
+            with_posts(c, found, &config)
+        }).await?;
+        Ok(Json(AResponse::_200(Some(json!(found)))))
+    }
+
+    #[get("/<id>")]
+    pub async fn get(id: i32, conn: DbConn, env: &State<EnvVariables>) -> Result<Cached<Json<AResponse>>, ApiError> {
+        let config = env.media.clone();
+        let found = conn.run(move |c| {
+            let found = load_one(c, id)?;
+            Ok::<_, ApiError>(with_posts(c, vec![found], &config)?)
+        }).await?;
+        Ok(Cached::json(AResponse::_200(Some(json!(found)))))
+    }
+
+    //The file itself. Supports Range requests so players and pdf viewers can seek.
+    #[get("/<id>/file")]
+    pub async fn get_file(id: i32, range: RangeRequest, conn: DbConn, env: &State<EnvVariables>) -> Result<Cached<MediaFile>, ApiError> {
+        let found = conn.run(move |c| load_one(c, id)).await?;
+
+        let mut file = File::open(LocalStore::new(&env.media).path(&found.checksum)).await.map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => ApiError::not_found(Some(String::from("The file of this media is missing."))),
+            _ => ApiError::internal_from(e),
+        })?;
+        let len = file.metadata().await.map_err(ApiError::internal_from)?.len();
+        let etag = format!("\"{}\"", found.checksum);
+
+        let body = match range.bytes(len, &etag) {
+            Ranged::Full => FileBody::Full(file),
+            Ranged::Part(start, end) => {
+                let mut bytes = vec![0u8; (end - start + 1) as usize];
+                file.seek(SeekFrom::Start(start)).await.map_err(ApiError::internal_from)?;
+                file.read_exact(&mut bytes).await.map_err(ApiError::internal_from)?;
+                FileBody::Part(bytes, start, end)
+            },
+            Ranged::Unsatisfiable => FileBody::Unsatisfiable,
+        };
+        Ok(Cached::new(MediaFile { body, len, mime_type: found.mime_type, etag: etag.clone() }, etag))
+    }
+
+    //A resized copy, name is <width>.<format> as listed in the media's variants. Rendered on the first request.
+    #[get("/<id>/variants/<name>")]
+    pub async fn get_variant(id: i32, name: &str, conn: DbConn, env: &State<EnvVariables>) -> Result<Cached<NamedFile>, ApiError> {
+        let found = conn.run(move |c| load_one(c, id)).await?;
+
+        let no_variant = || ApiError::not_found(Some(String::from("This media has no variant with that name.")));
+        let (width, format) = match name.split_once('.') {
+            Some((width, ext)) => (width.parse::<u32>().map_err(|_| no_variant())?, Format::parse(ext).ok_or_else(no_variant)?),
+            None => return Err(no_variant()),
+        };
+        if !variants::widths_for(&found, &env.media).contains(&width) || !variants::formats(&env.media).contains(&format) {
+            return Err(no_variant());
+        }
+
+        let store = LocalStore::new(&env.media);
+        let dest = store.variant_path(&found.checksum, width, format);
+        let path = variants::ensure(store.path(&found.checksum), dest, width, format, env.media.jpeg_quality).await.map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => ApiError::not_found(Some(String::from("The file of this media is missing."))),
+            _ => ApiError::internal_from(e),
+        })?;
+        let file = NamedFile::open(path).await.map_err(ApiError::internal_from)?;
+        let etag = format!("\"{}-{}.{}\"", found.checksum, width, format.extension());
+        Ok(Cached::new(file, etag))
+    }
+
+    #[put("/<id>/posts/<post_id>")]//Put 204 403 404
+    pub async fn put_post(id: i32, post_id: i32, conn: DbConn, user: ValidSession, trail: &Trail) -> Result<status::NoContent, ApiError> {
+        trail.watch(&conn, Resource::Media, id).await?;
+        conn.run(move |c| {
+            let found = load_one(c, id)?;
+            check_owner(c, &found, user.id)?;
+            post::table.filter(post::id.eq(post_id)).filter(post::deleted_at.is_null()).select(post::id).first::<i32>(c).map_err(|e| match e {
+                NotFound => ApiError::not_found(Some(String::from("Could not locate post with provided id."))),
+                e => ApiError::from(e),
+            })?;
+            diesel::insert_or_ignore_into(post_media::table)
+                .values((post_media::post_id.eq(post_id), post_media::media_id.eq(id)))
+                .execute(c)?;
+            Ok::<_, ApiError>(())
+        }).await?;
+        Ok(status::NoContent)
+    }
+
+    #[delete("/<id>/posts/<post_id>")]//Delete 204 403 404
+    pub async fn delete_post(id: i32, post_id: i32, conn: DbConn, user: ValidSession, trail: &Trail) -> Result<status::NoContent, ApiError> {
+        trail.watch(&conn, Resource::Media, id).await?;
+        conn.run(move |c| {
+            let found = load_one(c, id)?;
+            check_owner(c, &found, user.id)?;
+            let rows = diesel::delete(post_media::table.filter(post_media::post_id.eq(post_id)).filter(post_media::media_id.eq(id))).execute(c)?;
+            match rows {
+                0 => Err(ApiError::not_found(Some(String::from("This media is not linked to that post.")))),
+                _ => Ok(()),
+            }
+        }).await?;
+        Ok(status::NoContent)
+    }
+
+    //Posts that link the media lose the link. The file goes once no other media entry shares it.
+    #[delete("/<id>")]//Delete 204 403 404
+    pub async fn delete(id: i32, conn: DbConn, user: ValidSession, env: &State<EnvVariables>, trail: &Trail) -> Result<status::NoContent, ApiError> {
+        trail.watch(&conn, Resource::Media, id).await?;
+        let unused = conn.run(move |c| {
+            c.transaction::<_, ApiError, _>(|c| {
+                let found = load_one(c, id)?;
+                check_owner(c, &found, user.id)?;
+                Ok(delete_rows(c, &[id])?)
+            })
+        }).await?;
+        remove_files(&LocalStore::new(&env.media), unused).await;
+        Ok(status::NoContent)
+    }
+}
@@ -1,4 +1,4 @@
-use super::schema::{post, tag, post_tags, user, role, user_tags};
+use super::schema::{post, tag, post_tags, user, role, user_tags, series, media, audit_log, webhook, webhook_delivery, webhook_attempt};
 use rocket::serde::json::Value;
 
 #[derive(Debug, FromForm)]
@@ -11,6 +11,9 @@ use rocket::serde::json::Value;
     }
     
     impl QParams {
+        //Most rows one page may ask for, a larger step is cut down to it
+        pub const MAX_STEP: i64 = 1000;
+
         pub fn new_filter(filter: Filters) -> Self {
             QParams {
                 filter,
@@ -100,7 +103,7 @@ pub struct InformationalResponse {
 
  */
 
-#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[derive(serde::Serialize, serde::Deserialize, Debug, schemars::JsonSchema)]
 pub struct AResponse {
     pub status: String,
 
@@ -141,86 +144,6 @@ impl AResponse {
             errors: None,
         }
     }
-    pub fn _400(message: Option<String>) -> Self {
-        AResponse {
-            status: String::from("Error"),
-            data: None,
-            message: message,
-            location: None,
-            code: Some(String::from("INVALID_USER_INPUT")),
-            errors: None,
-        }
-    }
-    pub fn _401(message: Option<String>) -> Self {
-        AResponse {
-            status: String::from("Error"),
-            data: None,
-            message: message,
-            location: None,
-            code: Some(String::from("UNAUTHORIZED")),
-            errors: None,
-        }
-    }
-    pub fn _403(message: Option<String>) -> Self {
-        AResponse {
-            status: String::from("Error"),
-            data: None,
-            message: message,
-            location: None,
-            code: Some(String::from("FORBIDDEN")),
-            errors: None,
-        }
-    }
-    pub fn _404(message: Option<String>) -> Self {
-        AResponse {
-            status: String::from("Error"),
-            data: None,
-            message: message,
-            location: None,
-            code: Some(String::from("NOT_FOUND")),
-            errors: None,
-        }
-    }
-    pub fn _409(message: Option<String>) -> Self {
-        AResponse {
-            status: String::from("Error"),
-            data: None,
-            message: message,
-            location: None,
-            code: Some(String::from("CONFLICT")),
-            errors: None,
-        }
-    }
-    pub fn _422(message: Option<String>, code: Option<String>, errors: Option<Value>) -> Self {
-            AResponse {
-                status: String::from("Error"),
-                data: None,
-                message: message,
-                location: None,
-                code: code,
-                errors: errors,
-            }
-    }
-    pub fn _500() -> Self {
-        AResponse {
-            status: String::from("Error"),
-            data: None,
-            message: Some(String::from("Our apologies, something went wrong.")),
-            location: None,
-            code: Some(String::from("INTERNAL_SERVER_ERROR")),
-            errors: None,
-        }
-}
-    pub fn error(errors: Option<Value>) -> Self {
-            AResponse {
-                status: String::from("Error"),
-                data: None,
-                message: Some(String::from("An unexpected error type has occured.")),
-                location: None,
-                code: Some(String::from("UNEXPECTED_ERROR_TYPE")),
-                errors: errors,
-            }
-    }
 }
 
 
@@ -234,7 +157,7 @@ pub struct MyResponse {
     pub errors: Vec<ErrorResponse>,
 }
 
-#[derive(serde::Serialize, Queryable, Identifiable, Debug, serde::Deserialize, Clone)]
+#[derive(serde::Serialize, Queryable, Identifiable, Debug, serde::Deserialize, Clone, schemars::JsonSchema)]
 #[diesel(table_name = post)]
 pub struct BlogEntry {
     pub id: i32,
@@ -243,13 +166,107 @@ pub struct BlogEntry {
     pub created: Option<chrono::NaiveDateTime>,
     pub last_updated: Option<chrono::NaiveDate>,
     pub content: Option<String>,
+    pub version: i32,
+    //Set while the post is in the trash, see trash.rs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<chrono::NaiveDateTime>,
 }
 
-#[derive(serde::Serialize, Queryable, Identifiable, Debug, serde::Deserialize, AsChangeset, Selectable, PartialEq)]
+#[derive(serde::Serialize, Queryable, Identifiable, Debug, serde::Deserialize, AsChangeset, Selectable, PartialEq, schemars::JsonSchema)]
 #[diesel(table_name = tag)]
 pub struct Tag {
     pub id: i32,
     pub name: String,
+    pub version: i32,
+    pub parent_id: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(serde::Serialize, Queryable, Identifiable, Debug, serde::Deserialize, Selectable, schemars::JsonSchema)]
+#[diesel(table_name = series)]
+pub struct Series {
+    pub id: i32,
+    pub title: String,
+    pub description: Option<String>,
+}
+
+#[derive(serde::Serialize, Queryable, Identifiable, Debug, Selectable, Clone, schemars::JsonSchema)]
+#[diesel(table_name = media)]
+pub struct Media {
+    pub id: i32,
+    pub file_name: String,
+    pub mime_type: String,
+    pub size: i64,
+    //sha256 of the content, also the name of the file on disk
+    pub checksum: String,
+    pub uploader: Option<i32>,
+    pub created: chrono::NaiveDateTime,
+    //Pixels, only known for images that could be decoded
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+}
+
+#[derive(Queryable, Identifiable, Debug, Selectable)]
+#[diesel(table_name = audit_log)]
+pub struct AuditEntry {
+    pub id: i64,
+    //user id from the JWT, None for anonymous requests such as a login
+    pub actor: Option<i32>,
+    pub action: String,
+    pub resource: String,
+    pub resource_id: Option<i32>,
+    pub method: String,
+    pub path: String,
+    pub status: i32,
+    //JSON text of the resource around the change
+    pub before_state: Option<String>,
+    pub after_state: Option<String>,
+    pub ip: Option<String>,
+    pub created: chrono::NaiveDateTime,
+}
+
+#[derive(Queryable, Identifiable, Debug, Selectable, serde::Serialize)]
+#[diesel(table_name = webhook)]
+pub struct Webhook {
+    pub id: i32,
+    pub url: String,
+    //Signs the payloads. Only shown once, in the answer to the POST that created the webhook.
+    #[serde(skip_serializing)]
+    pub secret: String,
+    //Comma separated, see webhook::EVENTS
+    pub events: String,
+    pub active: bool,
+    pub created: chrono::NaiveDateTime,
+}
+
+#[derive(Queryable, Identifiable, Debug, Selectable, serde::Serialize)]
+#[diesel(table_name = webhook_delivery)]
+pub struct WebhookDelivery {
+    pub id: i64,
+    pub webhook_id: i32,
+    pub event: String,
+    pub resource_id: Option<i32>,
+    //The JSON body exactly as it is signed and sent
+    pub payload: String,
+    //pending, delivered or failed
+    pub status: String,
+    pub attempts: i32,
+    pub next_attempt: Option<chrono::NaiveDateTime>,
+    pub created: chrono::NaiveDateTime,
+    pub delivered: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Queryable, Identifiable, Debug, Selectable, serde::Serialize)]
+#[diesel(table_name = webhook_attempt)]
+pub struct WebhookAttempt {
+    pub id: i64,
+    pub delivery_id: i64,
+    pub attempted: chrono::NaiveDateTime,
+    //None when no answer came back, see error
+    pub response_status: Option<i32>,
+    pub error: Option<String>,
+    pub duration_ms: i32,
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Debug, Queryable)]
@@ -304,6 +321,8 @@ pub struct User {
     pub role: i32,
     pub active: Option<bool>,
     pub last_access: Option<chrono::NaiveDate>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<chrono::NaiveDateTime>,
 }
 
 #[derive(Insertable, serde::Deserialize, Queryable, Debug)]
@@ -317,7 +336,7 @@ pub struct NewUser {
     pub active: bool,
 }
 
-#[derive(serde::Serialize, serde::Deserialize, Identifiable, Queryable, PartialEq, Debug)]
+#[derive(serde::Serialize, serde::Deserialize, Identifiable, Queryable, PartialEq, Debug, schemars::JsonSchema)]
 #[diesel(table_name = role)]
 pub struct Role {
     pub id: i32,
@@ -338,4 +357,23 @@ use rocket::serde::Deserialize;
 #[serde(crate = "rocket::serde")]
 pub struct EnvVariables {
     pub jwt_secret: String,
+    #[serde(default)]
+    pub cors: crate::cors::CorsConfig,
+    //Reject post and tag writes that do not send If-Match, see cache.rs
+    #[serde(default)]
+    pub require_if_match: bool,
+    #[serde(default)]
+    pub media: crate::media::MediaConfig,
+    #[serde(default)]
+    pub trash: crate::trash::TrashConfig,
+    #[serde(default)]
+    pub webhooks: crate::webhook::WebhookConfig,
+    #[serde(default)]
+    pub graphql: crate::graphql::GraphQLConfig,
+    #[serde(default)]
+    pub versions: crate::version::VersionsConfig,
+    #[serde(default)]
+    pub bulk: crate::bulk::BulkConfig,
+    #[serde(default)]
+    pub idempotency: crate::idempotency::IdempotencyConfig,
 }
\ No newline at end of file
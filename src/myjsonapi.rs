@@ -1,32 +1,261 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::io::Cursor;
+use rocket::http::{ContentType, Header, MediaType, Status};
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::{self, Responder, Response};
+use rocket::serde::json::{Value, json};
+use serde_json::Map;
+use crate::error::ApiError;
 
 //https://jsonapi.org/format/
+/*
+Clients that send "Accept: application/vnd.api+json" get posts, tags and users as JSON:API documents instead of AResponse.
+Writes in that format are accepted when the body is sent with "Content-Type: application/vnd.api+json".
+
+GET /api/posts?include=tags,author&fields[posts]=title,tags
+{
+    "data": [{
+        "type": "posts", "id": "3",
+        "attributes": {"title": "Hello"},
+        "relationships": {"tags": {"data": [{"type": "tags", "id": "1"}]}},
+        "links": {"self": "/api/posts/3"}
+    }],
+    "included": [{"type": "tags", "id": "1", "attributes": {"name": "Rust"}, ...}],
+    "jsonapi": {"version": "1.0"}
+}
+*/
+
+pub fn media_type() -> MediaType {
+    MediaType::new("application", "vnd.api+json")
+}
+
+fn is_json_api(media_type: Option<&MediaType>) -> bool {
+    match media_type {
+        Some(m) => m.top() == "application" && m.sub() == "vnd.api+json",
+        None => false,
+    }
+}
+
+//True if the client asked for, or sent, a JSON:API document.
+pub fn wants_json_api(request: &Request<'_>) -> bool {
+    is_json_api(request.accept().map(|a| a.preferred().media_type()))
+        || is_json_api(request.content_type().map(|c| c.media_type()))
+}
+
+//Guard for the JSON:API variant of a GET route. Forwards to the plain route unless the client prefers JSON:API.
+//A route format alone is not enough, Rocket matches GET routes with a format when no Accept header is sent at all.
+pub struct JsonApiRequest;
+
+#[rocket::async_trait]
+impl<'r> FromRequest <'r> for JsonApiRequest {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<JsonApiRequest, ()> {
+        match is_json_api(request.accept().map(|a| a.preferred().media_type())) {
+            true => Outcome::Success(JsonApiRequest),
+            false => Outcome::Forward(()),
+        }
+    }
+}
+
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub struct JSONAPIError {
     pub status: String, //"401"
-    pub canonical: String, //Unauthorized
-    pub title: String, //JWT not authorized.
-    pub detail: String, //Your session is expired.
+    pub code: String, //UNAUTHORIZED
+    pub title: String, //Unauthorized
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>, //Your session is expired.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub meta: Option<Value>, //Field level problems, the same list a problem+json response carries.
+}
+
+//A top level document. Serialized with the JSON:API media type.
+pub struct Document {
+    pub status: Status,
+    pub body: Value,
+    pub location: Option<String>,
 }
 
+impl Document {
+    pub fn ok(body: Value) -> Self {
+        Document { status: Status::Ok, body, location: None }
+    }
 
-//Kind of works.. but one issue is that the Vecs require square brackets even when only one entry is passed.
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
-pub struct JSONAPIPatch {
-    pub id: String, //1
-    pub type_: String, //"articles"
-    pub attributes: Option<HashMap<String, String>>,
-    pub relationships: Option<
-            Vec<
-                HashMap<String,//"author"
-                    HashMap<String,//"data"
-                        Vec<
-                            HashMap<String, String>//"type": "people"
-                        >
-                    >
-                >
-            >
-    >, 
+    pub fn created(body: Value, location: String) -> Self {
+        Document { status: Status::Created, body, location: Some(location) }
+    }
+
+    pub fn single(data: Value, included: Vec<Value>) -> Value {
+        let mut doc = json!({"data": data, "jsonapi": {"version": "1.0"}});
+        if !included.is_empty() {
+            doc["included"] = json!(included);
+        }
+        doc
+    }
+
+    pub fn collection(data: Vec<Value>, included: Vec<Value>) -> Value {
+        Document::single(json!(data), included)
+    }
+
+    pub fn errors(errors: Vec<JSONAPIError>) -> Value {
+        json!({"errors": errors, "jsonapi": {"version": "1.0"}})
+    }
+}
+
+impl<'r> Responder<'r, 'static> for Document {
+    fn respond_to(self, _req: &'r Request<'_>) -> response::Result<'static> {
+        let body = self.body.to_string();
+        let mut response = Response::build();
+        response
+            .status(self.status)
+            .header(ContentType(media_type()))
+            .sized_body(body.len(), Cursor::new(body));
+        if let Some(location) = self.location {
+            response.header(Header::new("Location", location));
+        }
+        response.ok()
+    }
+}
+
+//fields[posts]=title,created  ->  {"posts": ["title", "created"]}
+pub struct Fieldsets(HashMap<String, Vec<String>>);
+
+impl Fieldsets {
+    pub fn parse(fields: Option<HashMap<String, String>>) -> Self {
+        Fieldsets(fields.unwrap_or_default()
+            .into_iter()
+            .map(|(type_, f)| (type_, f.split(',').map(|f| f.trim().to_string()).filter(|f| !f.is_empty()).collect()))
+            .collect())
+    }
+
+    fn allows(&self, type_: &str, field: &str) -> bool {
+        match self.0.get(type_) {
+            Some(fields) => fields.iter().any(|f| f == field),
+            None => true,
+        }
+    }
+}
+
+//include=tags,author  ->  {"tags", "author"}. Unsupported paths are a 400 per the spec.
+pub struct Includes(HashSet<String>);
+
+impl Includes {
+    pub fn parse(include: Option<String>, supported: &[&str]) -> Result<Self, ApiError> {
+        let requested: HashSet<String> = include.unwrap_or_default()
+            .split(',')
+            .map(|i| i.trim().to_string())
+            .filter(|i| !i.is_empty())
+            .collect();
+
+        match requested.iter().find(|i| !supported.contains(&i.as_str())) {
+            Some(i) => Err(ApiError::bad_request(Some(format!("'{}' can not be included. Supported values are: {}.", i, supported.join(", "))))),
+            None => Ok(Includes(requested)),
+        }
+    }
+
+    pub fn contains(&self, relationship: &str) -> bool {
+        self.0.contains(relationship)
+    }
+}
+
+pub fn identifier(type_: &str, id: i32) -> Value {
+    json!({"type": type_, "id": id.to_string()})
+}
+
+pub struct ResourceObject {
+    type_: &'static str,
+    id: i32,
+    attributes: Map<String, Value>,
+    relationships: Map<String, Value>,
+}
+
+impl ResourceObject {
+    //Serialize a model and use its fields (minus the id) as attributes.
+    pub fn new<T: serde::Serialize>(type_: &'static str, id: i32, model: &T) -> Self {
+        let mut attributes = match json!(model) {
+            Value::Object(map) => map,
+            _ => Map::new(),
+        };
+        attributes.remove("id");
+        ResourceObject { type_, id, attributes, relationships: Map::new() }
+    }
+
+    //Foreign keys belong in relationships, not attributes.
+    pub fn without(mut self, attribute: &str) -> Self {
+        self.attributes.remove(attribute);
+        self
+    }
+
+    pub fn to_one(mut self, name: &str, type_: &str, id: Option<i32>) -> Self {
+        let data = match id {
+            Some(id) => identifier(type_, id),
+            None => Value::Null,
+        };
+        self.relationships.insert(String::from(name), json!({"data": data}));
+        self
+    }
+
+    pub fn to_many<I: IntoIterator<Item = i32>>(mut self, name: &str, type_: &str, ids: I, related: Option<String>) -> Self {
+        let mut relationship = json!({"data": ids.into_iter().map(|id| identifier(type_, id)).collect::<Vec<Value>>()});
+        if let Some(related) = related {
+            relationship["links"] = json!({"related": related});
+        }
+        self.relationships.insert(String::from(name), relationship);
+        self
+    }
+
+    pub fn into_value(self, fields: &Fieldsets) -> Value {
+        let type_ = self.type_;
+        let attributes: Map<String, Value> = self.attributes.into_iter().filter(|(k, _)| fields.allows(type_, k)).collect();
+        let relationships: Map<String, Value> = self.relationships.into_iter().filter(|(k, _)| fields.allows(type_, k)).collect();
+
+        let mut resource = json!({
+            "type": type_,
+            "id": self.id.to_string(),
+            "links": {"self": format!("/api/{}/{}", type_, self.id)},
+        });
+        if !attributes.is_empty() {
+            resource["attributes"] = Value::Object(attributes);
+        }
+        if !relationships.is_empty() {
+            resource["relationships"] = Value::Object(relationships);
+        }
+        resource
+    }
+}
+
+//Incoming document for create / patch. {"data": {"type": "posts", "id": "3", "attributes": {...}}}
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct WriteDocument<A> {
+    pub data: WriteResource<A>,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct WriteResource<A> {
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub id: Option<String>,
+    pub attributes: A,
+    pub relationships: Option<Value>,
+}
+
+impl<A> WriteResource<A> {
+    //The spec asks for a 409 when the type or id in the body does not match the endpoint.
+    pub fn check(&self, type_: &str, id: Option<i32>) -> Result<(), ApiError> {
+        if self.type_ != type_ {
+            return Err(ApiError::conflict(Some(format!("Expected a resource of type '{}' but got '{}'.", type_, self.type_))));
+        }
+        if let Some(id) = id {
+            if self.id.as_deref() != Some(id.to_string().as_str()) {
+                return Err(ApiError::conflict(Some(String::from("The id in the body does not match the id in the url."))));
+            }
+        }
+        //Relationships have their own endpoints and guards (e.g. /api/posts/<id>/tags), don't bypass them here.
+        if self.relationships.is_some() {
+            return Err(ApiError::forbidden(Some(String::from("Relationships can not be changed in this request. Use the relationship's own endpoint."))));
+        }
+        Ok(())
+    }
 }
 
 //To return an array of 1 or to not... https://github.com/json-api/json-api/issues/268
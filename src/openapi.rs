@@ -0,0 +1,703 @@
+use std::collections::{BTreeMap, BTreeSet};
+use rocket::{Build, Rocket};
+use rocket::fairing;
+use rocket::http::Method;
+use rocket::serde::json::{Map, Value, json};
+use schemars::JsonSchema;
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::schema::Schema;
+use crate::version::{self, ApiVersion};
+
+/*
+The OpenAPI 3 document is built once at start up from two things:
+the routes mounted on rocket, which give the paths, methods, path and query parameters and json:api formats,
+and the table in docs() below, which gives each operation its summary, access, body and answer.
+Bodies and answers are schemas derived (schemars::JsonSchema) from the same types the handlers take and return,
+NewPost, Tags, CreateNewUser, AResponse and so on, so changing a field changes the document.
+
+GET /openapi.json              The document. The swagger ui under /openapi reads it.
+
+Only routes under /api and /graphql are documented. Routes that share a method and path, such as the json:api
+variants or the forbidden / unauthorized fallbacks, are one operation.
+On ignite every mounted route must have an entry in docs() and every entry a mounted route. Rocket refuses to
+launch when they disagree, and the routes_and_docs_agree test below fails, so the document is never out of date.
+Adding a route therefore means adding its entry here.
+*/
+
+//Error body of every failed request, see error.rs
+#[allow(dead_code)]
+#[derive(JsonSchema)]
+struct Problem {
+    r#type: String,
+    title: String,
+    status: u16,
+    code: String,
+    instance: String,
+    detail: Option<String>,
+    errors: Option<Value>,
+}
+
+//Body of POST /graphql
+#[allow(dead_code)]
+#[derive(JsonSchema)]
+struct GraphQLRequest {
+    query: String,
+    #[serde(rename = "operationName")]
+    operation_name: Option<String>,
+    variables: Option<Value>,
+}
+
+//Body of POST /api/media
+#[allow(dead_code)]
+#[derive(JsonSchema)]
+struct Upload {
+    #[schemars(schema_with = "binary")]
+    file: String,
+    //Link the upload to this post right away
+    post: Option<i32>,
+}
+
+fn binary(_: &mut SchemaGenerator) -> Schema {
+    schemars::schema::SchemaObject {
+        instance_type: Some(schemars::schema::InstanceType::String.into()),
+        format: Some(String::from("binary")),
+        ..Default::default()
+    }.into()
+}
+
+type SchemaFn = fn(&mut SchemaGenerator) -> Schema;
+
+fn schema_of<T: JsonSchema>(gen: &mut SchemaGenerator) -> Schema {
+    gen.subschema_for::<T>()
+}
+
+enum Access {
+    Public,
+    Session,
+    Admin,
+}
+
+impl Access {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Access::Public => "public",
+            Access::Session => "session",
+            Access::Admin => "admin",
+        }
+    }
+}
+
+enum Answer {
+    //AResponse, data holding the given schema when there is one
+    Envelope(Option<SchemaFn>),
+    //Plain json
+    Bare(SchemaFn),
+    //Anything that is not json
+    Content(&'static str),
+    Empty,
+}
+
+struct Doc {
+    method: Method,
+    path: &'static str,
+    tag: &'static str,
+    summary: &'static str,
+    description: &'static str,
+    access: Access,
+    body: Option<(&'static str, SchemaFn)>,
+    //The same body wrapped for application/vnd.api+json
+    write_document: Option<SchemaFn>,
+    answer: Answer,
+    status: u16,
+}
+
+fn doc(method: Method, path: &'static str, tag: &'static str, summary: &'static str) -> Doc {
+    Doc { method, path, tag, summary, description: "", access: Access::Public, body: None, write_document: None, answer: Answer::Envelope(None), status: 200 }
+}
+
+fn get(path: &'static str, tag: &'static str, summary: &'static str) -> Doc { doc(Method::Get, path, tag, summary) }
+fn post(path: &'static str, tag: &'static str, summary: &'static str) -> Doc { doc(Method::Post, path, tag, summary) }
+fn put(path: &'static str, tag: &'static str, summary: &'static str) -> Doc { doc(Method::Put, path, tag, summary) }
+fn patch(path: &'static str, tag: &'static str, summary: &'static str) -> Doc { doc(Method::Patch, path, tag, summary) }
+fn delete(path: &'static str, tag: &'static str, summary: &'static str) -> Doc { doc(Method::Delete, path, tag, summary) }
+
+impl Doc {
+    fn describe(mut self, description: &'static str) -> Self {
+        self.description = description;
+        self
+    }
+
+    fn session(mut self) -> Self {
+        self.access = Access::Session;
+        self
+    }
+
+    fn admin(mut self) -> Self {
+        self.access = Access::Admin;
+        self
+    }
+
+    fn body<T: JsonSchema>(mut self) -> Self {
+        self.body = Some(("application/json", schema_of::<T>));
+        self.write_document = Some(schema_of::<crate::myjsonapi::WriteDocument<T>>);
+        self
+    }
+
+    fn upload(mut self) -> Self {
+        self.body = Some(("multipart/form-data", schema_of::<Upload>));
+        self
+    }
+
+    fn graphql(mut self) -> Self {
+        self.body = Some(("application/json", schema_of::<GraphQLRequest>));
+        self.answer = Answer::Bare(schema_of::<Value>);
+        self
+    }
+
+    fn returns<T: JsonSchema>(mut self) -> Self {
+        self.answer = Answer::Envelope(Some(schema_of::<T>));
+        self
+    }
+
+    fn bare<T: JsonSchema>(mut self) -> Self {
+        self.answer = Answer::Bare(schema_of::<T>);
+        self
+    }
+
+    fn content(mut self, media_type: &'static str) -> Self {
+        self.answer = Answer::Content(media_type);
+        self
+    }
+
+    fn created(mut self) -> Self {
+        self.status = 201;
+        self
+    }
+
+    fn no_content(mut self) -> Self {
+        self.answer = Answer::Empty;
+        self.status = 204;
+        self
+    }
+}
+
+//One entry per documented method and path
+fn docs() -> Vec<Doc> {
+    vec![
+        get("/api", "Api", "Discovery document.")
+            .describe("Every mounted route with its methods, access and query parameters, the filters and orders each collection supports, the api version and build, and links to each collection.")
+            .bare::<Value>(),
+        get("/api/audit", "Audit", "Who changed what.")
+            .describe("Admins only. Every successful POST, PUT, PATCH and DELETE under /api is logged, newest first. Entries for posts, tags, users, roles, series and media carry the resource before and after the change.")
+            .admin(),
+        get("/api/events", "Events", "Live stream of changes.")
+            .describe("Server-Sent Events for posts, tags and users, named like post.updated or tag.deleted. Admins see everything, other sessions see tags, published posts and their own user. Send Last-Event-ID to resume; when events were missed a reset event comes first and the client should reload. The stream ends when the session expires.")
+            .session()
+            .content("text/event-stream"),
+        get("/api/media", "Media", "List uploaded media.")
+            .returns::<Vec<crate::models::Media>>(),
+        post("/api/media", "Media", "Upload a file.")
+            .describe("A multipart upload. The Content-Type of the file part must be one of the allowed types in Rocket.toml, otherwise it is a 415.<br> Files above the configured size limit are rejected with a 413.")
+            .session()
+            .upload()
+            .created(),
+        delete("/api/media/{id}", "Media", "Delete an uploaded file.")
+            .describe("The uploader or an admin only. Posts lose their link to it.")
+            .session(),
+        get("/api/media/{id}", "Media", "The metadata of an uploaded file.")
+            .returns::<crate::models::Media>(),
+        get("/api/media/{id}/file", "Media", "Download the file.")
+            .describe("Served with its stored Content-Type. A single byte range can be requested with the Range header.")
+            .content("application/octet-stream"),
+        delete("/api/media/{id}/posts/{post_id}", "Media", "Remove the link between a media entry and a post.")
+            .describe("The uploader or an admin only. The file is kept.")
+            .session()
+            .no_content(),
+        put("/api/media/{id}/posts/{post_id}", "Media", "Link a media entry to a post.")
+            .describe("The uploader or an admin only.")
+            .session()
+            .no_content(),
+        get("/api/media/{id}/variants/{name}", "Media", "Download a resized copy of an image.")
+            .describe("The names are listed in the variants of the media entry. A variant is rendered on its first request and kept on disk. Variants carry no EXIF.")
+            .content("image/*"),
+        get("/api/media/orphans", "Media", "Media that no post links to.")
+            .describe("Admins only.")
+            .admin()
+            .returns::<Vec<crate::models::Media>>(),
+        get("/api/posts", "Posts", "Return a list of blog posts.")
+            .describe("The API allows users to filter the type and quantity of posts by specifying query parameters that match columns in the database.")
+            .returns::<Vec<crate::post::routes::PostAndTags>>(),
+        post("/api/posts", "Posts", "Create a new post.")
+            .describe("When valid fieds are passed in the request body, a new post will be created. A 201 will contain the new post id. The session jwt will be used to determine the author automatically.")
+            .session()
+            .body::<crate::post::routes::NewPost>()
+            .created(),
+        delete("/api/posts/{id}", "Posts", "Delete a post by ID")
+            .describe("Moves the post identified by the given ID to the trash, with its tags. It can be restored until the trash is purged.")
+            .admin(),
+        post("/api/posts/bulk", "Posts", "Create, update, delete and tag many posts.")
+            .describe("Operations are create, patch, delete and attach_tags, each with the access of its single route. With mode atomic (the default) all of them are applied in one transaction or none are, a failure answers with the error of the first failed operation. With mode per_item each is applied on its own and the answer is a 207 with a result per operation.")
+            .session()
+            .body::<crate::bulk::Bulk<crate::post::routes::PostOperation>>()
+            .returns::<Vec<crate::bulk::ItemResult>>(),
+        get("/api/posts/{id}", "Posts", "Return a single post.")
+            .describe("When a valid post id is passed in, a post object is returned.")
+            .returns::<Vec<crate::post::routes::PostAndTags>>(),
+        patch("/api/posts/{id}", "Posts", "Update an existing Post.")
+            .describe("Title and content of the post can be altered.")
+            .session()
+            .body::<crate::post::routes::NewPost>()
+            .no_content(),
+        get("/api/posts/{id}/related", "Posts", "Posts similar to this one.")
+            .describe("Other published posts ranked by the tags they share with this post. Rare tags count for more than tags found on most posts.<br> With text=true, words shared by the titles and the start of the content add to the score, so posts without a common tag can show up too.<br> Posts without a created date or dated in the future are never returned.")
+            .returns::<Vec<crate::post::routes::RelatedPost>>(),
+        patch("/api/posts/{id}/tags", "Posts", "Add tags to a post.")
+            .describe("Use either query params or pass along a form to add tags to a post. Note the **Request Body** drop down below. Nothing is changed if any tag in the body does not exist, unless partial is set.")
+            .admin()
+            .body::<crate::post::routes::Tags>()
+            .no_content(),
+        put("/api/posts/{id}/tags", "Posts", "Replace all tags on a post.")
+            .describe("The body should contain some combination of \"names\" and \"ids\" for tags. All existing tags are replaced in a single transaction. An empty set will render the post tagless. Nothing is changed if any tag does not exist, unless partial is set.")
+            .admin()
+            .body::<crate::post::routes::Tags>()
+            .no_content(),
+        delete("/api/posts/{id}/tags/{tag_id}", "Posts", "Delete a tag from a post.")
+            .describe("Deletes the tag identified by the given ID.")
+            .admin()
+            .no_content(),
+        put("/api/posts/{id}/tags/{tag_id}", "Posts", "Add a tag to a post.")
+            .describe("Invalid tag id will return 404, invalid tag id will return 204.")
+            .admin()
+            .no_content(),
+        get("/api/roles", "Roles", "List the roles.")
+            .admin()
+            .bare::<Vec<crate::models::Role>>(),
+        post("/api/roles", "Roles", "Create a role.")
+            .describe("Answers with the number of roles created.")
+            .admin()
+            .body::<crate::models::Role>()
+            .bare::<usize>(),
+        get("/api/roles/{id}", "Roles", "Return a role in a list of one.")
+            .admin()
+            .bare::<Vec<crate::models::Role>>(),
+        patch("/api/roles/{id}", "Roles", "Rename a role.")
+            .describe("Answers with the number of roles changed.")
+            .admin()
+            .body::<crate::models::Role>()
+            .bare::<usize>(),
+        delete("/api/roles/{id}", "Roles", "Delete a role.")
+            .describe("Fails while users still have the role. Answers with the number of roles deleted.")
+            .admin()
+            .bare::<usize>(),
+        post("/api/roles/{id}", "Roles", "Rename a role.")
+            .describe("Answers with the number of roles changed.")
+            .admin()
+            .body::<crate::models::Role>()
+            .bare::<usize>(),
+        post("/api/roles/{id}/delete", "Roles", "Delete a role.")
+            .describe("Fails while users still have the role. Answers with the number of roles deleted.")
+            .admin()
+            .bare::<usize>(),
+        get("/api/series", "Series", "List the series.")
+            .returns::<Vec<crate::models::Series>>(),
+        post("/api/series", "Series", "Create a series.")
+            .describe("Admins only. Add the parts with PUT /series/{id}/posts.")
+            .admin()
+            .body::<crate::series::routes::NewSeries>()
+            .created(),
+        delete("/api/series/{id}", "Series", "Delete a series.")
+            .describe("The posts are kept, only the series and its order are removed.")
+            .admin(),
+        get("/api/series/{id}", "Series", "A series with its posts in reading order."),
+        patch("/api/series/{id}", "Series", "Change the title and description of a series.")
+            .admin()
+            .body::<crate::series::routes::NewSeries>(),
+        put("/api/series/{id}/posts", "Series", "Set the posts of a series.")
+            .describe("Replaces the parts of the series. The order of the list is the reading order. A post can only be part of one series, listing a post of another series is a 409.")
+            .admin()
+            .body::<crate::series::routes::SeriesPosts>(),
+        get("/api/tags", "Tags", "Return a list of post tags.")
+            .describe("Use the start and step parameters to get tags.")
+            .returns::<Vec<crate::models::Tag>>(),
+        post("/api/tags", "Tags", "Create a new tag.")
+            .describe("When valid fieds are passed in the request body, a new tag will be created. A 201 will contain the new tag id. Names ignore case and extra spaces, a name that already exists returns the existing tag.")
+            .session()
+            .body::<crate::tag::routes::NewTag>()
+            .created(),
+        delete("/api/tags/{id}", "Tags", "Delete a tag by ID")
+            .describe("Moves the tag identified by the given ID to the trash. It is hidden on its posts until it is restored. Once the trash is purged it is removed from posts and child tags move up to its parent.")
+            .session(),
+        post("/api/tags/bulk", "Tags", "Create, update and delete many tags.")
+            .describe("Operations are create, patch and delete. With mode atomic (the default) all of them are applied in one transaction or none are, a failure answers with the error of the first failed operation. With mode per_item each is applied on its own and the answer is a 207 with a result per operation.")
+            .session()
+            .body::<crate::bulk::Bulk<crate::tag::routes::TagOperation>>()
+            .returns::<Vec<crate::bulk::ItemResult>>(),
+        get("/api/tags/{id}", "Tags", "Retrieve an existing tag by its id.")
+            .describe("A valid id returns a single tag.")
+            .returns::<Vec<crate::models::Tag>>(),
+        patch("/api/tags/{id}", "Tags", "Update an existing tag by id.")
+            .describe("The name field can be altered. The old name stays an alias of the tag. A name already used by another tag is a 409, merge the tags instead.")
+            .session()
+            .body::<crate::tag::routes::NewTag>()
+            .no_content(),
+        get("/api/tags/{id}/ancestors", "Tags", "List the tags above a tag.")
+            .returns::<Vec<crate::models::Tag>>(),
+        get("/api/tags/{id}/children", "Tags", "List the tags directly below a tag.")
+            .returns::<Vec<crate::models::Tag>>(),
+        post("/api/tags/{id}/merge", "Tags", "Merge duplicate tags into this tag.")
+            .describe("Moves every post and owner of the source tags onto the target tag, then deletes the sources. All of it happens in one transaction.<br> The source names become aliases of the target, so filters such as ?eq[]=name=rust and tagging a post by name still find it.<br> Admins only.")
+            .admin()
+            .body::<crate::tag::routes::MergeTags>(),
+        put("/api/tags/{id}/parent", "Tags", "Move a tag and everything below it.")
+            .describe("Sets the parent of the tag. A null parent_id makes it a top level tag. Moving a tag below itself or one of its descendants is a 422.")
+            .session()
+            .body::<crate::tag::routes::MoveTag>()
+            .no_content(),
+        get("/api/tags/{id}/posts", "Tags", "Retrieve all the posts that are using the specified tag.")
+            .describe("A valid id will return a set of posts using the tag. Invalid ids will 404.")
+            .returns::<Vec<crate::post::routes::PostAndTags>>(),
+        get("/api/tags/stats", "Tags", "How often each tag is used.")
+            .describe("Every tag with the number of posts carrying it, the creation dates of its oldest and newest post and the ids of its owners.<br> With cloud=true only tags in use are returned, ordered by name, each with a weight from 1 to buckets for a tag cloud. Limit is applied first, so cloud=true&limit=30 is the 30 most used tags.")
+            .returns::<Vec<crate::tag::routes::TagStats>>(),
+        get("/api/trash", "Trash", "Deleted posts, tags and users.")
+            .describe("Admins only. Most recently deleted first. Items are purged for good once they are older than the configured retention, see purge_after.")
+            .admin(),
+        post("/api/trash/{kind}/{id}/restore", "Trash", "Take an item out of the trash.")
+            .describe("Admins only. A restored post or tag gets a new version. A tag cannot be restored while a live tag has its name.")
+            .admin(),
+        get("/api/users", "Users", "Return data about a user based on requester's session.")
+            .describe("Requires active session or returns 401.")
+            .session()
+            .returns::<crate::user::routes::UserWithoutPHC>(),
+        patch("/api/users", "Users", "Update your own user.")
+            .describe("Pass in the fields to change. The role cannot be changed this way.")
+            .session()
+            .body::<crate::user::routes::UpdateUserNoRole>()
+            .no_content(),
+        post("/api/users", "Users", "Create a new user.")
+            .describe("A new user will be created.")
+            .admin()
+            .body::<crate::user::routes::CreateNewUser>()
+            .created(),
+        delete("/api/users/{id}", "Users", "Delete a user by ID")
+            .describe("Moves the user identified by the given ID to the trash. They can no longer log in and can be restored until the trash is purged.")
+            .admin()
+            .no_content(),
+        get("/api/users/{id}", "Users", "Return a user by id.")
+            .admin()
+            .returns::<crate::user::routes::UserWithoutPHC>(),
+        patch("/api/users/{id}", "Users", "Update an existing user.")
+            .describe("Pass in any fields that you want to update.")
+            .admin()
+            .body::<crate::user::routes::UpdateUser>()
+            .no_content(),
+        post("/api/users/confirm_pw", "Users", "Check your password.")
+            .describe("A 200 when the password is the one of the logged in user, a 401 otherwise.")
+            .session()
+            .body::<crate::user::routes::ConfirmPW>()
+            .no_content(),
+        get("/api/users/list_of_all_users", "Users", "List the other users.")
+            .describe("Every user but the admin asking.")
+            .admin()
+            .returns::<Vec<crate::user::routes::UserWithoutPHC>>(),
+        delete("/api/users/session", "Users", "Delete your session")
+            .describe("Deletes your current session.")
+            .no_content(),
+        post("/api/users/session", "Users", "Create a new session.")
+            .describe("A cookie named jwt will be loaded into your browser.")
+            .body::<crate::user::routes::Login>()
+            .no_content(),
+        get("/api/webhooks", "Webhooks", "List webhooks.")
+            .describe("Admins only. Secrets are not shown.")
+            .admin(),
+        post("/api/webhooks", "Webhooks", "Subscribe a url to events.")
+            .describe("Admins only. Payloads are signed with HMAC-SHA256 of the body, sent in X-Webhook-Signature as sha256=<hex>. A secret is generated when none is sent. The answer is the only place the secret is shown.")
+            .admin()
+            .body::<crate::webhook::routes::NewWebhook>()
+            .created(),
+        delete("/api/webhooks/{id}", "Webhooks", "Delete a webhook.")
+            .describe("Admins only. Its deliveries go with it.")
+            .admin()
+            .no_content(),
+        get("/api/webhooks/{id}", "Webhooks", "Get a webhook.")
+            .describe("Admins only.")
+            .admin(),
+        patch("/api/webhooks/{id}", "Webhooks", "Change a webhook.")
+            .describe("Admins only. Send only what changes.")
+            .admin()
+            .body::<crate::webhook::routes::UpdateWebhook>(),
+        get("/api/webhooks/{id}/deliveries", "Webhooks", "Deliveries of a webhook.")
+            .describe("Admins only. Newest first.")
+            .admin(),
+        get("/api/webhooks/{id}/deliveries/{delivery_id}", "Webhooks", "One delivery with its attempts.")
+            .describe("Admins only. attempts_made lists every attempt, oldest first.")
+            .admin(),
+        post("/api/webhooks/{id}/deliveries/{delivery_id}/redeliver", "Webhooks", "Send a delivery again.")
+            .describe("Admins only. The delivery is queued with a fresh set of attempts. Earlier attempts are kept.")
+            .admin(),
+        post("/api/webhooks/{id}/ping", "Webhooks", "Queue a ping.")
+            .describe("Admins only. Sends a ping event to this webhook alone, for testing a receiver.")
+            .admin(),
+        post("/graphql", "GraphQL", "Run a GraphQL query or mutation.")
+            .describe("Posts, tags, users and roles with their relations in one request. Access follows the REST routes: posts, tags and users' names are public, the rest of a user is for admins and the user themselves, roles are for admins. Mutations need the same session or role as the matching REST write and take the post or tag version instead of If-Match. Queries deeper or more complex than [graphql] max_depth and max_complexity are refused. Errors carry the REST code and status under extensions. The schema can be explored at GET /graphql/playground.")
+            .graphql(),
+        get("/graphql/playground", "GraphQL", "An editor for GraphQL queries.")
+            .describe("Only served while playground is on in the graphql configuration.")
+            .content("text/html"),
+    ]
+}
+
+//What rocket has mounted for one method and path
+#[derive(Default)]
+pub(crate) struct Mounted {
+    pub(crate) query: BTreeSet<String>,
+    //The trailing <params..> of the query, a QParams
+    pub(crate) query_params: bool,
+    pub(crate) json_api: bool,
+    successor: Option<&'static str>,
+    //At least one of the routes is also mounted on v2
+    on_v2: bool,
+}
+
+impl Mounted {
+    //What replaces the method and path on v2 when none of its routes are mounted there, see version.rs
+    pub(crate) fn v1_only(&self) -> Option<&'static str> {
+        match self.on_v2 {
+            true => None,
+            false => self.successor,
+        }
+    }
+}
+
+//"/api/posts/<id>/tags/<tag_id>" -> "/api/posts/{id}/tags/{tag_id}"
+fn template(path: &str) -> String {
+    let segments: Vec<String> = path.split('/')
+        .filter(|s| !s.is_empty())
+        .map(|s| match s.strip_prefix('<').and_then(|s| s.strip_suffix('>')) {
+            Some(name) => format!("{{{}}}", name.trim_end_matches("..").trim_start_matches('_')),
+            None => s.to_string(),
+        })
+        .collect();
+    format!("/{}", segments.join("/"))
+}
+
+//Keyed by method and OpenAPI path
+pub(crate) fn mounted(rocket: &Rocket<Build>) -> BTreeMap<(String, String), Mounted> {
+    let mut found: BTreeMap<(String, String), Mounted> = BTreeMap::new();
+    for route in rocket.routes() {
+        let path = route.uri.path().as_str().to_string();
+        //The /api/v1 and /api/v2 mounts are the same handlers as /api
+        if !(path.starts_with("/api") || path.starts_with("/graphql")) || ApiVersion::of_path(&path).is_some() {
+            continue;
+        }
+        let entry = found.entry((route.method.as_str().to_string(), template(&path))).or_default();
+        for field in route.uri.query().map(|q| q.as_str()).unwrap_or("").split('&').filter(|f| !f.is_empty()) {
+            let name = field.trim_start_matches('<').trim_end_matches('>');
+            match name.ends_with("..") {
+                true => entry.query_params = true,
+                false => { entry.query.insert(name.to_string()); }
+            }
+        }
+        match version::successor(route) {
+            Some(successor) => entry.successor = Some(successor),
+            None => entry.on_v2 = true,
+        }
+        if route.format.as_ref().map(|f| f.to_string()) == Some(String::from("application/vnd.api+json")) {
+            entry.json_api = true;
+        }
+    }
+    found
+}
+
+fn param_schema(name: &str) -> Value {
+    match name {
+        "id" | "actor" | "buckets" | "limit" | "start" | "step" => json!({"type": "integer"}),
+        "cloud" | "create" | "descendants" | "partial" | "purge_media" | "text" => json!({"type": "boolean"}),
+        n if n.ends_with("_id") => json!({"type": "integer"}),
+        _ => json!({"type": "string"}),
+    }
+}
+
+fn parameters(path: &str, mounted: &Mounted) -> Vec<Value> {
+    let mut parameters: Vec<Value> = path.split('/')
+        .filter_map(|s| s.strip_prefix('{').and_then(|s| s.strip_suffix('}')))
+        .map(|name| json!({"name": name, "in": "path", "required": true, "schema": param_schema(name)}))
+        .collect();
+    for name in &mounted.query {
+        parameters.push(json!({"name": name, "in": "query", "required": false, "schema": param_schema(name)}));
+    }
+    if mounted.query_params {
+        for name in ["start", "step"] {
+            parameters.push(json!({"name": name, "in": "query", "required": false, "schema": param_schema(name)}));
+        }
+        parameters.push(json!({
+            "name": "order", "in": "query", "required": false, "style": "form", "explode": true,
+            "description": "Column to sort by, prefix with - for descending.",
+            "schema": {"type": "array", "items": {"type": "string"}}
+        }));
+        for (op, description) in [
+            ("eq", "column=value. Repeated values of a column are OR'ed."),
+            ("like", "column=value, % is the wildcard."),
+            ("ge", "column=value, greater than or equal to."),
+            ("le", "column=value, less than or equal to."),
+            ("between", "column=low,high"),
+        ] {
+            parameters.push(json!({
+                "name": format!("filter.{}", op), "in": "query", "required": false, "style": "form", "explode": true,
+                "description": description,
+                "schema": {"type": "array", "items": {"type": "string"}}
+            }));
+        }
+    }
+    parameters
+}
+
+fn operation(doc: &Doc, mounted: &Mounted, gen: &mut SchemaGenerator) -> Value {
+    let mut op = json!({
+        "tags": [doc.tag],
+        "summary": doc.summary,
+        "operationId": format!("{} {}", doc.method.as_str().to_lowercase(), doc.path),
+    });
+    if !doc.description.is_empty() {
+        op["description"] = json!(doc.description);
+    }
+    if let Some(successor) = mounted.v1_only() {
+        op["deprecated"] = json!(true);
+        op["description"] = json!(format!("{} Not on /api/v2, use {}.", doc.description, successor).trim_start());
+    }
+    let parameters = parameters(doc.path, mounted);
+    if !parameters.is_empty() {
+        op["parameters"] = json!(parameters);
+    }
+    if let Some((media_type, schema)) = doc.body {
+        let mut content = Map::new();
+        content.insert(media_type.to_string(), json!({"schema": schema(gen)}));
+        if let (true, Some(write_document)) = (mounted.json_api, doc.write_document) {
+            content.insert(String::from("application/vnd.api+json"), json!({"schema": write_document(gen)}));
+        }
+        op["requestBody"] = json!({"required": true, "content": content});
+    }
+
+    let mut content = Map::new();
+    match doc.answer {
+        Answer::Envelope(data) => {
+            let envelope = schema_of::<crate::models::AResponse>(gen);
+            let schema = match data {
+                Some(data) => json!({"allOf": [envelope, {"type": "object", "properties": {"data": data(gen)}}]}),
+                None => json!(envelope),
+            };
+            content.insert(String::from("application/json"), json!({"schema": schema}));
+        },
+        Answer::Bare(schema) => { content.insert(String::from("application/json"), json!({"schema": schema(gen)})); },
+        Answer::Content(media_type) => { content.insert(media_type.to_string(), json!({})); },
+        Answer::Empty => (),
+    }
+    if mounted.json_api && !content.is_empty() {
+        content.insert(String::from("application/vnd.api+json"), json!({}));
+    }
+    let mut success = json!({"description": "Success"});
+    if !content.is_empty() {
+        success["content"] = Value::Object(content);
+    }
+    let problem = schema_of::<Problem>(gen);
+    op["responses"] = json!({
+        (doc.status.to_string()): success,
+        "default": {"description": "Error", "content": {"application/problem+json": {"schema": problem}}},
+    });
+    match doc.access {
+        Access::Public => (),
+        Access::Session => op["security"] = json!([{"CookieJWT": []}]),
+        Access::Admin => {
+            op["security"] = json!([{"CookieJWT": []}]);
+            op["x-admin"] = json!(true);
+        },
+    }
+    op
+}
+
+//Access ("public", "session" or "admin") and summary of every documented route, keyed like mounted()
+pub(crate) fn access() -> BTreeMap<(String, String), (&'static str, &'static str)> {
+    docs().into_iter()
+        .map(|d| ((d.method.as_str().to_string(), d.path.to_string()), (d.access.as_str(), d.summary)))
+        .collect()
+}
+
+//Every mounted route without an entry and every entry without a mounted route
+fn disagreements(docs: &[Doc], mounted: &BTreeMap<(String, String), Mounted>) -> Vec<String> {
+    let documented: BTreeSet<(String, String)> = docs.iter().map(|d| (d.method.as_str().to_string(), d.path.to_string())).collect();
+    let mut problems: Vec<String> = mounted.keys()
+        .filter(|k| !documented.contains(*k))
+        .map(|(m, p)| format!("{} {} is mounted but not documented", m, p))
+        .collect();
+    problems.extend(documented.iter()
+        .filter(|k| !mounted.contains_key(*k))
+        .map(|(m, p)| format!("{} {} is documented but not mounted", m, p)));
+    problems
+}
+
+fn document(docs: &[Doc], mounted: &BTreeMap<(String, String), Mounted>) -> Value {
+    let mut gen = SchemaSettings::openapi3().into_generator();
+    let mut paths: BTreeMap<&str, Map<String, Value>> = BTreeMap::new();
+    let mut tags: BTreeSet<&str> = BTreeSet::new();
+    for doc in docs {
+        let Some(found) = mounted.get(&(doc.method.as_str().to_string(), doc.path.to_string())) else { continue };
+        tags.insert(doc.tag);
+        let op = operation(doc, found, &mut gen);
+        paths.entry(doc.path).or_default().insert(doc.method.as_str().to_lowercase(), op);
+    }
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "DennisMarwood.com",
+            "description": "CRUD post entries for homepage blog.<br> Paths are written as v1, /api/v1/... is the same. For v2 use /api/v2/... or send Accept: application/vnd.blog.v2+json. Deprecated operations are not on v2.<br> Any POST with a json or multipart body, or a body of at most 512 bytes, may send an Idempotency-Key header, a retry with the same key gets the first answer back with Idempotent-Replayed: true, a different request under the key a 409. A key on POST /api/users/session is ignored.",
+            "contact": {"name": "Dennis Marwood", "url": "https://dennismarwood.com/contact", "email": "dennismarwood@gmail.com"},
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "servers": [{"url": "/"}],
+        "tags": tags.into_iter().map(|t| json!({"name": t})).collect::<Vec<Value>>(),
+        "paths": paths,
+        "components": {
+            "schemas": gen.take_definitions(),
+            "securitySchemes": {"CookieJWT": {"type": "apiKey", "in": "cookie", "name": "jwt"}},
+        },
+    })
+}
+
+pub struct OpenApi(String);
+
+pub async fn attach(rocket: Rocket<Build>) -> fairing::Result {
+    let docs = docs();
+    let mounted = mounted(&rocket);
+    let problems = disagreements(&docs, &mounted);
+    for problem in &problems {
+        error!("OpenAPI: {}, see openapi.rs", problem);
+    }
+    if !problems.is_empty() {
+        return Err(rocket);
+    }
+    let document = document(&docs, &mounted).to_string();
+    Ok(rocket.manage(OpenApi(document)))
+}
+
+pub mod routes {
+    use super::*;
+    use rocket::State;
+    use rocket::response::content::RawJson;
+
+    #[get("/openapi.json")]
+    pub fn openapi_json(document: &State<OpenApi>) -> RawJson<String> {
+        RawJson(document.0.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn routes_and_docs_agree() {
+        let rocket = crate::rocket();
+        let problems = disagreements(&docs(), &mounted(&rocket));
+        assert!(problems.is_empty(), "{}", problems.join("\n"));
+    }
+}
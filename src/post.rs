@@ -1,24 +1,36 @@
+use std::collections::{HashMap, HashSet};
+use crate::cache::{Cached, IfMatch};
 use crate::config::DbConn;
-use crate::schema::{post, tag};
-use crate::models::{BlogEntry, AResponse, QParams, Filters, BlogTags, Tag};
+use crate::error::ApiError;
+use crate::idempotency::Keyed;
+use crate::schema::{post, post_tags, tag, user};
+use crate::models::{BlogEntry, AResponse, EnvVariables, QParams, Filters, BlogTags, Tag};
+use crate::post_tags::{AttachOptions, TagOutcome, TagRef};
+use crate::myjsonapi::{Document, Fieldsets, Includes, JsonApiRequest, ResourceObject, WriteDocument};
 use diesel::prelude::*;
+use diesel::dsl::sql;
 use diesel::mysql::Mysql;
-use diesel::result::DatabaseErrorKind::{UniqueViolation, NotNullViolation };
-use diesel::result::Error::{DatabaseError, QueryBuilderError};
-use rocket::http::{Status};
+use diesel::sql_types::{Date, Nullable, Text, Timestamp};
+use rocket::http::Status;
 use rocket::response::status;
-use rocket::serde::json::{Json, json};
+use rocket::serde::json::{Json, Value, json};
+use rocket::State;
 
 pub mod routes {
     use crate::{auth::{Level1, ValidSession, StandardUser, AdminUser}, jwt::get_jwt};
     //pub async fn new_post<'a>(conn: DbConn, new_entry: Json<NewBlogEntryWithTags>, _x: Level1)
 
     use super::*;
+    use crate::audit::{Resource, Trail};
+    use crate::bulk::{admin_only, Bulk, Done, Outcome};
 
-    #[derive(serde::Serialize, serde::Deserialize)]
+    #[derive(serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
     pub struct PostAndTags {
         post: BlogEntry,
         tags: Vec<Tag>,
+        //Previous and next part when the post belongs to a series. Only filled in for a single post.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        series: Option<crate::series::Navigation>,
     }
 
     pub enum PostFields {
@@ -30,7 +42,7 @@ pub mod routes {
         Content(String,)
     }
 
-    #[derive(Debug, serde::Deserialize, Insertable)]
+    #[derive(Debug, serde::Deserialize, Insertable, schemars::JsonSchema)]
     #[diesel(table_name = post)]
     pub struct NewPost {
         pub title: String,
@@ -51,6 +63,16 @@ pub mod routes {
         pub content: Option<String>,
     }
 
+    //JSON:API patch. Only the attributes that are sent are changed.
+    #[derive(Debug, serde::Deserialize, AsChangeset, schemars::JsonSchema)]
+    #[diesel(table_name = post)]
+    pub struct PatchPost {
+        pub title: Option<String>,
+        pub content: Option<String>,
+        #[serde(skip)]
+        pub last_updated: Option<chrono::NaiveDate>,
+    }
+
     #[derive(Debug, serde::Serialize, serde::Deserialize, Queryable, Clone)]
     struct NewBlogEntryWithTags {
         pub title: String,
@@ -60,12 +82,16 @@ pub mod routes {
     }
 
     // TODO: Rename this, sounds like a collection of Tags not a collection of ids and names that can be used to retrieve Tags.
-    #[derive(serde::Serialize, serde::Deserialize)]
+    #[derive(serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
     pub struct Tags {
         id: Option<Vec<i32>>,
         name: Option<Vec<String>>,
     }
 
+    //Columns validation() and the order loop accept, listed by the discovery document at GET /api
+    pub const FILTER_COLUMNS: [&str; 6] = ["id", "title", "author", "content", "created", "lastupdated"];
+    pub const ORDER_COLUMNS: [&str; 5] = ["id", "title", "author", "created", "lastupdated"];
+
     fn validation(qp: String) -> Option<PostFields> {
         // Verify that the query parameter is valid in format
         if let Some((k, v)) = qp.split_once('=') {
@@ -96,142 +122,139 @@ pub mod routes {
         } else {None}
     }
 
-    async fn parse_and_query(params: QParams, conn: &DbConn) -> QueryResult<Vec<BlogEntry>> {
-        //https://docs.diesel.rs/2.0.x/diesel/prelude/trait.QueryDsl.html#method.filter
+    pub async fn parse_and_query(params: QParams, conn: &DbConn) -> QueryResult<Vec<BlogEntry>> {
         conn.run(move |c| {
+            build_query(params).load::<BlogEntry>(c)
+        }).await 
+    }
 
-            let mut query = post::table.into_boxed::<Mysql>();
-
-            for f in params.filter.eq {
-                if let Some(query_parameter) = validation(f){
-                    match query_parameter {
-                        PostFields::Id(id) => query = query.or_filter(post::id.eq(id)),
-                        PostFields::Title(title) => query = query.or_filter(post::title.eq(title)),
-                        PostFields::Author(author) => query = query.or_filter(post::author.eq(author)),
-                        PostFields::Created(created) => query = query.or_filter(post::created.eq(created)),
-                        PostFields::LastUpdated(lu) => query = query.or_filter(post::last_updated.eq(lu)),
-                        PostFields::Content(content) => query = query.or_filter(post::content.eq(content)),
-                    }
+    fn build_query(params: QParams) -> post::BoxedQuery<'static, Mysql> {
+        //https://docs.diesel.rs/2.0.x/diesel/prelude/trait.QueryDsl.html#method.filter
+        let mut query = post::table.into_boxed::<Mysql>();
+
+        for f in params.filter.eq {
+            if let Some(query_parameter) = validation(f){
+                match query_parameter {
+                    PostFields::Id(id) => query = query.or_filter(post::id.eq(id)),
+                    PostFields::Title(title) => query = query.or_filter(post::title.eq(title)),
+                    PostFields::Author(author) => query = query.or_filter(post::author.eq(author)),
+                    PostFields::Created(created) => query = query.or_filter(post::created.eq(created)),
+                    PostFields::LastUpdated(lu) => query = query.or_filter(post::last_updated.eq(lu)),
+                    PostFields::Content(content) => query = query.or_filter(post::content.eq(content)),
                 }
             }
+        }
 
-            for f in params.filter.ge {
-                if let Some(query_parameter) = validation(f){
-                    match query_parameter {
-                        PostFields::Id(id) => query = query.or_filter(post::id.ge(id)),
-                        PostFields::Title(title) => query = query.or_filter(post::title.ge(title)),
-                        PostFields::Author(author) => query = query.or_filter(post::author.ge(author)),
-                        PostFields::Created(created) => query = query.or_filter(post::created.ge(created)),
-                        PostFields::LastUpdated(lu) => query = query.or_filter(post::last_updated.ge(lu)),
-                        PostFields::Content(content) => query = query.or_filter(post::content.ge(content)),
-                    }
+        for f in params.filter.ge {
+            if let Some(query_parameter) = validation(f){
+                match query_parameter {
+                    PostFields::Id(id) => query = query.or_filter(post::id.ge(id)),
+                    PostFields::Title(title) => query = query.or_filter(post::title.ge(title)),
+                    PostFields::Author(author) => query = query.or_filter(post::author.ge(author)),
+                    PostFields::Created(created) => query = query.or_filter(post::created.ge(created)),
+                    PostFields::LastUpdated(lu) => query = query.or_filter(post::last_updated.ge(lu)),
+                    PostFields::Content(content) => query = query.or_filter(post::content.ge(content)),
                 }
             }
+        }
 
-            for f in params.filter.le {
-                if let Some(query_parameter) = validation(f){
-                    match query_parameter {
-                        PostFields::Id(id) => query = query.or_filter(post::id.le(id)),
-                        PostFields::Title(title) => query = query.or_filter(post::title.le(title)),
-                        PostFields::Author(author) => query = query.or_filter(post::author.le(author)),
-                        PostFields::Created(created) => query = query.or_filter(post::created.le(created)),
-                        PostFields::LastUpdated(lu) => query = query.or_filter(post::last_updated.le(lu)),
-                        PostFields::Content(content) => query = query.or_filter(post::content.le(content)),
-                    }
+        for f in params.filter.le {
+            if let Some(query_parameter) = validation(f){
+                match query_parameter {
+                    PostFields::Id(id) => query = query.or_filter(post::id.le(id)),
+                    PostFields::Title(title) => query = query.or_filter(post::title.le(title)),
+                    PostFields::Author(author) => query = query.or_filter(post::author.le(author)),
+                    PostFields::Created(created) => query = query.or_filter(post::created.le(created)),
+                    PostFields::LastUpdated(lu) => query = query.or_filter(post::last_updated.le(lu)),
+                    PostFields::Content(content) => query = query.or_filter(post::content.le(content)),
                 }
             }
+        }
 
-            for f in params.filter.like {
-                if let Some(query_parameter) = validation(f){
-                    match query_parameter {
-                        PostFields::Title(title) => query = query.or_filter(post::title.like(title)),
-                        PostFields::Author(author) => query = query.or_filter(post::author.like(author)),
-                        PostFields::Content(content) => query = query.or_filter(post::content.like(content)),
-                        _ => {},
-                    }
+        for f in params.filter.like {
+            if let Some(query_parameter) = validation(f){
+                match query_parameter {
+                    PostFields::Title(title) => query = query.or_filter(post::title.like(title)),
+                    PostFields::Author(author) => query = query.or_filter(post::author.like(author)),
+                    PostFields::Content(content) => query = query.or_filter(post::content.like(content)),
+                    _ => {},
                 }
             }
+        }
 
-            for b in &params.filter.between {
-                if let Some((k, v)) = b.split_once('=') {
-                    if let Some((l, r)) = v.split_once(',') {
-                        match k.to_lowercase().as_str() {
-                            "id" => if let (Ok(l), Ok(r)) = (l.parse::<i32>(), r.parse::<i32>()) {
-                                        query = query.or_filter(post::id.between(l, r));
+        for b in &params.filter.between {
+            if let Some((k, v)) = b.split_once('=') {
+                if let Some((l, r)) = v.split_once(',') {
+                    match k.to_lowercase().as_str() {
+                        "id" => if let (Ok(l), Ok(r)) = (l.parse::<i32>(), r.parse::<i32>()) {
+                                    query = query.or_filter(post::id.between(l, r));
+                                }
+                        "title" => query = query.or_filter(post::title.between(l, r)),
+                        "created" => if let (Ok(l), Ok(r)) = (
+                                        chrono::NaiveDate::parse_from_str(l, "%Y-%m-%d"), 
+                                        chrono::NaiveDate::parse_from_str(r, "%Y-%m-%d"),
+                                    )
+                                    {
+                                        query = query.or_filter(
+                                            post::created.between(
+                                                l.and_hms_opt(0, 0, 0).unwrap_or_default(), 
+                                                r.and_hms_opt(0, 0, 0).unwrap_or_default()
+                                            )
+                                        );
                                     }
-                            "title" => query = query.or_filter(post::title.between(l, r)),
-                            "created" => if let (Ok(l), Ok(r)) = (
-                                            chrono::NaiveDate::parse_from_str(l, "%Y-%m-%d"), 
-                                            chrono::NaiveDate::parse_from_str(r, "%Y-%m-%d"),
-                                        )
-                                        {
-                                            query = query.or_filter(
-                                                post::created.between(
-                                                    l.and_hms_opt(0, 0, 0).unwrap_or_default(), 
-                                                    r.and_hms_opt(0, 0, 0).unwrap_or_default()
-                                                )
-                                            );
-                                        }
-                            "lastupdated" => if let (Ok(l), Ok(r)) = (
-                                            chrono::NaiveDate::parse_from_str(l, "%Y-%m-%d"), 
-                                            chrono::NaiveDate::parse_from_str(r, "%Y-%m-%d"),
-                                        )
-                                        {
-                                            query = query.or_filter(post::last_updated.between(l,r));
-                                        } 
-                            _ => (),
-                        }
+                        "lastupdated" => if let (Ok(l), Ok(r)) = (
+                                        chrono::NaiveDate::parse_from_str(l, "%Y-%m-%d"), 
+                                        chrono::NaiveDate::parse_from_str(r, "%Y-%m-%d"),
+                                    )
+                                    {
+                                        query = query.or_filter(post::last_updated.between(l,r));
+                                    } 
+                        _ => (),
                     }
                 }
             }
+        }
 
-            for o in params.order {
-                match o.to_lowercase().as_str() {
-                    "id" => query = query.then_order_by(post::id.asc()),
-                    "-id" => query = query.then_order_by(post::id.desc()),
-                    "title" => query = query.then_order_by(post::title.asc()),
-                    "-title" => query = query.then_order_by(post::title.desc()),
-                    "author" => query = query.then_order_by(post::author.asc()),
-                    "-author" => query = query.then_order_by(post::author.desc()),
-                    "created" => query = query.then_order_by(post::created.asc()),
-                    "-created" => query = query.then_order_by(post::created.desc()),
-                    "lastupdated" => query = query.then_order_by(post::last_updated.asc()),
-                    "-lastupdated" => query = query.then_order_by(post::last_updated.desc()),
-                    _ => {},
-                }
-            }
+        //After the filters above, they are or'ed together and this has to hold for all of them
+        query = query.filter(post::deleted_at.is_null());
 
-            //page indexing
-            let start: i64 = params.start.unwrap_or(0);
-            let step: i64 = params.step.unwrap_or(10);
-            query = query.limit(step);
-            query = query.offset(start);
-            query.load::<BlogEntry>(c)
+        for o in params.order {
+            match o.to_lowercase().as_str() {
+                "id" => query = query.then_order_by(post::id.asc()),
+                "-id" => query = query.then_order_by(post::id.desc()),
+                "title" => query = query.then_order_by(post::title.asc()),
+                "-title" => query = query.then_order_by(post::title.desc()),
+                "author" => query = query.then_order_by(post::author.asc()),
+                "-author" => query = query.then_order_by(post::author.desc()),
+                "created" => query = query.then_order_by(post::created.asc()),
+                "-created" => query = query.then_order_by(post::created.desc()),
+                "lastupdated" => query = query.then_order_by(post::last_updated.asc()),
+                "-lastupdated" => query = query.then_order_by(post::last_updated.desc()),
+                _ => {},
+            }
+        }
 
-        }).await 
+        //page indexing
+        let start: i64 = params.start.unwrap_or(0).max(0);
+        let step: i64 = params.step.unwrap_or(10).clamp(0, QParams::MAX_STEP);
+        query = query.limit(step);
+        query = query.offset(start);
+        query
     }
 
-    pub async fn post_and_tags(params: QParams, conn: &DbConn) -> Result<Vec<PostAndTags>, status::Custom<Json<AResponse>>> {
+    pub async fn post_and_tags(params: QParams, conn: &DbConn) -> Result<Vec<PostAndTags>, ApiError> {
         //Given a vec of BlogEntry structs retrieve tags on each of the posts
         //https://diesel.rs/guides/relations.html#many-to-many-or-mn
         //https://docs.rs/diesel/latest/diesel/prelude/trait.QueryDsl.html#method.group_by
 
-        let target_posts = match parse_and_query(params, &conn).await {
-            Ok(posts) => posts,
-            Err(e) => 
-                return Err(status::Custom(Status::InternalServerError, Json(AResponse::error(Some(json!([{"message":  format!("{:?}",e) }])))))),
-        };
+        let target_posts = parse_and_query(params, &conn).await?;
 
-        conn.run(move |c| {
-            let tags: Vec<(BlogTags, Tag)> = match BlogTags::belonging_to(&target_posts) 
+        conn.run(move |c| -> Result<Vec<PostAndTags>, ApiError> {
+            let tags: Vec<(BlogTags, Tag)> = BlogTags::belonging_to(&target_posts) 
                 .inner_join(tag::table)
+                .filter(tag::deleted_at.is_null())
                 .select(  (BlogTags::as_select(), Tag::as_select()) )
-                .load(c)
-                {
-                    Ok(tags) => tags,
-                    Err(e) => 
-                        return Err(status::Custom(Status::InternalServerError, Json(AResponse::error(Some(json!([{"message":  format!("{:?}",e) }])))))),
-                };
+                .load(c)?;
 
             let posts_and_their_tags: Vec<(BlogEntry, Vec<Tag>)> = tags
                 .grouped_by(&target_posts) // A vec of vec of tuples of BlogTags and Tag. They are indexed to target_posts
@@ -245,28 +268,246 @@ pub mod routes {
             //Clean up the json format a bit
             let mut result: Vec<PostAndTags> = Vec::with_capacity(10);
             for (p, t) in posts_and_their_tags {
-                result.push(PostAndTags { post: p, tags: t });
+                result.push(PostAndTags { post: p, tags: t, series: None });
             };
             Ok(result)
         }).await
     }
 
-    async fn get_a_post_id( conn: &DbConn, title: &String, author: &String) -> Option<i32> {
-        //mysql does not return an id after creating a new entry. This helper function does only that.
-        let (title, author) = (title.clone(), author.clone());
-        match conn.run(  |c| {
-            post::table
-                .filter(post::author.eq(author))
-                .filter(post::title.eq(title))
-                .select(post::id)
-                .first::<i32>(c)
-        }).await {
-            Ok(id) => Some(id),
-            Err(_) => None,
+    //Post listings with ?fields= and/or ?include=. Only the requested columns are read from the db.
+    const LISTING_FIELDS: [&str; 6] = ["id", "title", "author", "created", "last_updated", "content"];
+    const LISTING_INCLUDES: [&str; 3] = ["tags", "author", "excerpt"];
+    const EXCERPT_CHARS: usize = 280;
+    //Enough of the content to build an excerpt from, even after markup is stripped.
+    const EXCERPT_SOURCE_SQL: &str = "LEFT(post.content, 1000)";
+
+    pub struct Listing {
+        fields: HashSet<String>,
+        tags: bool,
+        author: bool,
+        excerpt: bool,
+    }
+
+    impl Listing {
+        //None when the client asked for neither, the original {post, tags} format is used then.
+        pub fn parse(fields: Option<String>, include: Option<String>) -> Result<Option<Self>, ApiError> {
+            if fields.is_none() && include.is_none() {
+                return Ok(None);
+            }
+
+            let fields = match fields {
+                Some(f) => split_list(&f, &LISTING_FIELDS, "fields")?,
+                None => LISTING_FIELDS.iter().map(|f| f.to_string()).collect(),
+            };
+            let include = split_list(&include.unwrap_or_default(), &LISTING_INCLUDES, "include")?;
+
+            Ok(Some(Listing {
+                fields,
+                tags: include.contains("tags"),
+                author: include.contains("author"),
+                excerpt: include.contains("excerpt"),
+            }))
+        }
+
+        fn column(&self, field: &str) -> &'static str {
+            match (self.fields.contains(field), field) {
+                (false, _) => "NULL",
+                (true, "title") => "post.title",
+                (true, "author") => "post.author",
+                (true, "created") => "post.created",
+                (true, "last_updated") => "post.last_updated",
+                (true, _) => "post.content",
+            }
+        }
+
+        fn content_column(&self) -> &'static str {
+            match (self.fields.contains("content"), self.excerpt) {
+                (false, true) => EXCERPT_SOURCE_SQL,
+                _ => self.column("content"),
+            }
+        }
+    }
+
+    fn split_list(list: &str, allowed: &[&str], param: &str) -> Result<HashSet<String>, ApiError> {
+        let mut values = HashSet::new();
+        for v in list.split(',').map(|v| v.trim().to_lowercase()).filter(|v| !v.is_empty()) {
+            if !allowed.contains(&v.as_str()) {
+                return Err(ApiError::bad_request(Some(format!("'{}' is not a valid {} value. Use any of: {}.", v, param, allowed.join(",")))));
+            }
+            values.insert(v);
+        }
+        Ok(values)
+    }
+
+    #[derive(serde::Serialize)]
+    pub struct PostListing {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        id: Option<i32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        title: Option<String>,
+        //The author column, or the author's id and name when include=author.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        author: Option<Value>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        created: Option<chrono::NaiveDateTime>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        last_updated: Option<chrono::NaiveDate>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        content: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        excerpt: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        tags: Option<Vec<Tag>>,
+    }
+
+    //Plain text preview of a post. Markup is dropped and the text is cut on a word boundary.
+    fn excerpt(content: &str) -> String {
+        let mut text = String::with_capacity(content.len());
+        let mut in_tag = false;
+        for ch in content.chars() {
+            match ch {
+                '<' => in_tag = true,
+                '>' if in_tag => {
+                    in_tag = false;
+                    text.push(' ');
+                },
+                _ if !in_tag => text.push(ch),
+                _ => {},
+            }
+        }
+
+        let mut result = String::new();
+        let mut len = 0;
+        for word in text.split_whitespace().map(|w| w.trim_matches(|c| matches!(c, '#' | '*' | '_' | '`'))).filter(|w| !w.is_empty()) {
+            let word_len = word.chars().count();
+            if len + word_len + 1 > EXCERPT_CHARS {
+                result.push('…');
+                break;
+            }
+            if len > 0 {
+                result.push(' ');
+                len += 1;
+            }
+            result.push_str(word);
+            len += word_len;
         }
+        result
     }
 
-    fn validate_user_input(p: &NewPost) -> Result<(), status::Custom<Json<AResponse>>> {
+    pub async fn post_listing(params: QParams, listing: &Listing, conn: &DbConn) -> Result<Vec<PostListing>, ApiError> {
+        let select = (
+            post::id,
+            sql::<Nullable<Text>>(listing.column("title")),
+            sql::<Nullable<Text>>(listing.column("author")),
+            sql::<Nullable<Timestamp>>(listing.column("created")),
+            sql::<Nullable<Date>>(listing.column("last_updated")),
+            sql::<Nullable<Text>>(listing.content_column()),
+        );
+        let rows = conn.run(move |c| {
+            build_query(params)
+                .select(select)
+                .load::<(i32, Option<String>, Option<String>, Option<chrono::NaiveDateTime>, Option<chrono::NaiveDate>, Option<String>)>(c)
+        }).await?;
+
+        let ids = rows.iter().map(|r| r.0).collect::<Vec<i32>>();
+
+        let mut tags_of: HashMap<i32, Vec<Tag>> = HashMap::new();
+        if listing.tags {
+            let post_ids = ids.clone();
+            let tags = conn.run(move |c| {
+                post_tags::table
+                    .inner_join(tag::table)
+                    .filter(post_tags::post_id.eq_any(post_ids))
+                    .filter(tag::deleted_at.is_null())
+                    .select((post_tags::post_id, Tag::as_select()))
+                    .load::<(i32, Tag)>(c)
+            }).await?;
+            for (post_id, t) in tags {
+                tags_of.entry(post_id).or_default().push(t);
+            }
+        }
+
+        //Authors are stored as the user id, look their names up when asked to.
+        let mut author_of: HashMap<i32, Value> = HashMap::new();
+        if listing.author {
+            let post_ids = ids.clone();
+            let post_authors = conn.run(move |c| {
+                post::table
+                    .filter(post::id.eq_any(post_ids))
+                    .select((post::id, post::author))
+                    .load::<(i32, String)>(c)
+            }).await?;
+
+            let author_ids = post_authors.iter().filter_map(|(_, a)| a.parse::<i32>().ok()).collect::<HashSet<i32>>();
+            let names = author_names(conn, author_ids.into_iter().collect()).await?
+                .into_iter()
+                .map(|(id, first_name, last_name)| (id, json!({"id": id, "first_name": first_name, "last_name": last_name})))
+                .collect::<HashMap<i32, Value>>();
+
+            for (post_id, author) in post_authors {
+                let embedded = author.parse::<i32>().ok()
+                    .and_then(|a| names.get(&a).cloned())
+                    .unwrap_or(Value::String(author));
+                author_of.insert(post_id, embedded);
+            }
+        }
+
+        let result = rows.into_iter().map(|(id, title, author, created, last_updated, content)| {
+            let excerpt = match listing.excerpt {
+                true => Some(excerpt(content.as_deref().unwrap_or_default())),
+                false => None,
+            };
+            let author = match listing.author {
+                true => author_of.remove(&id),
+                false => author.map(Value::String),
+            };
+            PostListing {
+                id: listing.fields.contains("id").then_some(id),
+                title,
+                author,
+                created,
+                last_updated,
+                content: content.filter(|_| listing.fields.contains("content")),
+                excerpt,
+                tags: listing.tags.then(|| tags_of.remove(&id).unwrap_or_default()),
+            }
+        }).collect::<Vec<PostListing>>();
+
+        Ok(result)
+    }
+
+    //Check If-Match against the stored version and take the next one. Only one writer can claim a given version.
+    //Changing a post's tags is a write to the post as well. Call it inside the transaction that makes the change.
+    fn claim_version(c: &mut MysqlConnection, id: i32, if_match: &IfMatch) -> Result<(), ApiError> {
+        let current = post::table.filter(post::id.eq(id)).filter(post::deleted_at.is_null()).select(post::version).first::<i32>(c)
+            .map_err(|e| match e {
+                diesel::result::Error::NotFound => ApiError::not_found(Some(String::from("Could not locate post with provided id."))),
+                e => ApiError::from(e),
+            })?;
+        if_match.check(current)?;
+
+        let rows = diesel::update(post::table.filter(post::id.eq(id)).filter(post::version.eq(current)))
+            .set(post::version.eq(post::version + 1))
+            .execute(c)?;
+        match rows {
+            1 => Ok(()),
+            //Someone else wrote between the read and the update
+            _ => Err(ApiError::precondition_failed(current + 1)),
+        }
+    }
+
+    fn get_a_post_id(c: &mut MysqlConnection, title: String, author: String) -> Option<i32> {
+        //mysql does not return an id after creating a new entry. This helper function does only that.
+        post::table
+            .filter(post::author.eq(author))
+            .filter(post::title.eq(title))
+            .filter(post::deleted_at.is_null())
+            .select(post::id)
+            .first::<i32>(c)
+            .ok()
+    }
+
+    fn validate_user_input(p: &NewPost) -> Result<(), ApiError> {
         //Diesel does not have an error code for invalid input. Manually check.
         let mut messages = Vec::new();
         if !(1..=100).contains(&p.title.len()) {
@@ -279,282 +520,593 @@ pub mod routes {
         match messages.len() 
         {
             0 => Ok(()),
-            _ => Err(status::Custom(Status::UnprocessableEntity, Json(AResponse::_422(
-            Some(String::from("Correct input and try again.")),
-                    Some(String::from("INVALID_INPUT")),
-            Some(json!(messages))))))
-        
+            _ => Err(ApiError::invalid_input(json!(messages))),
         }
     }
 
-    async fn retrieve_one_post(tag_id: i32, conn: &DbConn) -> Result< Vec<BlogEntry>, status::Custom<Json<AResponse>> > {
+    async fn retrieve_one_post(tag_id: i32, conn: &DbConn) -> Result< Vec<BlogEntry>, ApiError > {
         //Given an id, return a vec of BlogEntry with a single entry or an error 404 / 500
  
         let q_params = QParams::new_filter(Filters::new_eq(vec![format!("id={}", tag_id)]));
         
-        match parse_and_query(q_params, &conn).await {
-            Ok(post) => {
-                match post.len() {
-                    1 => Ok(post),
-                    0 => Err(status::Custom(Status::NotFound, Json(AResponse::_404(
-                            Some(String::from("Could not locate post with provided id.")))))),
-                    _ => Err(status::Custom(Status::InternalServerError, Json(AResponse::_500()))),
-                }
-            },
-            Err(e) => 
-                Err(status::Custom(Status::InternalServerError, Json(AResponse::error(Some(json!([{"message":  format!("{:?}",e) }])))))),
+        let post = parse_and_query(q_params, &conn).await?;
+        match post.len() {
+            1 => Ok(post),
+            0 => Err(ApiError::not_found(Some(String::from("Could not locate post with provided id.")))),
+            _ => Err(ApiError::internal()),
         }
-
     }
 
-    #[get("/?<params..>")]
-    pub async fn get_posts(params: QParams, conn: DbConn) -> Result<Json<AResponse>, status::Custom<Json<AResponse>>> {
-        match post_and_tags(params, &conn).await
-        {
-            Ok(posts) => Ok(Json(AResponse::_200(Some(json!(posts))))),
-            Err(e) => Err(e),
+    #[get("/?<fields>&<include>&<params..>", rank = 2)]
+    pub async fn get_posts(fields: Option<String>, include: Option<String>, params: QParams, conn: DbConn) -> Result<Cached<Json<AResponse>>, ApiError> {
+        if let Some(listing) = Listing::parse(fields, include)? {
+            let posts = post_listing(params, &listing, &conn).await?;
+            return Ok(Cached::json(AResponse::_200(Some(json!(posts)))));
         }
+        let posts = post_and_tags(params, &conn).await?;
+        Ok(Cached::json(AResponse::_200(Some(json!(posts)))))
+    }
+
+    //Last-Modified for a single post. Posts that were never updated fall back to the day they were created.
+    fn modified_on(p: &PostAndTags) -> Option<chrono::NaiveDate> {
+        p.post.last_updated.or(p.post.created.map(|c| c.date()))
     }
 
-    #[get("/<id>")]
-    pub async fn get(id: i32, conn: DbConn) -> Result<Json<AResponse>, status::Custom<Json<AResponse>>> {
+    #[get("/<id>", rank = 2)]
+    pub async fn get(id: i32, conn: DbConn) -> Result<Cached<Json<AResponse>>, ApiError> {
         let q_params = QParams::new_filter(Filters::new_eq(vec![format!("id={}", id)]));
-        match post_and_tags(q_params, &conn).await
-        {
-            Ok(posts) => match posts.len() {
-                0 => return Err(status::Custom(Status::NotFound, Json(AResponse::_404(None)))),
-                _ => Ok(Json(AResponse::_200(Some(json!(posts))))),
+        let mut posts = post_and_tags(q_params, &conn).await?;
+        if let Some(p) = posts.first_mut() {
+            p.series = conn.run(move |c| crate::series::navigation(c, id)).await?;
+        }
+        match posts.first() {
+            None => Err(ApiError::not_found(None)),
+            Some(p) => {
+                let (modified, version) = (modified_on(p), p.post.version);
+                Ok(Cached::json(AResponse::_200(Some(json!(posts)))).last_modified(modified).versioned(version))
+            },
+        }
+    }
+
+    //"You may also like". Other posts ranked by the tags they share with this one, rare tags counting for more.
+    const RELATED_LIMIT: i64 = 5;
+    const RELATED_MAX: i64 = 20;
+    //What identical wording is worth next to sharing one tag that is on every post
+    const TEXT_WEIGHT: f64 = 2.0;
+
+    #[derive(serde::Serialize, schemars::JsonSchema)]
+    pub struct RelatedPost {
+        id: i32,
+        title: String,
+        author: String,
+        created: Option<chrono::NaiveDateTime>,
+        excerpt: String,
+        shared_tags: Vec<String>,
+        score: f64,
+    }
+
+    //Lower case words of more than 3 letters from the title and the start of the content
+    fn words(title: &str, content: &str) -> HashSet<String> {
+        format!("{} {}", title, excerpt(content))
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|w| w.chars().count() > 3)
+            .map(str::to_lowercase)
+            .collect()
+    }
+
+    //Posts without a created date, or dated in the future, are not published and never recommended.
+    fn related_posts(c: &mut MysqlConnection, target: &BlogEntry, limit: usize, text: bool) -> QueryResult<Vec<RelatedPost>> {
+        use diesel::dsl::{count_star, now};
+
+        let tags = post_tags::table
+            .inner_join(tag::table)
+            .filter(post_tags::post_id.eq(target.id))
+            .filter(tag::deleted_at.is_null())
+            .select((tag::id, tag::name))
+            .load::<(i32, String)>(c)?;
+        let tag_ids = tags.iter().map(|(id, _)| *id).collect::<Vec<i32>>();
+
+        //Inverse document frequency over the published posts, the ones that can be recommended.
+        //The +1 keeps a tag that is on every post worth something.
+        let total = post::table
+            .filter(post::created.le(now.nullable()))
+            .filter(post::deleted_at.is_null())
+            .count()
+            .get_result::<i64>(c)? as f64;
+        let rarity = post_tags::table
+            .inner_join(post::table)
+            .filter(post_tags::tag_id.eq_any(&tag_ids))
+            .filter(post::created.le(now.nullable()))
+            .filter(post::deleted_at.is_null())
+            .group_by(post_tags::tag_id)
+            .select((post_tags::tag_id, count_star()))
+            .load::<(i32, i64)>(c)?
+            .into_iter()
+            .map(|(tag_id, posts)| (tag_id, (total / posts as f64).ln() + 1.0))
+            .collect::<HashMap<i32, f64>>();
+
+        let mut scores: HashMap<i32, (f64, Vec<String>)> = HashMap::new();
+        let shared = post_tags::table
+            .inner_join(post::table)
+            .filter(post_tags::tag_id.eq_any(&tag_ids))
+            .filter(post::id.ne(target.id))
+            .filter(post::created.le(now.nullable()))
+            .filter(post::deleted_at.is_null())
+            .select((post_tags::post_id, post_tags::tag_id))
+            .load::<(i32, i32)>(c)?;
+        for (post_id, tag_id) in shared {
+            let entry = scores.entry(post_id).or_insert((0.0, Vec::new()));
+            entry.0 += rarity.get(&tag_id).copied().unwrap_or(1.0);
+            if let Some((_, name)) = tags.iter().find(|(id, _)| *id == tag_id) {
+                entry.1.push(name.clone());
             }
-            
-            Err(e) => return Err(status::Custom(Status::InternalServerError, Json(AResponse::error(Some(json!([{"message":  format!("{:?}",e) }])))))),
         }
+
+        //Jaccard similarity of the words. Also finds posts that share no tag at all.
+        if text {
+            let target_words = words(&target.title, target.content.as_deref().unwrap_or(""));
+            let candidates = post::table
+                .filter(post::id.ne(target.id))
+                .filter(post::created.le(now.nullable()))
+                .filter(post::deleted_at.is_null())
+                .select((post::id, post::title, sql::<Nullable<Text>>(EXCERPT_SOURCE_SQL)))
+                .load::<(i32, String, Option<String>)>(c)?;
+            for (post_id, title, content) in candidates {
+                let other = words(&title, content.as_deref().unwrap_or(""));
+                let union = target_words.union(&other).count();
+                let common = target_words.intersection(&other).count();
+                if common > 0 {
+                    scores.entry(post_id).or_insert((0.0, Vec::new())).0 += common as f64 / union as f64 * TEXT_WEIGHT;
+                }
+            }
+        }
+
+        //Newer posts first on a tie
+        let mut ranked = scores.into_iter().collect::<Vec<(i32, (f64, Vec<String>))>>();
+        ranked.sort_by(|a, b| b.1.0.total_cmp(&a.1.0).then(b.0.cmp(&a.0)));
+        ranked.truncate(limit);
+
+        let ids = ranked.iter().map(|(id, _)| *id).collect::<Vec<i32>>();
+        let mut posts = post::table
+            .filter(post::id.eq_any(&ids))
+            .load::<BlogEntry>(c)?
+            .into_iter()
+            .map(|p| (p.id, p))
+            .collect::<HashMap<i32, BlogEntry>>();
+
+        Ok(ranked.into_iter()
+            .filter_map(|(id, (score, shared_tags))| posts.remove(&id).map(|p| RelatedPost {
+                id,
+                title: p.title,
+                author: p.author,
+                created: p.created,
+                excerpt: excerpt(p.content.as_deref().unwrap_or("")),
+                shared_tags,
+                score: (score * 1000.0).round() / 1000.0,
+            }))
+            .collect())
     }
 
-    #[post("/", format="json", data="<new_post>")]
-    pub async fn post_(conn: DbConn, mut new_post: Json<NewPost>, user: ValidSession) -> Result<status::Created<String>, status::Custom<Json<AResponse>> > {
+    #[get("/<id>/related?<limit>&<text>")]
+    pub async fn get_related(id: i32, limit: Option<i64>, text: Option<bool>, conn: DbConn) -> Result<Cached<Json<AResponse>>, ApiError> {
+        let limit = limit.unwrap_or(RELATED_LIMIT).clamp(1, RELATED_MAX) as usize;
+        let mut target = retrieve_one_post(id, &conn).await?;
+        let target = target.remove(0);
+
+        let related = conn.run(move |c| related_posts(c, &target, limit, text.unwrap_or(false))).await?;
+        Ok(Cached::json(AResponse::_200(Some(json!(related)))))
+    }
+
+    //The writes behind the routes take a connection, POST /posts/bulk runs many of them in one transaction. See bulk.rs
+    fn insert_post(c: &mut MysqlConnection, mut new_post: NewPost, user_id: i32, trail: &Trail) -> Result<Option<i32>, ApiError> {
         //Do not accept tags with a new post. User should attach tags in a seperate request.
         validate_user_input(&new_post)?;
-        
-        let (post_title, post_author) = (&new_post.title.clone(), &user.id.to_string());
-        
-        new_post.author = Some(user.id.to_string());
-        match conn.run(move |c| {
-            diesel::insert_into(post::table)
-            .values(&new_post.into_inner())
-            .execute(c)
-        }).await {
-            Ok(_) => {
-                //Successfully created post, now retrieve it's id
-                match get_a_post_id(&conn, post_title, post_author).await {
-                    Some(id) => {
-                        let uri = uri!("/api/posts/", get(id)).to_string();
-                        let body = json!(AResponse::_201(Some(id.to_string()))).to_string();
-                        Ok(status::Created::new(uri).body(body))
-                    },
-                    None => Ok(status::Created::new("")),//Or maybe this should be a 500?
-                }
+
+        let (post_title, post_author) = (new_post.title.clone(), user_id.to_string());
+
+        new_post.author = Some(user_id.to_string());
+        diesel::insert_into(post::table)
+            .values(&new_post)
+            .execute(c)?;
+
+        //Successfully created post, now retrieve it's id
+        let id = get_a_post_id(c, post_title, post_author);
+        if let Some(id) = id {
+            trail.created(Resource::Post, id);
+        }
+        Ok(id)
+    }
+
+    pub async fn create_post(conn: &DbConn, new_post: NewPost, user: &ValidSession, trail: &Trail) -> Result<Option<i32>, ApiError> {
+        let (user_id, trail) = (user.id, trail.clone());
+        conn.run(move |c| insert_post(c, new_post, user_id, &trail)).await
+    }
+
+    #[post("/", format="json", data="<new_post>")]
+    pub async fn post_(conn: DbConn, new_post: Keyed<NewPost>, user: ValidSession, trail: &Trail) -> Result<status::Created<String>, ApiError > {
+        match create_post(&conn, new_post.into_inner(), &user, trail).await? {
+            Some(id) => {
+                let uri = uri!("/api/posts/", get(id)).to_string();
+                let body = json!(AResponse::_201(Some(id.to_string()))).to_string();
+                Ok(status::Created::new(uri).body(body))
             },
-            Err(DatabaseError(UniqueViolation, d)) => 
-                Err(status::Custom(Status::UnprocessableEntity, Json(AResponse::_422(
-                    Some(String::from(d.message())), 
-                    Some(String::from("UNIQUE_VIOLATION")),
-                    None)))),
-            
-            Err(DatabaseError(NotNullViolation, d)) => 
-                Err(status::Custom(Status::UnprocessableEntity, Json(AResponse::_422(
-                    Some(String::from(d.message())), 
-                    Some(String::from("NOT_NULL_VIOLATION")),
-                    None)))),
-            
-            Err(e) => 
-                Err(status::Custom(Status::InternalServerError, Json(AResponse::error(Some(json!([{"message":  format!("{:?}",e) }])))))),
+            None => Ok(status::Created::new("")),//Or maybe this should be a 500?
         }
     }
 
-    #[patch("/<id>",  format="json", data="<new_post>")]
-    pub async fn patch(id: i32, conn: DbConn, new_post: Json<NewPost>, user: ValidSession) -> Result<status::NoContent, status::Custom<Json<AResponse>>> {
+    fn modify_post(c: &mut MysqlConnection, id: i32, new_post: NewPost, user_id: i32, if_match: &IfMatch, trail: &Trail) -> Result<(), ApiError> {
         //TODO NewPost is the wrong data type here. Need one that just takes in the optional post title and optional post content.
         //Do not accept tags with a patch. User should attach tags in a seperate request.
         validate_user_input(&new_post)?;
+        trail.watch_on(c, Resource::Post, id)?;
 
-        match conn.run(move |c| {
+        let rows = c.transaction(|c| {
+            claim_version(c, id, if_match)?;
             let updated_post = 
                 UpdatePost {
                     id, 
                     title: new_post.title.clone(), //This needs to be updated to take in the user name from the jwt.
-                    author: user.id.to_string(),
+                    author: user_id.to_string(),
                     created: None,//Some(new_post.created.unwrap_or_else(|| chrono::offset::Local::now().naive_local())),
                     last_updated: Some(chrono::offset::Local::now().date_naive()),
                     content: new_post.content.clone(),
                 };
-            diesel::update(&updated_post).set(&updated_post).execute(c)
-            
-        }).await {
-            Ok(rows) => {
-                match rows {
-                    1 => Ok(status::NoContent),
-                    0 => Err(status::Custom(Status::NotFound, Json(AResponse::_404(
-                            Some(String::from("Could not locate post with provided id.")))))),
-                    _ => Err(status::Custom(Status::InternalServerError, Json(AResponse::_500()))),
-                }
-            }
+            diesel::update(&updated_post).set(&updated_post).execute(c).map_err(ApiError::from)
+        })?;
 
-            Err(DatabaseError(UniqueViolation, d)) => 
-                Err(status::Custom(Status::UnprocessableEntity, Json(AResponse::_422(
-                    Some(String::from(d.message())), 
-                    Some(String::from("UNIQUE_VIOLATION")),
-                    None)))),
-            
-            Err(DatabaseError(NotNullViolation, d)) => 
-                Err(status::Custom(Status::UnprocessableEntity, Json(AResponse::_422(
-                    Some(String::from(d.message())), 
-                    Some(String::from("NOT_NULL_VIOLATION")),
-                    None)))),
-            
-            Err(QueryBuilderError(_)) => 
-                Err(status::Custom(Status::BadRequest, Json(AResponse::_400(
-                    Some(String::from("You need to provide the 'title' and 'author' in the body of your request.")))))),
-
-            Err(e) => 
-                Err(status::Custom(Status::InternalServerError, Json(AResponse::error(Some(json!([{"message":  format!("{:?}",e) }])))))),
-        }
-    }
-
-    #[delete("/<id>")]
-    pub async fn delete(id: i32, conn: DbConn, _x: Level1) -> Result< Json<AResponse>, status::Custom<Json<AResponse>> > {
-        //Retrieve the target post
-        let target_post = retrieve_one_post(id, &conn).await?;
+        match rows {
+            1 => Ok(()),
+            0 => Err(ApiError::not_found(Some(String::from("Could not locate post with provided id.")))),
+            _ => Err(ApiError::internal()),
+        }
+    }
 
-        //Remove the associated blog_tags for the post
-        crate::post_tags::delete_entries(&conn, crate::post_tags::BelongsTo::Post(target_post)).await?;
+    pub async fn update_post(conn: &DbConn, id: i32, new_post: NewPost, user: &ValidSession, if_match: IfMatch, trail: &Trail) -> Result<(), ApiError> {
+        let (user_id, trail) = (user.id, trail.clone());
+        conn.run(move |c| modify_post(c, id, new_post, user_id, &if_match, &trail)).await
+    }
 
-        //Now remove the post
-        match conn.run(move |c|{
-            diesel::delete(post::table.filter(post::id.eq(id))).execute(c)
-        }).await {
-            Ok(_) => return Ok(Json(AResponse::_200(None))),
-            Err(e) => 
-                return Err(status::Custom(Status::InternalServerError, Json(AResponse::error(Some(json!([{"message":  format!("{:?}",e) }])))))),
-        }
+    #[patch("/<id>",  format="json", data="<new_post>")]
+    pub async fn patch(id: i32, conn: DbConn, new_post: Json<NewPost>, user: ValidSession, if_match: IfMatch, trail: &Trail) -> Result<status::NoContent, ApiError> {
+        update_post(&conn, id, new_post.into_inner(), &user, if_match, trail).await?;
+        Ok(status::NoContent)
     }
 
-    #[put("/<post_id>/tags/<tag_id>")]
-    pub async fn put_post_tag(post_id: i32, tag_id: i32, conn: DbConn, _x: Level1) -> Result< status::NoContent, status::Custom<Json<AResponse>> > {
+    //Moves the post to the trash. Returns the media only this post used and, with purge_media, the files that are left to remove.
+    fn trash_post(c: &mut MysqlConnection, id: i32, purge_media: bool, if_match: &IfMatch, trail: &Trail) -> Result<(Vec<i32>, Vec<String>), ApiError> {
         //Retrieve the target post
-        let target_post = retrieve_one_post(post_id, &conn).await?;
+        post::table.filter(post::id.eq(id)).filter(post::deleted_at.is_null()).select(post::id).first::<i32>(c)
+            .map_err(|e| match e {
+                diesel::result::Error::NotFound => ApiError::not_found(Some(String::from("Could not locate post with provided id."))),
+                e => ApiError::from(e),
+            })?;
 
-        //Retrieve the target tags
-        let q_params = QParams::new_filter(Filters::new_eq(vec![format!("id={}", tag_id)]));
+        trail.watch_on(c, Resource::Post, id)?;
+        if purge_media {
+            for orphan in crate::media::orphaned_by(c, id)? {
+                trail.watch_on(c, Resource::Media, orphan)?;
+            }
+        }
 
-        let tags = match crate::tag::routes::parse_and_query(q_params, &conn).await {
-            Ok(tags) => tags,
-            Err(e) => 
-                return Err(status::Custom(Status::InternalServerError, Json(AResponse::error(Some(json!([{"message":  format!("{:?}",e) }])))))),
-        };
+        c.transaction(|c| {
+            claim_version(c, id, if_match)?;
+            let orphans = crate::media::orphaned_by(c, id)?;
+            diesel::update(post::table.filter(post::id.eq(id)))
+                .set(post::deleted_at.eq(diesel::dsl::now))
+                .execute(c)?;
+            let unused_files = match purge_media {
+                true => crate::media::delete_rows(c, &orphans)?,
+                false => Vec::new(),
+            };
+            Ok::<_, ApiError>((orphans, unused_files))
+        })
+    }
 
-        crate::post_tags::add_entries(&conn, target_post, tags).await?;
-        Ok(status::NoContent)
+    pub async fn delete_post(conn: &DbConn, id: i32, purge_media: bool, if_match: IfMatch, trail: &Trail) -> Result<(Vec<i32>, Vec<String>), ApiError> {
+        let trail = trail.clone();
+        conn.run(move |c| trash_post(c, id, purge_media, &if_match, &trail)).await
     }
 
-    #[patch("/<id>/tags?<tag_params..>", rank = 2)]
-    pub async fn patch_post_tags(id: i32, tag_params: QParams, conn: DbConn, _x: Level1) -> Result< status::NoContent, status::Custom<Json<AResponse>> > {
-        //Retrieve the target post
-        let target_post = retrieve_one_post(id, &conn).await?;
+    //The post goes to the trash, with its tags, and can be restored until the trash is purged. See trash.rs.
+    //Media only this post used are reported, and deleted right away with ?purge_media=true
+    #[delete("/<id>?<purge_media>")]
+    pub async fn delete(id: i32, purge_media: Option<bool>, conn: DbConn, _x: Level1, if_match: IfMatch, env: &State<EnvVariables>, trail: &Trail) -> Result< Json<AResponse>, ApiError > {
+        let purge_media = purge_media.unwrap_or(false);
+        let (orphans, unused_files) = delete_post(&conn, id, purge_media, if_match, trail).await?;
+        crate::media::remove_files(&crate::media::LocalStore::new(&env.media), unused_files).await;
 
-        //Retrieve the target tags
-        let tags = match crate::tag::routes::parse_and_query(tag_params, &conn).await {
-            Ok(tags) => tags,
-            Err(e) => 
-                return Err(status::Custom(Status::InternalServerError, Json(AResponse::error(Some(json!([{"message":  format!("{:?}",e) }])))))),
-        };
-        crate::post_tags::add_entries(&conn, target_post, tags).await?;
-        Ok(status::NoContent)      
+        Ok(Json(AResponse::_200(Some(json!({"orphaned media": orphans, "media purged": purge_media})))))
+    }
+
+    //Run a change to the post's tags in one transaction, together with the version claim.
+    fn attach_tags(c: &mut MysqlConnection, id: i32, refs: Vec<TagRef>, options: &AttachOptions, if_match: &IfMatch, trail: &Trail) -> Result<Vec<TagOutcome>, ApiError> {
+        trail.watch_on(c, Resource::Post, id)?;
+        c.transaction(|c| {
+            claim_version(c, id, if_match)?;
+            crate::post_tags::attach(c, id, refs, options, trail)
+        })
     }
 
-    #[patch("/<id>/tags", format="json", data="<tags>", rank = 1)]
-    pub async fn patch_post_tags_form(id: i32, tags: Json<Tags>, conn: DbConn, _x: Level1) -> Result< status::NoContent, status::Custom<Json<AResponse>> > {
-        //TODO: 
-        // Updating the openapi docs has made me realize that all of my inserts are NOT "All or nothing" / transactional operations.
-        // A user could pass in invalid tags or a mix of valid / invalid tags and never be aware that their insert partially failed (code 207).
-        // Worse, some errors (tag name too long for example) would cause the transactiong to fail immediately. While other errors (a duplicate tag mixed in with new tags)
-        // would cause some entries to be added and others not while returning an error code (not a 207) to the user.
-        // I will check if diesel has a "test update" like function that I can use to make the updates / inserts transactional.
-        // Another option is to have the response be a vec of responses and provide a 207 and help as needed (non-transactional).
-        // I don't think this is an issue on entries that only take a single value such as /post/{post_id}/tag/{tag_id}.
-        // https://docs.diesel.rs/master/diesel/result/enum.Error.html#variant.RollbackTransaction
-        // This may also not be an issue on forms where the user must pass in a single entry such as post tag. Rocket will 404.
-        //
+    async fn change_tags(conn: &DbConn, id: i32, refs: Vec<TagRef>, options: AttachOptions, if_match: IfMatch, trail: &Trail) -> Result<Vec<TagOutcome>, ApiError> {
+        let trail = trail.clone();
+        conn.run(move |c| attach_tags(c, id, refs, &options, &if_match, &trail)).await
+    }
 
+    //204 when everything requested was applied. In partial mode a 207 lists what happened to each tag.
+    #[derive(Responder)]
+    pub enum TagChanges {
+        #[response(status = 204)]
+        Applied(()),
+        #[response(status = 207)]
+        Partial(Json<AResponse>),
+    }
 
+    impl TagChanges {
+        fn new(outcomes: Vec<TagOutcome>, partial: bool) -> Self {
+            match partial {
+                true => TagChanges::Partial(Json(AResponse::_200(Some(json!(outcomes))))),
+                false => TagChanges::Applied(()),
+            }
+        }
+    }
+
+    //The ids and names sent in a Tags body, in the order they were sent.
+    fn tag_refs(tags: &Tags) -> Vec<TagRef> {
+        let mut refs = tags.id.clone().unwrap_or_default().into_iter().map(TagRef::Id).collect::<Vec<TagRef>>();
+        refs.extend(tags.name.clone().unwrap_or_default().into_iter().map(TagRef::Name));
+        refs
+    }
+
+    #[put("/<id>/tags/<tag_id>")]
+    pub async fn put_post_tag(id: i32, tag_id: i32, conn: DbConn, _x: Level1, if_match: IfMatch, trail: &Trail) -> Result< status::NoContent, ApiError > {
         //Retrieve the target post
-        let target_post = retrieve_one_post(id, &conn).await?;
+        retrieve_one_post(id, &conn).await?;
 
-        //Retrieve the target tags
-        let mut q = tags.id.clone().unwrap_or_default().into_iter().map(|id| format!("id={id}")).collect::<Vec<String>>();
-        q.append(&mut tags.name.clone().unwrap_or_default().into_iter().map(|name| format!("name={name}")).collect::<Vec<String>>());
-        let q_params = QParams::new_filter(Filters::new_eq(q));
-
-        let tags = match crate::tag::routes::parse_and_query(q_params, &conn).await {
-            Ok(tags) => tags,
-            Err(e) => 
-                return Err(status::Custom(Status::InternalServerError, Json(AResponse::error(Some(json!([{"message":  format!("{:?}",e) }])))))),
-        };
-        crate::post_tags::add_entries(&conn, target_post, tags).await?;
+        let options = AttachOptions { replace: false, partial: false, create_for: None };
+        change_tags(&conn, id, vec![TagRef::Id(tag_id)], options, if_match, trail).await
+            .map_err(|e| match e.status {
+                //The tag is part of the url
+                Status::UnprocessableEntity => ApiError::not_found(Some(format!("No tag with id {}.", tag_id))),
+                _ => e,
+            })?;
         Ok(status::NoContent)
-        
     }
 
-    #[put("/<id>/tags", format="json", data="<tags>")]
-    pub async fn put_post_tags_form(id: i32, tags: Json<Tags>, conn: DbConn, _x: Level1) -> Result< status::NoContent, status::Custom<Json<AResponse>> > {
+    //v1 only, on v2 the tags are sent as a Tags body. See version.rs
+    #[patch("/<id>/tags?<tag_params..>", rank = 2)]
+    pub async fn patch_post_tags(id: i32, tag_params: QParams, conn: DbConn, _x: Level1, if_match: IfMatch, trail: &Trail) -> Result< status::NoContent, ApiError > {
         //Retrieve the target post
-        let target_post = retrieve_one_post(id, &conn).await?;
+        retrieve_one_post(id, &conn).await?;
 
-        //Retrieve the target tags
-        //The user passes in json object with two optional vecs. One has ids the other has names. Gather up all tags that match elements from either vec.
-        let mut q = tags.id.clone().unwrap_or_default().into_iter().map(|id| format!("id={id}")).collect::<Vec<String>>();
-        q.append(&mut tags.name.clone().unwrap_or_default().into_iter().map(|name| format!("name={name}")).collect::<Vec<String>>());
-        let q_params = QParams::new_filter(Filters::new_eq(q));
-
-        // Don't shadow this value.
-        let tags = match crate::tag::routes::parse_and_query(q_params, &conn).await {
-            Ok(tags) => tags,
-            Err(e) => 
-                return Err(status::Custom(Status::InternalServerError, Json(AResponse::error(Some(json!([{"message":  format!("{:?}",e) }])))))),
-        };
-        // Now is the time to confirm that every id and name in "tags" is found in the result of the parse_and_query.
-        // If not then we need to return an error saying what names or ids are bad.
+        //Retrieve the target tags. These are filters, every tag they match is attached.
+        let tags = crate::tag::routes::parse_and_query(tag_params, &conn).await?;
+        let refs = tags.into_iter().map(|t| TagRef::Id(t.id)).collect();
 
-        // TODO: Have that issue here where a user could have passed in non-existent or invalid tags for this post.
-        // I guess the invalid tags would only be duplicates, and we can just ignore that.
-        // But what if they passed in an id that was incorrect?
-        // I want qparams to be open ended on what thier query return. But I also want some strictness in this case to tell the user that they passed in bad data.
+        change_tags(&conn, id, refs, AttachOptions { replace: false, partial: false, create_for: None }, if_match, trail).await?;
+        Ok(status::NoContent)      
+    }
 
-        
-        //Remove any existing tags attached to the post
-        crate::post_tags::delete_entries(&conn, crate::post_tags::BelongsTo::Post(target_post.clone())).await?;
+    //Unknown names are created for the user unless create=false is sent.
+    fn create_for(create: Option<bool>, user: &ValidSession) -> Option<i32> {
+        match create.unwrap_or(true) {
+            true => Some(user.id),
+            false => None,
+        }
+    }
 
-        //Add new tags
-        crate::post_tags::add_entries(&conn, target_post, tags).await?;
-        Ok(status::NoContent)
-        
+    #[patch("/<id>/tags?<partial>&<create>", format="json", data="<tags>", rank = 1)]
+    pub async fn patch_post_tags_form(id: i32, partial: Option<bool>, create: Option<bool>, tags: Json<Tags>, conn: DbConn, _x: Level1, user: ValidSession, if_match: IfMatch, trail: &Trail) -> Result< TagChanges, ApiError > {
+        //Retrieve the target post
+        retrieve_one_post(id, &conn).await?;
+
+        let partial = partial.unwrap_or(false);
+        let options = AttachOptions { replace: false, partial, create_for: create_for(create, &user) };
+        let outcomes = change_tags(&conn, id, tag_refs(&tags), options, if_match, trail).await?;
+        Ok(TagChanges::new(outcomes, partial))
+    }
+
+    #[put("/<id>/tags?<partial>&<create>", format="json", data="<tags>")]
+    pub async fn put_post_tags_form(id: i32, partial: Option<bool>, create: Option<bool>, tags: Json<Tags>, conn: DbConn, _x: Level1, user: ValidSession, if_match: IfMatch, trail: &Trail) -> Result< TagChanges, ApiError > {
+        //Retrieve the target post
+        retrieve_one_post(id, &conn).await?;
+
+        //The user passes in json object with two optional vecs. One has ids the other has names.
+        //The post ends up with exactly those tags, removing and adding in the same transaction.
+        let partial = partial.unwrap_or(false);
+        let options = AttachOptions { replace: true, partial, create_for: create_for(create, &user) };
+        let outcomes = change_tags(&conn, id, tag_refs(&tags), options, if_match, trail).await?;
+        Ok(TagChanges::new(outcomes, partial))
     }
 
     #[delete("/<id>/tags/<tag_id>")]
-    pub async fn delete_post_tag(id: i32, tag_id: i32, conn: DbConn, _x: Level1) -> Result< status::NoContent, status::Custom<Json<AResponse>> > {
+    pub async fn delete_post_tag(id: i32, tag_id: i32, conn: DbConn, _x: Level1, if_match: IfMatch, trail: &Trail) -> Result< status::NoContent, ApiError > {
         //Retrieve the target post
         let target_post = retrieve_one_post(id, &conn).await?;
+        trail.watch(&conn, Resource::Post, id).await?;
 
         //Retrieve the target tags
         let q_params = QParams::new_filter(Filters::new_eq(vec![format!("id={}", tag_id)]));
-
-        let target_tags = match crate::tag::routes::parse_and_query(q_params, &conn).await {
-            Ok(tags) => tags,
-            Err(e) => 
-                return Err(status::Custom(Status::InternalServerError, Json(AResponse::error(Some(json!([{"message":  format!("{:?}",e) }])))))),
-        };
+        let target_tags = crate::tag::routes::parse_and_query(q_params, &conn).await?;
 
         //Remove the associated blog_tags for the post and tag
-        crate::post_tags::delete_entries(&conn, crate::post_tags::BelongsTo::PostTags((target_post, target_tags))).await?;
+        conn.run(move |c| {
+            c.transaction(|c| {
+                claim_version(c, id, &if_match)?;
+                crate::post_tags::delete_entries(c, crate::post_tags::BelongsTo::PostTags((target_post, target_tags))).map_err(ApiError::from)
+            })
+        }).await?;
         Ok(status::NoContent)
     }
+
+    //One entry of POST /posts/bulk, see bulk.rs
+    #[derive(serde::Deserialize, schemars::JsonSchema)]
+    #[serde(tag = "op", rename_all = "snake_case")]
+    pub enum PostOperation {
+        Create { post: NewPost },
+        Patch { id: i32, post: NewPost, version: Option<i32> },
+        Delete { id: i32, version: Option<i32> },
+        AttachTags {
+            id: i32,
+            tags: Tags,
+            //The post ends up with exactly these tags, like PUT /<id>/tags
+            #[serde(default)]
+            replace: bool,
+            version: Option<i32>,
+        },
+    }
+
+    //Access is the one of the single routes, deleting and tagging posts is for admins
+    fn apply(c: &mut MysqlConnection, op: PostOperation, user_id: i32, admin: bool, require_if_match: bool, trail: &Trail) -> Result<Done, ApiError> {
+        match op {
+            PostOperation::Create { post } => insert_post(c, post, user_id, trail).map(Done::created),
+            PostOperation::Patch { id, post, version } => {
+                modify_post(c, id, post, user_id, &IfMatch::from_version(version, require_if_match), trail)?;
+                Ok(Done::applied(id))
+            },
+            PostOperation::Delete { id, version } => {
+                admin_only(admin)?;
+                let (orphans, _) = trash_post(c, id, false, &IfMatch::from_version(version, require_if_match), trail)?;
+                Ok(Done::with(id, json!({"orphaned media": orphans})))
+            },
+            PostOperation::AttachTags { id, tags, replace, version } => {
+                admin_only(admin)?;
+                let options = AttachOptions { replace, partial: false, create_for: Some(user_id) };
+                attach_tags(c, id, tag_refs(&tags), &options, &IfMatch::from_version(version, require_if_match), trail)?;
+                Ok(Done::applied(id))
+            },
+        }
+    }
+
+    #[post("/bulk", format="json", data="<bulk>")]
+    pub async fn bulk(bulk: Keyed<Bulk<PostOperation>>, conn: DbConn, user: ValidSession, admin: Option<AdminUser>, env: &State<EnvVariables>, trail: &Trail) -> Result<Outcome, ApiError> {
+        bulk.check(&env.bulk)?;
+        let (user_id, admin, require_if_match, trail) = (user.id, admin.is_some(), env.require_if_match, trail.clone());
+        conn.run(move |c| {
+            crate::bulk::run(c, bulk.into_inner(), &trail, |c, op, trail| apply(c, op, user_id, admin, require_if_match, trail))
+        }).await
+    }
+
+    //JSON:API representation of posts. The plain routes above are used unless the client asks for application/vnd.api+json.
+    fn post_resource(p: &PostAndTags) -> ResourceObject {
+        ResourceObject::new("posts", p.post.id, &p.post)
+            .without("author")
+            .to_one("author", "users", p.post.author.parse::<i32>().ok())
+            .to_many("tags", "tags", p.tags.iter().map(|t| t.id), None)
+    }
+
+    //Authors are public, only expose their names.
+    async fn author_names(conn: &DbConn, ids: Vec<i32>) -> Result<Vec<(i32, Option<String>, Option<String>)>, ApiError> {
+        conn.run(move |c| {
+            user::table
+                .filter(user::id.eq_any(ids))
+                .select((user::id, user::first_name, user::last_name))
+                .load::<(i32, Option<String>, Option<String>)>(c)
+        }).await.map_err(ApiError::from)
+    }
+
+    async fn load_authors(conn: &DbConn, ids: Vec<i32>) -> Result<Vec<ResourceObject>, ApiError> {
+        let authors = author_names(conn, ids).await?;
+
+        Ok(authors.into_iter()
+            .map(|(id, first_name, last_name)|
+                ResourceObject::new("users", id, &json!({"first_name": first_name, "last_name": last_name})))
+            .collect())
+    }
+
+    //Returns the primary data and the included resources for a set of posts.
+    pub async fn posts_document(posts: Vec<PostAndTags>, includes: &Includes, fields: &Fieldsets, conn: &DbConn) -> Result<(Vec<Value>, Vec<Value>), ApiError> {
+        let mut included = Vec::new();
+
+        if includes.contains("tags") {
+            let mut seen = HashSet::new();
+            for t in posts.iter().flat_map(|p| p.tags.iter()) {
+                if seen.insert(t.id) {
+                    included.push(ResourceObject::new("tags", t.id, t).into_value(fields));
+                }
+            }
+        }
+
+        if includes.contains("author") {
+            let ids = posts.iter()
+                .filter_map(|p| p.post.author.parse::<i32>().ok())
+                .collect::<HashSet<i32>>()
+                .into_iter()
+                .collect::<Vec<i32>>();
+            included.extend(load_authors(conn, ids).await?.into_iter().map(|a| a.into_value(fields)));
+        }
+
+        let data = posts.iter().map(|p| post_resource(p).into_value(fields)).collect();
+        Ok((data, included))
+    }
+
+    //The document for one post, with its Last-Modified date and version.
+    async fn post_document(id: i32, includes: &Includes, fields: &Fieldsets, conn: &DbConn) -> Result<(Value, Option<chrono::NaiveDate>, i32), ApiError> {
+        let q_params = QParams::new_filter(Filters::new_eq(vec![format!("id={}", id)]));
+        let posts = post_and_tags(q_params, conn).await?;
+        let (modified, version) = match posts.first() {
+            Some(p) => (modified_on(p), p.post.version),
+            None => return Err(ApiError::not_found(None)),
+        };
+        let (mut data, included) = posts_document(posts, includes, fields, conn).await?;
+        Ok((Document::single(data.remove(0), included), modified, version))
+    }
+
+    #[get("/?<include>&<fields>&<params..>", rank = 1)]
+    pub async fn get_posts_json_api(include: Option<String>, fields: Option<HashMap<String, String>>, params: QParams, _j: JsonApiRequest, conn: DbConn) -> Result<Cached<Document>, ApiError> {
+        let includes = Includes::parse(include, &["tags", "author"])?;
+        let fields = Fieldsets::parse(fields);
+        let posts = post_and_tags(params, &conn).await?;
+        let (data, included) = posts_document(posts, &includes, &fields, &conn).await?;
+        Ok(Cached::document(Document::ok(Document::collection(data, included))))
+    }
+
+    #[get("/<id>?<include>&<fields>", rank = 1)]
+    pub async fn get_json_api(id: i32, include: Option<String>, fields: Option<HashMap<String, String>>, _j: JsonApiRequest, conn: DbConn) -> Result<Cached<Document>, ApiError> {
+        let includes = Includes::parse(include, &["tags", "author"])?;
+        let fields = Fieldsets::parse(fields);
+        let (body, modified, version) = post_document(id, &includes, &fields, &conn).await?;
+        Ok(Cached::document(Document::ok(body)).last_modified(modified).versioned(version))
+    }
+
+    #[post("/", format="application/vnd.api+json", data="<doc>")]
+    pub async fn post_json_api(conn: DbConn, doc: Keyed<WriteDocument<NewPost>>, user: ValidSession, trail: &Trail) -> Result<Document, ApiError> {
+        let doc = doc.into_inner();
+        doc.data.check("posts", None)?;
+
+        match create_post(&conn, doc.data.attributes, &user, trail).await? {
+            Some(id) => {
+                let (body, _, _) = post_document(id, &Includes::parse(None, &[])?, &Fieldsets::parse(None), &conn).await?;
+                Ok(Document::created(body, uri!("/api/posts/", get(id)).to_string()))
+            },
+            None => Err(ApiError::internal_from("Created a post but could not read its id back.")),
+        }
+    }
+
+    #[patch("/<id>", format="application/vnd.api+json", data="<doc>")]
+    pub async fn patch_json_api(id: i32, conn: DbConn, doc: Json<WriteDocument<PatchPost>>, _user: ValidSession, if_match: IfMatch, trail: &Trail) -> Result<Document, ApiError> {
+        let doc = doc.into_inner();
+        doc.data.check("posts", Some(id))?;
+
+        let mut changes = doc.data.attributes;
+        if let Some(title) = &changes.title {
+            if !(1..=100).contains(&title.len()) {
+                return Err(ApiError::invalid_input(json!([{"field": "title", "message":  "Valid length is 1 to 100 chars."}])));
+            }
+        }
+        changes.last_updated = Some(chrono::offset::Local::now().date_naive());
+        trail.watch(&conn, Resource::Post, id).await?;
+
+        let rows = conn.run(move |c| {
+            c.transaction(|c| {
+                claim_version(c, id, &if_match)?;
+                diesel::update(post::table.filter(post::id.eq(id))).set(&changes).execute(c).map_err(ApiError::from)
+            })
+        }).await?;
+        if rows == 0 {
+            return Err(ApiError::not_found(Some(String::from("Could not locate post with provided id."))));
+        }
+
+        let (body, _, _) = post_document(id, &Includes::parse(None, &[])?, &Fieldsets::parse(None), &conn).await?;
+        Ok(Document::ok(body))
+    }
 }
\ No newline at end of file
@@ -1,10 +1,25 @@
-use crate::config::DbConn;
-use crate::models::{AResponse, BlogTags, BlogEntry, Tag};
-use crate::schema::{post_tags};
+use std::collections::HashSet;
+use crate::audit::{Resource, Trail};
+use crate::error::ApiError;
+use crate::models::{BlogTags, BlogEntry, Tag};
+use crate::schema::{post_tags, tag};
 use diesel::prelude::*;
-use rocket::http::Status;
-use rocket::response::status;
-use rocket::serde::json::{Json, json};
+use rocket::serde::json::json;
+
+/*
+Every change to a post's tags goes through here and runs on a connection that is already inside a transaction,
+so the caller decides what gets committed together.
+
+All or nothing (default): any requested id or name that does not exist rolls the whole request back with a 422.
+Partial (?partial=true): the tags that exist are applied, the rest are reported. The route answers 207 with one
+entry per requested tag:
+
+    [{"id": 3, "status": "attached"}, {"name": "rust", "id": 7, "status": "already_present"}, {"id": 99, "status": "not_found"}]
+
+Names that do not exist yet are created and attached in the same transaction, owned by the user making the
+request ({"name": "axum", "id": 12, "status": "created"}). Send ?create=false to treat them as not found instead.
+A created tag is audited, and sent to webhooks and the event stream, like one from POST /api/tags.
+*/
 
 pub enum BelongsTo {
     Post(Vec<BlogEntry>),
@@ -12,53 +27,145 @@ pub enum BelongsTo {
     PostTags((Vec<BlogEntry>, Vec<Tag>)),
 }
 //TODO can types remove this duplicated code?
-pub async fn delete_entries(conn: &DbConn, key: BelongsTo) -> Result<usize, status::Custom<Json<AResponse>> >{
-    conn.run(move |c| {
-        match key 
-        {
-            BelongsTo::Post(v) => match diesel::delete(BlogTags::belonging_to(&v)).execute(c)
-            {
-                Ok(count) => Ok(count),
-                Err(e) => Err(status::Custom(Status::InternalServerError, Json(AResponse::error(Some(json!([{"message":  format!("{:?}",e) }])))))),
+pub fn delete_entries(c: &mut MysqlConnection, key: BelongsTo) -> QueryResult<usize> {
+    match key 
+    {
+        BelongsTo::Post(v) => diesel::delete(BlogTags::belonging_to(&v)).execute(c),
+        BelongsTo::Tag(v)  => diesel::delete(BlogTags::belonging_to(&v)).execute(c),
+        BelongsTo::PostTags((posts, tags)) => 
+            diesel::delete(
+                BlogTags::belonging_to(&posts)//blog_tags rows matching posts
+                    .filter(post_tags::tag_id
+                        .eq_any(&tags.into_iter().map(|tag| tag.id).collect::<Vec<i32>>())//blog_tags rows matching posts and tags
+                    )
+            ).execute(c),
+    }
+}
 
-            }
-            BelongsTo::Tag(v)  => match diesel::delete(BlogTags::belonging_to(&v)).execute(c) 
-            {
-                Ok(count) => Ok(count),
-                Err(e) => Err(status::Custom(Status::InternalServerError, Json(AResponse::error(Some(json!([{"message":  format!("{:?}",e) }])))))),
-            }
-            BelongsTo::PostTags((posts, tags)) => match 
-                diesel::delete(
-                    BlogTags::belonging_to(&posts)//blog_tags rows matching posts
-                        .filter(post_tags::tag_id
-                            .eq_any(&tags.into_iter().map(|tag| tag.id).collect::<Vec<i32>>())//blog_tags rows matching posts and tags
-                        )
-                ).execute(c)
-            { 
-                Ok(count) => Ok(count),
-                Err(e) => Err(status::Custom(Status::InternalServerError, Json(AResponse::error(Some(json!([{"message":  format!("{:?}",e) }])))))),
-            }
-        }
-    }).await
+//A tag as the client named it.
+#[derive(Debug, Clone)]
+pub enum TagRef {
+    Id(i32),
+    Name(String),
 }
 
-pub async fn add_entries(conn: &DbConn, mut post: Vec<BlogEntry>, tags: Vec<Tag>) -> Result<usize, status::Custom<Json<AResponse>>> {
-    let post = match post.pop() {
-        Some(p) => p,
-        None => return Ok(0),
-    };
+#[derive(serde::Serialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum TagStatus {
+    Attached,
+    //The tag did not exist, it was created and attached
+    Created,
+    AlreadyPresent,
+    Detached,
+    NotFound,
+}
 
-    //create Vec<(blog_id, tag_id)
-    let entries =
-        tags.into_iter().map(|tag| (post_tags::post_id.eq(post.id), post_tags::tag_id.eq(tag.id))).collect::<Vec<_>>();
+//What happened to one requested tag.
+#[derive(serde::Serialize, Debug)]
+pub struct TagOutcome {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub status: TagStatus,
+}
 
-    //add blog_id and tag_id to blog_tags table
-    conn.run(move |c| {
-        match diesel::insert_into(post_tags::table).values(entries).execute(c) {
-            Ok(tag_count) => Ok(tag_count),
-            Err(e) => Err(status::Custom(Status::InternalServerError, Json(AResponse::error(Some(json!([{"message":  format!("{:?}",e) }])))))),
+pub struct AttachOptions {
+    //Remove the post's tags that were not requested (PUT)
+    pub replace: bool,
+    //Apply what can be applied instead of rolling back on unknown tags
+    pub partial: bool,
+    //Create unknown names, owned by this user
+    pub create_for: Option<i32>,
+}
+
+//Find the id of every requested tag. Names are not unique, the oldest tag with the name wins.
+//The second value is true for tags that were created here, they are recorded on the trail.
+fn resolve(c: &mut MysqlConnection, refs: &[TagRef], create_for: Option<i32>, trail: &Trail) -> Result<Vec<(Option<i32>, bool)>, ApiError> {
+    let mut ids = Vec::with_capacity(refs.len());
+    for r in refs {
+        let id = match r {
+            TagRef::Id(id) => (tag::table.filter(tag::id.eq(id)).filter(tag::deleted_at.is_null()).select(tag::id).first::<i32>(c).optional()?, false),
+            TagRef::Name(name) => {
+                let existing = crate::tag::helper::find_by_name(c, name)?;
+                match (existing, create_for) {
+                    (None, Some(user_id)) => {
+                        crate::tag::helper::validate_name(name)?;
+                        let id = crate::tag::helper::insert_owned_tag(c, name, user_id)?;
+                        trail.created(Resource::Tag, id);
+                        (Some(id), true)
+                    },
+                    (existing, _) => (existing, false),
+                }
+            },
+        };
+        ids.push(id);
+    }
+    Ok(ids)
+}
+
+pub fn attach(c: &mut MysqlConnection, post_id: i32, refs: Vec<TagRef>, options: &AttachOptions, trail: &Trail) -> Result<Vec<TagOutcome>, ApiError> {
+    let resolved = resolve(c, &refs, options.create_for, trail)?;
+    let ids = resolved.iter().map(|(id, _)| *id).collect::<Vec<Option<i32>>>();
+
+    if !options.partial {
+        let missing = refs.iter().zip(&ids)
+            .filter(|(_, id)| id.is_none())
+            .map(|(r, _)| match r {
+                TagRef::Id(id) => json!({"field": "id", "message": format!("No tag with id {}.", id)}),
+                TagRef::Name(name) => json!({"field": "name", "message": format!("No tag named '{}'.", name)}),
+            })
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            return Err(ApiError::invalid_input(json!(missing)));
         }
-    }).await
+    }
+
+    //Links to tags in the trash are neither reported nor replaced, they come back if the tag is restored
+    let present = post_tags::table
+        .inner_join(tag::table)
+        .filter(post_tags::post_id.eq(post_id))
+        .filter(tag::deleted_at.is_null())
+        .select(post_tags::tag_id)
+        .load::<i32>(c)?
+        .into_iter()
+        .collect::<HashSet<i32>>();
+    let wanted = ids.iter().flatten().copied().collect::<HashSet<i32>>();
+
+    let mut outcomes = Vec::with_capacity(refs.len());
+    if options.replace {
+        let removed = present.difference(&wanted).copied().collect::<Vec<i32>>();
+        if !removed.is_empty() {
+            diesel::delete(post_tags::table
+                .filter(post_tags::post_id.eq(post_id))
+                .filter(post_tags::tag_id.eq_any(&removed)))
+                .execute(c)?;
+        }
+        outcomes.extend(removed.into_iter().map(|id| TagOutcome { id: Some(id), name: None, status: TagStatus::Detached }));
+    }
+
+    let mut added = HashSet::new();
+    for (r, (id, created)) in refs.into_iter().zip(resolved) {
+        let name = match r {
+            TagRef::Name(name) => Some(name),
+            TagRef::Id(_) => None,
+        };
+        let status = match id {
+            None => TagStatus::NotFound,
+            Some(id) if present.contains(&id) || !added.insert(id) => TagStatus::AlreadyPresent,
+            Some(id) => {
+                diesel::insert_into(post_tags::table)
+                    .values((post_tags::post_id.eq(post_id), post_tags::tag_id.eq(id)))
+                    .execute(c)?;
+                match created {
+                    true => TagStatus::Created,
+                    false => TagStatus::Attached,
+                }
+            },
+        };
+        outcomes.push(TagOutcome { id, name, status });
+    }
+    Ok(outcomes)
 }
 
 //use std::iter::zip;
@@ -1,17 +1,18 @@
 use rocket::serde::json::{Json, Value, json};
 use diesel::prelude::*;
 use crate::config::DbConn;
+use crate::error::ApiError;
+use crate::idempotency::Keyed;
 use crate::models::Role;
 use crate::schema::{role};
-use rocket::http::{Status};
-use rocket::response::status;
 
 pub mod routes {
     use super::*;
     use crate::auth::Level1;
+    use crate::audit::{Resource, Trail};
 
     #[get("/")]
-    pub async fn get_roles(conn: DbConn, _x: Level1) -> Result< Value, status::Custom<Value>> {
+    pub async fn get_roles(conn: DbConn, _x: Level1) -> Result< Value, ApiError> {
         match conn.run(|c| {
             role::table
                 .limit(100)
@@ -19,12 +20,12 @@ pub mod routes {
         }).await
         {
             Ok(results) => Ok(json!(results)),
-            Err(e) => Err(status::Custom(Status::InternalServerError , json!(format!("{}", e)))),
+            Err(e) => Err(ApiError::from(e)),
         }
     }
 
     #[get("/<id>")]
-    pub async fn get_role(conn: DbConn, id: i32, _x: Level1) -> Result< Value, status::Custom<Value>> {
+    pub async fn get_role(conn: DbConn, id: i32, _x: Level1) -> Result< Value, ApiError> {
         match conn.run(move |c| {
             role::table
                 .filter(role::id.eq(id))
@@ -32,13 +33,14 @@ pub mod routes {
         }).await
         {
             Ok(results) => Ok(json!(results)),
-            Err(e) => Err(status::Custom(Status::InternalServerError , json!(format!("{}", e)))),
+            Err(e) => Err(ApiError::from(e)),
         }
     }
 
     #[post("/", data = "<new_entry>")]
-    pub async fn new_role(conn: DbConn, new_entry: Json<Role>, _x: Level1) -> Result< Value, status::Custom<Value>> {
+    pub async fn new_role(conn: DbConn, new_entry: Keyed<Role>, _x: Level1, trail: &Trail) -> Result< Value, ApiError> {
         println!("{:?}", new_entry);
+        trail.created(Resource::Role, new_entry.id);
         match conn.run(move |c| {
         diesel::insert_into(role::table)
             .values((
@@ -49,13 +51,15 @@ pub mod routes {
         }).await
         {
             Ok(results) => Ok(json!(results)),
-            Err(e) => Err(status::Custom(Status::InternalServerError , json!(format!("{}", e)))),
+            Err(e) => Err(ApiError::from(e)),
         }
 
     }
 
-    #[post("/<id>", data = "<new_entry>")]
-    pub async fn update_role(conn: DbConn, id: i32, new_entry: Json<Role>, _x: Level1) -> Result< Value, status::Custom<Value>> {
+    async fn update(conn: DbConn, id: i32, new_entry: Role, trail: &Trail) -> Result< Value, ApiError> {
+        //A changed id is logged as the old role going and the new one appearing
+        trail.watch(&conn, Resource::Role, id).await?;
+        trail.watch(&conn, Resource::Role, new_entry.id).await?;
         match conn.run(move |c| {
             diesel::update(role::table)
                 .filter(role::id.eq(id))
@@ -69,12 +73,12 @@ pub mod routes {
         }).await
         {
             Ok(results) => Ok(json!(results)),
-            Err(e) => Err(status::Custom(Status::InternalServerError , json!(format!("{}", e)))),
+            Err(e) => Err(ApiError::from(e)),
         }
     }
 
-    #[post("/<id>/delete")]
-    pub async fn delete_role(conn: DbConn, id: i32, _x: Level1) -> Result< Value, status::Custom<Value>> {
+    async fn remove(conn: DbConn, id: i32, trail: &Trail) -> Result< Value, ApiError> {
+        trail.watch(&conn, Resource::Role, id).await?;
         match conn.run(move |c| {
             diesel::delete(
                 role::table
@@ -84,8 +88,30 @@ pub mod routes {
         }).await
         {
             Ok(results) => Ok(json!(results)),
-            Err(e) => Err(status::Custom(Status::InternalServerError , json!(format!("{}", e)))),
+            Err(e) => Err(ApiError::from(e)),
         }
     }
+
+    //v1 only, PATCH /<id> on v2. See version.rs
+    #[post("/<id>", data = "<new_entry>")]
+    pub async fn update_role(conn: DbConn, id: i32, new_entry: Keyed<Role>, _x: Level1, trail: &Trail) -> Result< Value, ApiError> {
+        update(conn, id, new_entry.into_inner(), trail).await
+    }
+
+    #[patch("/<id>", data = "<new_entry>")]
+    pub async fn patch_role(conn: DbConn, id: i32, new_entry: Json<Role>, _x: Level1, trail: &Trail) -> Result< Value, ApiError> {
+        update(conn, id, new_entry.into_inner(), trail).await
+    }
+
+    //v1 only, DELETE /<id> on v2. See version.rs
+    #[post("/<id>/delete")]
+    pub async fn delete_role(conn: DbConn, id: i32, _x: Level1, trail: &Trail) -> Result< Value, ApiError> {
+        remove(conn, id, trail).await
+    }
+
+    #[delete("/<id>")]
+    pub async fn remove_role(conn: DbConn, id: i32, _x: Level1, trail: &Trail) -> Result< Value, ApiError> {
+        remove(conn, id, trail).await
+    }
     
 }
\ No newline at end of file
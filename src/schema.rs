@@ -1,5 +1,50 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    audit_log (id) {
+        id -> BigInt,
+        actor -> Nullable<Integer>,
+        action -> Varchar,
+        resource -> Varchar,
+        resource_id -> Nullable<Integer>,
+        method -> Varchar,
+        path -> Varchar,
+        status -> Integer,
+        before_state -> Nullable<Mediumtext>,
+        after_state -> Nullable<Mediumtext>,
+        ip -> Nullable<Varchar>,
+        created -> Timestamp,
+    }
+}
+
+diesel::table! {
+    idempotency_key (id) {
+        id -> BigInt,
+        idem_key -> Varchar,
+        scope -> Varchar,
+        fingerprint -> Char,
+        status -> Nullable<Integer>,
+        content_type -> Nullable<Varchar>,
+        location -> Nullable<Varchar>,
+        body -> Nullable<Mediumtext>,
+        created -> Timestamp,
+    }
+}
+
+diesel::table! {
+    media (id) {
+        id -> Integer,
+        file_name -> Varchar,
+        mime_type -> Varchar,
+        size -> BigInt,
+        checksum -> Char,
+        uploader -> Nullable<Integer>,
+        created -> Timestamp,
+        width -> Nullable<Integer>,
+        height -> Nullable<Integer>,
+    }
+}
+
 diesel::table! {
     post (id) {
         id -> Integer,
@@ -8,6 +53,15 @@ diesel::table! {
         created -> Nullable<Timestamp>,
         last_updated -> Nullable<Date>,
         content -> Nullable<Text>,
+        version -> Integer,
+        deleted_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    post_media (post_id, media_id) {
+        post_id -> Integer,
+        media_id -> Integer,
     }
 }
 
@@ -26,10 +80,36 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    series (id) {
+        id -> Integer,
+        title -> Varchar,
+        description -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    series_posts (series_id, post_id) {
+        series_id -> Integer,
+        post_id -> Integer,
+        position -> Integer,
+    }
+}
+
 diesel::table! {
     tag (id) {
         id -> Integer,
         name -> Varchar,
+        version -> Integer,
+        parent_id -> Nullable<Integer>,
+        deleted_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    tag_alias (name) {
+        name -> Varchar,
+        tag_id -> Integer,
     }
 }
 
@@ -44,6 +124,7 @@ diesel::table! {
         role -> Integer,
         active -> Nullable<Bool>,
         last_access -> Nullable<Date>,
+        deleted_at -> Nullable<Timestamp>,
     }
 }
 
@@ -54,17 +135,72 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    webhook (id) {
+        id -> Integer,
+        url -> Varchar,
+        secret -> Varchar,
+        events -> Varchar,
+        active -> Bool,
+        created -> Timestamp,
+    }
+}
+
+diesel::table! {
+    webhook_attempt (id) {
+        id -> BigInt,
+        delivery_id -> BigInt,
+        attempted -> Timestamp,
+        response_status -> Nullable<Integer>,
+        error -> Nullable<Varchar>,
+        duration_ms -> Integer,
+    }
+}
+
+diesel::table! {
+    webhook_delivery (id) {
+        id -> BigInt,
+        webhook_id -> Integer,
+        event -> Varchar,
+        resource_id -> Nullable<Integer>,
+        payload -> Mediumtext,
+        status -> Varchar,
+        attempts -> Integer,
+        next_attempt -> Nullable<Timestamp>,
+        created -> Timestamp,
+        delivered -> Nullable<Timestamp>,
+    }
+}
+
+diesel::joinable!(media -> user (uploader));
+diesel::joinable!(post_media -> media (media_id));
+diesel::joinable!(post_media -> post (post_id));
 diesel::joinable!(post_tags -> post (post_id));
 diesel::joinable!(post_tags -> tag (tag_id));
+diesel::joinable!(series_posts -> post (post_id));
+diesel::joinable!(series_posts -> series (series_id));
+diesel::joinable!(tag_alias -> tag (tag_id));
 diesel::joinable!(user -> role (role));
 diesel::joinable!(user_tags -> tag (tag_id));
 diesel::joinable!(user_tags -> user (user_id));
+diesel::joinable!(webhook_attempt -> webhook_delivery (delivery_id));
+diesel::joinable!(webhook_delivery -> webhook (webhook_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    audit_log,
+    idempotency_key,
+    media,
     post,
+    post_media,
     post_tags,
     role,
+    series,
+    series_posts,
     tag,
+    tag_alias,
     user,
     user_tags,
+    webhook,
+    webhook_attempt,
+    webhook_delivery,
 );
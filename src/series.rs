@@ -0,0 +1,224 @@
+use rocket::response::status;
+use rocket::serde::json::{Json, json};
+use diesel::prelude::*;
+use crate::cache::Cached;
+use crate::config::DbConn;
+use crate::error::ApiError;
+use crate::idempotency::Keyed;
+use crate::models::{AResponse, LastInsertId, Series};
+use crate::schema::{post, series, series_posts};
+
+/*
+A series ties the parts of a multi-part post together in reading order.
+A post is part of at most one series, so a single post knows its previous and next part without being told which
+series to look in. Positions start at 1 and have no gaps, PUT /api/series/<id>/posts rewrites them all.
+*/
+
+//One part of a series
+#[derive(serde::Serialize, serde::Deserialize, Debug, Queryable, schemars::JsonSchema)]
+pub struct SeriesEntry {
+    pub position: i32,
+    pub id: i32,
+    pub title: String,
+}
+
+//Where a post sits in its series. Added to a single post by post::routes::get.
+#[derive(serde::Serialize, serde::Deserialize, Debug, schemars::JsonSchema)]
+pub struct Navigation {
+    pub id: i32,
+    pub title: String,
+    pub position: i32,
+    pub parts: usize,
+    pub previous: Option<SeriesEntry>,
+    pub next: Option<SeriesEntry>,
+}
+
+fn entries(c: &mut MysqlConnection, series_id: i32) -> QueryResult<Vec<SeriesEntry>> {
+    series_posts::table
+        .inner_join(post::table)
+        .filter(series_posts::series_id.eq(series_id))
+        .filter(post::deleted_at.is_null())
+        .select((series_posts::position, post::id, post::title))
+        .order(series_posts::position.asc())
+        .load::<SeriesEntry>(c)
+}
+
+pub fn navigation(c: &mut MysqlConnection, post_id: i32) -> QueryResult<Option<Navigation>> {
+    let series = series_posts::table
+        .inner_join(series::table)
+        .filter(series_posts::post_id.eq(post_id))
+        .select(Series::as_select())
+        .first::<Series>(c)
+        .optional()?;
+    let series = match series {
+        Some(series) => series,
+        None => return Ok(None),
+    };
+
+    let mut parts = entries(c, series.id)?;
+    let index = match parts.iter().position(|p| p.id == post_id) {
+        Some(index) => index,
+        None => return Ok(None),
+    };
+    let (position, count) = (parts[index].position, parts.len());
+    //Take next first, removing it does not move previous
+    let next = if index + 1 < count { Some(parts.remove(index + 1)) } else { None };
+    let previous = if index > 0 { Some(parts.remove(index - 1)) } else { None };
+
+    Ok(Some(Navigation { id: series.id, title: series.title, position, parts: count, previous, next }))
+}
+
+pub mod routes {
+    use super::*;
+    use crate::auth::Level1;
+    use crate::audit::{Resource, Trail};
+    use diesel::result::Error::NotFound;
+
+    #[derive(Debug, serde::Deserialize, Insertable, AsChangeset, schemars::JsonSchema)]
+    #[diesel(table_name = series)]
+    pub struct NewSeries {
+        pub title: String,
+        pub description: Option<String>,
+    }
+
+    #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+    pub struct SeriesPosts {
+        pub posts: Vec<i32>,
+    }
+
+    fn validate_user_input(new_series: &NewSeries) -> Result<(), ApiError> {
+        if !(1..=255).contains(&new_series.title.trim().len()) {
+            return Err(ApiError::invalid_input(json!([{"field": "title", "message":  "Valid length is 1 to 255 chars."}])));
+        }
+        Ok(())
+    }
+
+    fn not_found() -> ApiError {
+        ApiError::not_found(Some(String::from("Could not locate series with provided id.")))
+    }
+
+    #[get("/?<start>&<step>")]
+    pub async fn get_all(start: Option<i64>, step: Option<i64>, conn: DbConn) -> Result<Cached<Json<AResponse>>, ApiError> {
+        let all = conn.run(move |c| {
+            series::table
+                .order(series::id.asc())
+                .offset(start.unwrap_or(0))
+                .limit(step.unwrap_or(100))
+                .load::<Series>(c)
+        }).await?;
+        Ok(Cached::json(AResponse::_200(Some(json!(all)))))
+    }
+
+    #[get("/<id>")]
+    pub async fn get(id: i32, conn: DbConn) -> Result<Cached<Json<AResponse>>, ApiError> {
+        let (found, parts) = conn.run(move |c| {
+            let found = series::table.filter(series::id.eq(id)).first::<Series>(c)?;
+            let parts = entries(c, id)?;
+            QueryResult::Ok((found, parts))
+        }).await.map_err(|e| match e {
+            NotFound => not_found(),
+            e => ApiError::from(e),
+        })?;
+
+        let d = json!({"id": found.id, "title": found.title, "description": found.description, "posts": parts});
+        Ok(Cached::json(AResponse::_200(Some(d))))
+    }
+
+    #[post("/", format="json", data="<new_series>")]
+    pub async fn post_(conn: DbConn, new_series: Keyed<NewSeries>, _x: Level1, trail: &Trail) -> Result<status::Created<String>, ApiError> {
+        let new_series = new_series.into_inner();
+        validate_user_input(&new_series)?;
+
+        let id = conn.run(move |c| {
+            c.transaction(|c| {
+                diesel::insert_into(series::table).values(&new_series).execute(c)?;
+                diesel::sql_query("SELECT LAST_INSERT_ID() AS id").get_result::<LastInsertId>(c)
+            })
+        }).await?.id as i32;
+        trail.created(Resource::Series, id);
+
+        let uri = uri!("/api/series/", get(id)).to_string();
+        let body = json!(AResponse::_201(Some(uri.clone()))).to_string();
+        Ok(status::Created::new(uri).body(body))
+    }
+
+    #[patch("/<id>", format="json", data="<new_series>")]//Patch 204 404 422
+    pub async fn patch(id: i32, conn: DbConn, new_series: Json<NewSeries>, _x: Level1, trail: &Trail) -> Result<status::NoContent, ApiError> {
+        let new_series = new_series.into_inner();
+        validate_user_input(&new_series)?;
+        trail.watch(&conn, Resource::Series, id).await?;
+
+        let rows = conn.run(move |c| {
+            diesel::update(series::table.filter(series::id.eq(id))).set(&new_series).execute(c)
+        }).await?;
+        match rows {
+            0 => Err(not_found()),
+            _ => Ok(status::NoContent),
+        }
+    }
+
+    //Replace the parts of a series. The order of the list is the reading order.
+    #[put("/<id>/posts", format="json", data="<parts>")]//Put 204 404 409 422
+    pub async fn put_posts(id: i32, conn: DbConn, parts: Json<SeriesPosts>, _x: Level1, trail: &Trail) -> Result<status::NoContent, ApiError> {
+        let parts = parts.into_inner().posts;
+
+        let mut seen = std::collections::HashSet::new();
+        if let Some(twice) = parts.iter().find(|p| !seen.insert(**p)) {
+            return Err(ApiError::invalid_input(json!([{"field": "posts", "message": format!("Post {} is listed more than once.", twice)}])));
+        }
+        trail.watch(&conn, Resource::Series, id).await?;
+
+        conn.run(move |c| {
+            c.transaction::<_, ApiError, _>(|c| {
+                series::table.filter(series::id.eq(id)).select(series::id).first::<i32>(c)
+                    .map_err(|e| match e {
+                        NotFound => not_found(),
+                        e => ApiError::from(e),
+                    })?;
+
+                let found = post::table.filter(post::id.eq_any(&parts)).filter(post::deleted_at.is_null()).select(post::id).load::<i32>(c)?;
+                let missing = parts.iter()
+                    .filter(|p| !found.contains(*p))
+                    .map(|p| json!({"field": "posts", "message": format!("No post with id {}.", p)}))
+                    .collect::<Vec<_>>();
+                if !missing.is_empty() {
+                    return Err(ApiError::invalid_input(json!(missing)));
+                }
+
+                let elsewhere = series_posts::table
+                    .filter(series_posts::post_id.eq_any(&parts))
+                    .filter(series_posts::series_id.ne(id))
+                    .select((series_posts::post_id, series_posts::series_id))
+                    .load::<(i32, i32)>(c)?;
+                if !elsewhere.is_empty() {
+                    let errors = elsewhere.iter()
+                        .map(|(p, s)| json!({"field": "posts", "message": format!("Post {} is already part of series {}.", p, s)}))
+                        .collect::<Vec<_>>();
+                    return Err(ApiError::conflict(Some(String::from("A post can only be part of one series."))).with_errors(json!(errors)));
+                }
+
+                diesel::delete(series_posts::table.filter(series_posts::series_id.eq(id))).execute(c)?;
+                let rows = parts.iter()
+                    .enumerate()
+                    .map(|(i, p)| (series_posts::series_id.eq(id), series_posts::post_id.eq(*p), series_posts::position.eq(i as i32 + 1)))
+                    .collect::<Vec<_>>();
+                diesel::insert_into(series_posts::table).values(rows).execute(c)?;
+                Ok(())
+            })
+        }).await?;
+        Ok(status::NoContent)
+    }
+
+    //The posts stay, only the series and its order are removed
+    #[delete("/<id>")]//Delete 204 404
+    pub async fn delete(id: i32, conn: DbConn, _x: Level1, trail: &Trail) -> Result<status::NoContent, ApiError> {
+        trail.watch(&conn, Resource::Series, id).await?;
+        let rows = conn.run(move |c| {
+            diesel::delete(series::table.filter(series::id.eq(id))).execute(c)
+        }).await?;
+        match rows {
+            0 => Err(not_found()),
+            _ => Ok(status::NoContent),
+        }
+    }
+}
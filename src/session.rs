@@ -1,4 +1,5 @@
-use rocket::serde::json::{Value, json};//Json
+//use rocket::serde::json::{Value, json};//Json
+use crate::error::ApiError;
 //use diesel::prelude::*;
 //use crate::config::DbConn;
 //use crate::schema::{user, role};
@@ -14,8 +15,8 @@ pub mod routes {
     use super::*;
 
     #[catch(401)]
-    pub fn email_or_pw_incorrect(req: &Request<'_>) -> Value {
-        json!(format!("You lack needed persmisison or your provided credentials were incorrect. {}", req.uri()))
+    pub fn email_or_pw_incorrect(_req: &Request<'_>) -> ApiError {
+        ApiError::unauthorized(Some(String::from("You lack needed persmisison or your provided credentials were incorrect.")))
     }
 
     #[derive(serde::Deserialize)]
@@ -35,6 +36,7 @@ pub mod routes {
                 user::table
                 .left_join(role::table)
                 .filter(user::email.eq(user_email))
+                .filter(user::deleted_at.is_null())
                 .select((user::id, user::phc, user::role, role::user_role.nullable()))
                 .first::<(i32, Option<String>, i32, Option<String>)>(c)//Throw error if 0 rows returned
             }).await
@@ -71,6 +73,7 @@ pub mod routes {
         match conn.run(|c| {
             diesel::update(user::table)
             .filter(user::email.eq(user_email))
+            .filter(user::deleted_at.is_null())
             .set(user::last_access.eq(Some(chrono::Utc::now().date_naive())))
             .execute(c)
         }).await {
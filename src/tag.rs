@@ -1,12 +1,14 @@
+use std::collections::HashMap;
+use crate::cache::{Cached, IfMatch};
 use crate::config::DbConn;
-use crate::schema::{tag, post_tags, user_tags, user};
+use crate::error::ApiError;
+use crate::idempotency::Keyed;
+use crate::schema::{tag, post_tags, tag_alias, user_tags, user};
 use crate::models::{Tag, AResponse, QParams, Filters, BlogTags, NewUserTag, TagsUsers};
+use crate::myjsonapi::{Document, Fieldsets, Includes, JsonApiRequest, ResourceObject, WriteDocument};
 use diesel::prelude::*;
-use diesel::result::DatabaseErrorKind::{UniqueViolation, NotNullViolation};
-use diesel::result::Error::{DatabaseError, QueryBuilderError, RollbackErrorOnCommit};
-use rocket::http::Status;
 use rocket::response::status;
-use rocket::serde::json::{Json, json};
+use rocket::serde::json::{Json, Value, json};
 
 
 pub mod routes {
@@ -14,19 +16,46 @@ pub mod routes {
     use diesel::{mysql::Mysql, result::Error::NotFound};
     use super::*;
     use crate::tag::helper::get_a_tag_id;
+    use crate::audit::{Resource, Trail};
+    use crate::bulk::{Bulk, Done, Outcome};
+    use crate::models::EnvVariables;
+    use rocket::State;
 
     enum TagFields {
         Id(i32),
         Name(String),
         Owner(i32),
+        Parent(i32),
     }
     
-    #[derive(Debug, Insertable, serde::Deserialize)]
+    #[derive(Debug, Insertable, serde::Deserialize, schemars::JsonSchema)]
     #[diesel(table_name = tag)]
     pub struct NewTag {
         pub name: String,
     }
     
+    #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+    pub struct MoveTag {
+        pub parent_id: Option<i32>,
+    }
+
+    #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+    pub struct MergeTags {
+        pub sources: Vec<i32>,
+    }
+
+    #[derive(Debug, serde::Serialize, schemars::JsonSchema)]
+    pub struct TagStats {
+        pub id: i32,
+        pub name: String,
+        pub parent_id: Option<i32>,
+        pub posts: i64,
+        //Dates of the oldest and newest post carrying the tag
+        pub first_used: Option<chrono::NaiveDateTime>,
+        pub last_used: Option<chrono::NaiveDateTime>,
+        pub owners: Vec<i32>,
+    }
+
     #[derive(Debug, AsChangeset, serde::Serialize, serde::Deserialize, Identifiable)]
     #[diesel(table_name = tag)]
     struct UpdateTag {
@@ -34,6 +63,10 @@ pub mod routes {
         pub name: Option<String>,
     }
 
+    //Columns validation() and the order loop accept, listed by the discovery document at GET /api
+    pub const FILTER_COLUMNS: [&str; 4] = ["id", "owner", "parent", "name"];
+    pub const ORDER_COLUMNS: [&str; 2] = ["id", "name"];
+
     fn validation(qp: String) -> Option<TagFields> {
         // Verify that the query parameter is valid in format
         if let Some((k, v)) = qp.split_once('=') {
@@ -50,6 +83,12 @@ pub mod routes {
                         _ => None,
                     }
                 },
+                "parent" => {
+                    match v.parse::<i32>() {
+                        Ok(v) => Some(TagFields::Parent(v)),
+                        _ => None,
+                    }
+                },
                 "name" => Some(TagFields::Name(String::from(v))),
                 _ => None,
             }
@@ -62,8 +101,8 @@ pub mod routes {
 
             let mut query = tag::table.into_boxed::<Mysql>();
             //page indexing
-            let start: i64 = params.start.unwrap_or(0);
-            let step: i64 = params.step.unwrap_or(100);
+            let start: i64 = params.start.unwrap_or(0).max(0);
+            let step: i64 = params.step.unwrap_or(100).clamp(0, QParams::MAX_STEP);
             query = query.offset(start);
             query = query.limit(step);
 
@@ -71,8 +110,11 @@ pub mod routes {
                 if let Some(query_parameter) = validation(f){
                     match query_parameter {
                         TagFields::Id(id) => query = query.or_filter(tag::id.eq(id)),
-                        TagFields::Name(name) => query = query.or_filter(tag::name.eq(name)),
+                        //Old names left behind by a merge or rename still find the tag
+                        TagFields::Name(name) => query = query.or_filter(tag::name.eq(name.clone()).or(tag::id.eq_any(
+                            tag_alias::table.filter(tag_alias::name.eq(helper::normalize_name(&name).to_lowercase())).select(tag_alias::tag_id)))),
                         TagFields::Owner(id) => query = query.or_filter( tag::id.eq_any( user_tags::table.filter( user_tags::user_id.eq(id)).select(user_tags::tag_id))),
+                        TagFields::Parent(id) => query = query.or_filter(tag::parent_id.eq(id)),
                     }
                 }
             }
@@ -133,6 +175,9 @@ pub mod routes {
                 }
             }
 
+            //Tags in the trash are left out whatever the filters matched
+            query = query.filter(tag::deleted_at.is_null());
+
             for o in params.order {
                 match o.as_str() {
                     "id" => query = query.then_order_by(tag::id.asc()),
@@ -148,39 +193,30 @@ pub mod routes {
         }).await 
     }
 
-    async fn retrieve_one_tag(post_id: i32, conn: &DbConn) -> Result< Vec<Tag>, status::Custom<Json<AResponse>> > {
+    async fn retrieve_one_tag(post_id: i32, conn: &DbConn) -> Result< Vec<Tag>, ApiError > {
         //Given an id, return a vec of Tag with a single entry or an error 404 / 500
         let q_params = QParams::new_filter(Filters::new_eq(vec![format!("id={}", post_id)]));
   
-        match parse_and_query(q_params, &conn).await {
-            Ok(tags) => {
-                match tags.len() {
-                    1 => Ok(tags),
-                    0 => Err(status::Custom(Status::NotFound, Json(AResponse::_404(
-                            Some(String::from("Could not locate tag with provided id.")))))),
-                    _ => Err(status::Custom(Status::InternalServerError, Json(AResponse::_500()))),
-                }
-            },
-            Err(e) => 
-                return Err(status::Custom(Status::InternalServerError, Json(AResponse::error(Some(json!([{"message":  format!("{:?}",e) }])))))),
+        let tags = parse_and_query(q_params, &conn).await?;
+        match tags.len() {
+            1 => Ok(tags),
+            0 => Err(ApiError::not_found(Some(String::from("Could not locate tag with provided id.")))),
+            _ => Err(ApiError::internal()),
         }
     }
 
-    #[get("/<id>")]
-    pub async fn get(id: i32, conn: DbConn) -> Result<Json<AResponse>, status::Custom<Json<AResponse>>> {
+    #[get("/<id>", rank = 2)]
+    pub async fn get(id: i32, conn: DbConn) -> Result<Cached<Json<AResponse>>, ApiError> {
         let q_params = QParams::new_filter(Filters::new_eq(vec![format!("id={}", id)]));
-        match parse_and_query(q_params, &conn).await {
-            Ok(tags) => match tags.len() {
-                0 => Err(status::Custom(Status::NotFound, Json(AResponse::_404(None)))),
-                _ => Ok(Json(AResponse::_200(Some(json!(tags))))),
-            }    
-            Err(e) => 
-                Err(status::Custom(Status::InternalServerError, Json(AResponse::error(Some(json!([{"message":  format!("{:?}",e) }])))))),
+        let tags = parse_and_query(q_params, &conn).await?;
+        match tags.first().map(|t| t.version) {
+            None => Err(ApiError::not_found(None)),
+            Some(version) => Ok(Cached::json(AResponse::_200(Some(json!(tags)))).versioned(version)),
         }
     }
 
-    #[get("/?<params..>")]
-    pub async fn get_users_tags(params: QParams, conn: DbConn) -> Result<Json<AResponse>, status::Custom<Json<AResponse>>> {
+    #[get("/?<params..>", rank = 2)]
+    pub async fn get_users_tags(params: QParams, conn: DbConn) -> Result<Cached<Json<AResponse>>, ApiError> {
         //Retrieve user's tags
         // let users_tags: Vec<i32> = 
         //     conn.run(move |c| {  
@@ -202,13 +238,8 @@ pub mod routes {
         //     params.filter.eq.push(format!("id={}", t));
         // }
 
-        match parse_and_query(params, &conn).await {
-            Ok(tags) => {
-                Ok(Json(AResponse::_200(Some(json!(tags)))))
-            },
-            Err(e) => 
-                Err(status::Custom(Status::InternalServerError, Json(AResponse::error(Some(json!([{"message":  format!("{:?}",e) }])))))),
-        }
+        let tags = parse_and_query(params, &conn).await?;
+        Ok(Cached::json(AResponse::_200(Some(json!(tags)))))
     }
 
     // #[get("/?<params..>", rank = 1)]
@@ -275,202 +306,682 @@ pub mod routes {
     //     }
     // }
 
-    #[post("/", format="json", data="<new_tag>")]
-    pub async fn post(conn: DbConn, new_tag: Json<NewTag>, user: ValidSession) -> Result<status::Created<String>, status::Custom<Json<AResponse>> > {
-        //Diesel does not have an error code for invalid input. Manually check.
-        if !(1..=100).contains(&new_tag.name.len()) {
-            return Err(status::Custom(Status::UnprocessableEntity, Json(AResponse::_422(
-                Some(String::from("Correct input and try again.")),
-                Some(String::from("INVALID_INPUT")),
-                Some(json!([{"field": "name", "message":  "Valid length is 1 to 100 chars."}]))))));
+    fn validate_user_input(new_tag: &NewTag) -> Result<(), ApiError> {
+        crate::tag::helper::validate_name(&new_tag.name)
+    }
+
+    //The writes behind the routes take a connection, POST /tags/bulk runs many of them in one transaction. See bulk.rs
+    fn insert_tag(c: &mut MysqlConnection, new_tag: &NewTag, user_id: i32, trail: &Trail) -> Result<i32, ApiError> {
+        validate_user_input(new_tag)?;
+
+        //An existing tag with the name is reused, the user only becomes an owner
+        if let Some(existing) = helper::find_by_name(c, &new_tag.name)? {
+            trail.watch_on(c, Resource::Tag, existing)?;
         }
 
-        let tag_name = new_tag.name.clone();
+        let tag_id = c.transaction(|c| crate::tag::helper::insert_owned_tag(c, &new_tag.name, user_id))?;
+        trail.created(Resource::Tag, tag_id);
+        Ok(tag_id)
+    }
 
-        match conn.run(move |c| {
-            c.transaction(|c| {
-                diesel::insert_or_ignore_into(tag::table)
-                    .values(&new_tag.into_inner())
-                    .execute(c)?;
+    pub async fn create_tag(conn: &DbConn, new_tag: NewTag, user: &ValidSession, trail: &Trail) -> Result<i32, ApiError> {
+        let (user_id, trail) = (user.id, trail.clone());
+        conn.run(move |c| insert_tag(c, &new_tag, user_id, &trail)).await
+    }
 
-                let tag_id: i32 = tag::table
-                .filter(tag::name.eq(&tag_name))
-                .select(tag::id)
-                .first(c)?;
-            
-                let new_user_tag = NewUserTag { user_id: user.id, tag_id: tag_id };
+    #[post("/", format="json", data="<new_tag>")]
+    pub async fn post(conn: DbConn, new_tag: Keyed<NewTag>, user: ValidSession, trail: &Trail) -> Result<status::Created<String>, ApiError > {
+        let tag_id = create_tag(&conn, new_tag.into_inner(), &user, trail).await?;
 
-                diesel::insert_into(user_tags::table)
-                .values(&new_user_tag)
-                .execute(c)?;
-                Ok(tag_id)
-            })
-            
- 
-        }).await {
-            Ok(tag_id) => {
-                let uri = uri!("/api/tags/", get(tag_id)).to_string();
-                let body = json!(AResponse::_201(Some(uri.clone()))).to_string();
-                Ok(status::Created::new(uri).body(body))
-            },
-            Err(DatabaseError(UniqueViolation, d)) => 
-                Err(status::Custom(Status::Conflict, Json(AResponse::_409(
-                    Some(String::from(d.message())))))),
-            
-            Err(DatabaseError(NotNullViolation, d)) => 
-                Err(status::Custom(Status::UnprocessableEntity, Json(AResponse::_422(
-                    Some(String::from(d.message())), 
-                    Some(String::from("NOT_NULL_VIOLATION")),
-                    None)))),
-                
-            Err(RollbackErrorOnCommit { rollback_error, commit_error }) => {
-                Err(status::Custom(Status::UnprocessableEntity, Json(AResponse::_422(
-                    Some(format!("{} + {}", rollback_error, commit_error)), 
-                    Some(String::from("ROLL_BACK_ERROR")),
-                    None))))
-            },
-    
-            Err(e) => 
-                Err(status::Custom(Status::InternalServerError, Json(AResponse::error(Some(json!([{"message":  format!("{:?}",e) }])))))),
-        }
+        let uri = uri!("/api/tags/", get(tag_id)).to_string();
+        let body = json!(AResponse::_201(Some(uri.clone()))).to_string();
+        Ok(status::Created::new(uri).body(body))
     }
 
-    #[patch("/<id>",  format="json", data="<new_tag>")]//Patch 204 400 404 422
-    pub async fn patch(id: i32, conn: DbConn, new_tag: Json<NewTag>, user: ValidSession) -> Result<status::NoContent, status::Custom<Json<AResponse>>> {
-        //Diesel does not have an error code for invalid input. Manually check.
-        if !(1..=100).contains(&new_tag.name.len()) {
-            return Err(status::Custom(Status::UnprocessableEntity, Json(AResponse::_422(
-                Some(String::from("Correct input and try again.")),
-                Some(String::from("INVALID_INPUT")),
-                Some(json!([{"field": "name", "message":  "Valid length is 1 to 100 chars."}]))))));
+    //Check If-Match against the stored version and take the next one. Only one writer can claim a given version.
+    fn claim_version(c: &mut MysqlConnection, id: i32, if_match: &IfMatch) -> Result<(), ApiError> {
+        let current = tag::table.filter(tag::id.eq(id)).filter(tag::deleted_at.is_null()).select(tag::version).first::<i32>(c)
+            .map_err(|e| match e {
+                NotFound => ApiError::not_found(Some(String::from("Could not locate tag with provided id."))),
+                e => ApiError::from(e),
+            })?;
+        if_match.check(current)?;
+
+        let rows = diesel::update(tag::table.filter(tag::id.eq(id)).filter(tag::version.eq(current)))
+            .set(tag::version.eq(tag::version + 1))
+            .execute(c)?;
+        match rows {
+            1 => Ok(()),
+            //Someone else wrote between the read and the update
+            _ => Err(ApiError::precondition_failed(current + 1)),
         }
-        
-        match conn.run(move |c| {
-
-            //Confirm user owns this tag
-            user::table
-                .inner_join(user_tags::table)
-                .filter(
-                    //user owns tag
-                    user::id.eq(user.id)
-                    .and(user_tags::tag_id.eq(id))
-                    //user is admin
-                    .or(
-                        user::id.eq(user.id)
-                        .and(user::role.eq(1))
-                    )
+    }
+
+    fn owned_by(c: &mut MysqlConnection, id: i32, user_id: i32) -> Result<(), ApiError> {
+        //Confirm user owns this tag
+        user::table
+            .inner_join(user_tags::table)
+            .filter(
+                //user owns tag
+                user::id.eq(user_id)
+                .and(user_tags::tag_id.eq(id))
+                //user is admin
+                .or(
+                    user::id.eq(user_id)
+                    .and(user::role.eq(1))
                 )
-                .select(user_tags::tag_id)
-                .first::<i32>(c)?;
-            
-            let updated_tag = UpdateTag {id, name: Some(new_tag.name.clone())};
-            diesel::update(&updated_tag).set(&updated_tag).execute(c)
+            )
+            .select(user_tags::tag_id)
+            .first::<i32>(c)
+            .map_err(|e| match e {
+                NotFound => ApiError::not_found(Some(String::from("Could not locate tag with the provided id that is owned by this user."))),
+                e => ApiError::from(e),
+            })?;
+        Ok(())
+    }
 
-            //println!("\n{}\n", diesel::debug_query::<Mysql , _>(&x));
-            //https://docs.diesel.rs/master/diesel/result/enum.Error.html
-        }).await {
-            Ok(rows) => {
-                match rows {
-                    1 => Ok(status::NoContent),
-                    0 => Err(status::Custom(Status::NotFound, Json(AResponse::_404(
-                            Some(String::from("Could not locate tag with provided id owned by this user.")))))),
-                    _ => Err(status::Custom(Status::InternalServerError, Json(AResponse::_500()))),
-                }
+    fn modify_tag(c: &mut MysqlConnection, id: i32, new_tag: &NewTag, user_id: i32, if_match: &IfMatch, trail: &Trail) -> Result<(), ApiError> {
+        validate_user_input(new_tag)?;
+        owned_by(c, id, user_id)?;
+
+        //Renaming onto another tag's name would bring the duplicates back. Those tags should be merged.
+        if let Some(other) = helper::find_by_name(c, &new_tag.name)?.filter(|other| *other != id) {
+            return Err(ApiError::conflict(Some(format!("The name is already used by tag {}. Merge the tags instead.", other))));
+        }
+
+        trail.watch_on(c, Resource::Tag, id)?;
+
+        let rows = c.transaction(|c| {
+            claim_version(c, id, if_match)?;
+
+            //The old name keeps resolving to this tag
+            let old_name = tag::table.filter(tag::id.eq(id)).select(tag::name).first::<String>(c)?;
+            if old_name.to_lowercase() != helper::normalize_name(&new_tag.name).to_lowercase() {
+                helper::add_alias(c, &old_name, id)?;
             }
 
-            Err(diesel::NotFound) => 
-                Err(status::Custom(Status::NotFound, Json(AResponse::_404(
-                    Some(String::from("Could not locate tag with the provided id that is owned by this user.")))))),
-            
-            Err(DatabaseError(UniqueViolation, d)) => 
-                Err(status::Custom(Status::UnprocessableEntity, Json(AResponse::_422(
-                    Some(String::from(d.message())), 
-                    Some(String::from("UNIQUE_VIOLATION")),
-                    None)))),
-            
-            Err(DatabaseError(NotNullViolation, d)) => 
-                Err(status::Custom(Status::UnprocessableEntity, Json(AResponse::_422(
-                    Some(String::from(d.message())), 
-                    Some(String::from("NOT_NULL_VIOLATION")),
-                    None)))),
-            
-            Err(QueryBuilderError(_)) => 
-                Err(status::Custom(Status::BadRequest, Json(AResponse::_400(
-                    Some(String::from("You need to provide the 'name' in the body of your request.")))))),
+            let updated_tag = UpdateTag {id, name: Some(helper::normalize_name(&new_tag.name))};
+            diesel::update(&updated_tag).set(&updated_tag).execute(c).map_err(ApiError::from)
+
+            //println!("\n{}\n", diesel::debug_query::<Mysql , _>(&x));
+            //https://docs.diesel.rs/master/diesel/result/enum.Error.html
+        })?;
 
-            Err(e) => 
-                Err(status::Custom(Status::InternalServerError, Json(AResponse::error(Some(json!([{"message":  format!("{:?}",e) }])))))),
+        match rows {
+            1 => Ok(()),
+            0 => Err(ApiError::not_found(Some(String::from("Could not locate tag with provided id owned by this user.")))),
+            _ => Err(ApiError::internal()),
         }
     }
 
-    #[delete("/<id>")]//Delete 204 400 404 422
-    pub async fn delete(id: i32, conn: DbConn, user: ValidSession) -> Result< Json<AResponse>, status::Custom<Json<AResponse>> > {
+    pub async fn update_tag(conn: &DbConn, id: i32, new_tag: NewTag, user: &ValidSession, if_match: &IfMatch, trail: &Trail) -> Result<(), ApiError> {
+        let (user_id, if_match, trail) = (user.id, if_match.clone(), trail.clone());
+        conn.run(move |c| modify_tag(c, id, &new_tag, user_id, &if_match, &trail)).await
+    }
+
+    #[patch("/<id>",  format="json", data="<new_tag>")]//Patch 204 400 404 422
+    pub async fn patch(id: i32, conn: DbConn, new_tag: Json<NewTag>, user: ValidSession, if_match: IfMatch, trail: &Trail) -> Result<status::NoContent, ApiError> {
+        update_tag(&conn, id, new_tag.into_inner(), &user, &if_match, trail).await?;
+        Ok(status::NoContent)
+    }
+
+    //The tag goes to the trash and stays on its posts, hidden, until it is restored or purged. See trash.rs.
+    fn trash_tag(c: &mut MysqlConnection, id: i32, if_match: &IfMatch, trail: &Trail) -> Result<Value, ApiError> {
         //Retrieve the target tag
-        let target_tag = retrieve_one_tag(id, &conn).await?;
+        let target_tag = tag::table.filter(tag::id.eq(id)).filter(tag::deleted_at.is_null()).select(Tag::as_select()).first::<Tag>(c)
+            .map_err(|e| match e {
+                NotFound => ApiError::not_found(Some(String::from("Could not locate tag with provided id."))),
+                e => ApiError::from(e),
+            })?;
+        trail.watch_on(c, Resource::Tag, id)?;
 
         let mut d = json!(
             {
-                "name": target_tag[0].name, 
-                "id": target_tag[0].id, 
+                "name": target_tag.name, 
+                "id": target_tag.id, 
                 "was on blogs": 0
             }
         );
 
-        //Remove the associated blog_tags for the tag
-        let blog_tags_count = match crate::post_tags::delete_entries(&conn, crate::post_tags::BelongsTo::Tag(target_tag)).await {
-            Ok(ok) => ok,
-            Err(e) => 
-                return Err(status::Custom(Status::InternalServerError, Json(AResponse::error(Some(json!([{"message":  format!("{:?}",e) }])))))),
-        };
+        let blog_tags_count = c.transaction(|c| {
+            claim_version(c, id, if_match)?;
+            let count = post_tags::table.filter(post_tags::tag_id.eq(id)).count().get_result::<i64>(c)?;
+            diesel::update(tag::table.filter(tag::id.eq(id)))
+                .set(tag::deleted_at.eq(diesel::dsl::now))
+                .execute(c)?;
+            Ok::<_, ApiError>(count)
+        })?;
 
-        //Now remove the tag
-        match conn.run(move |c|{
-            diesel::delete(tag::table.filter(tag::id.eq(id))).execute(c)
-        }).await {
-            Ok(_) => {
-                d["Affected posts"] = json!(blog_tags_count);
-                return Ok(Json(AResponse::_200(Some(d))));
-            },
-            Err(e) => 
-                return Err(status::Custom(Status::InternalServerError, Json(AResponse::error(Some(json!([{"message":  format!("{:?}",e) }])))))),
+        d["Affected posts"] = json!(blog_tags_count);
+        Ok(d)
+    }
+
+    pub async fn delete_tag(conn: &DbConn, id: i32, if_match: &IfMatch, trail: &Trail) -> Result<Value, ApiError> {
+        let (if_match, trail) = (if_match.clone(), trail.clone());
+        conn.run(move |c| trash_tag(c, id, &if_match, &trail)).await
+    }
+
+    #[delete("/<id>")]//Delete 204 400 404 422
+    pub async fn delete(id: i32, conn: DbConn, _user: ValidSession, if_match: IfMatch, trail: &Trail) -> Result< Json<AResponse>, ApiError > {
+        let d = delete_tag(&conn, id, &if_match, trail).await?;
+        Ok(Json(AResponse::_200(Some(d))))
+    }
+
+    //Fold duplicate tags such as "rust" and "Rust" into one. The sources' names become aliases of the target.
+    #[post("/<id>/merge", format="json", data="<merge>")]//Post 200 400 404 412 422
+    pub async fn merge(id: i32, conn: DbConn, merge: Keyed<MergeTags>, _x: Level1, if_match: IfMatch, trail: &Trail) -> Result< Json<AResponse>, ApiError > {
+        let target_tag = retrieve_one_tag(id, &conn).await?;
+        let mut requested = merge.into_inner().sources;
+        requested.sort_unstable();
+        requested.dedup();
+        if requested.is_empty() || requested.contains(&id) {
+            return Err(ApiError::invalid_input(json!([{"field": "sources", "message": "List at least one tag and not the target itself."}])));
+        }
+        for watched in std::iter::once(id).chain(requested.iter().copied()) {
+            trail.watch(&conn, Resource::Tag, watched).await?;
         }
+
+        let target_name = target_tag[0].name.to_lowercase();
+        let (sources, affected) = conn.run(move |c| {
+            c.transaction::<_, ApiError, _>(|c| {
+                claim_version(c, id, &if_match)?;
+                let sources = tag::table
+                    .filter(tag::id.eq_any(&requested))
+                    .filter(tag::deleted_at.is_null())
+                    .select((tag::id, tag::name))
+                    .load::<(i32, String)>(c)?;
+                let missing = requested.iter()
+                    .filter(|r| !sources.iter().any(|(s, _)| s == *r))
+                    .map(|r| json!({"field": "sources", "message": format!("No tag with id {}.", r)}))
+                    .collect::<Vec<_>>();
+                if !missing.is_empty() {
+                    return Err(ApiError::invalid_input(json!(missing)));
+                }
+
+                let mut affected = 0;
+                for (source, name) in &sources {
+                    //A post or user that already has the target keeps its existing row
+                    let tagged = post_tags::table.filter(post_tags::tag_id.eq(id)).select(post_tags::post_id).load::<i32>(c)?;
+                    affected += diesel::delete(post_tags::table.filter(post_tags::tag_id.eq(source)).filter(post_tags::post_id.eq_any(&tagged))).execute(c)?;
+                    affected += diesel::update(post_tags::table.filter(post_tags::tag_id.eq(source)))
+                        .set(post_tags::tag_id.eq(id))
+                        .execute(c)?;
+
+                    let owners = user_tags::table.filter(user_tags::tag_id.eq(id)).select(user_tags::user_id).load::<i32>(c)?;
+                    diesel::delete(user_tags::table.filter(user_tags::tag_id.eq(source)).filter(user_tags::user_id.eq_any(&owners))).execute(c)?;
+                    diesel::update(user_tags::table.filter(user_tags::tag_id.eq(source)))
+                        .set(user_tags::tag_id.eq(id))
+                        .execute(c)?;
+
+                    //Names that led to the source now lead to the target
+                    diesel::update(tag_alias::table.filter(tag_alias::tag_id.eq(source)))
+                        .set(tag_alias::tag_id.eq(id))
+                        .execute(c)?;
+                    if name.to_lowercase() != target_name {
+                        helper::add_alias(c, name, id)?;
+                    }
+
+                    //Moving children up, not onto the target, cannot create a cycle when the target sits below the source
+                    helper::lift_children(c, *source)?;
+                    diesel::delete(tag::table.filter(tag::id.eq(source))).execute(c)?;
+                }
+                Ok((sources, affected))
+            })
+        }).await?;
+
+        let merged = sources.into_iter().map(|(id, name)| json!({"id": id, "name": name})).collect::<Vec<_>>();
+        let d = json!(
+            {
+                "name": target_tag[0].name,
+                "id": id,
+                "merged": merged,
+                "Affected posts": affected
+            }
+        );
+        Ok(Json(AResponse::_200(Some(d))))
+    }
+
+    async fn tag_stats(conn: &DbConn) -> QueryResult<Vec<TagStats>> {
+        use diesel::dsl::{count_distinct, max, min};
+        //The post route shadows the table inside this module
+        use crate::schema::post;
+
+        conn.run(|c| {
+            let tags = tag::table.filter(tag::deleted_at.is_null()).order(tag::name.asc()).load::<Tag>(c)?;
+
+            //One row for each tag that is on at least one post
+            let mut usage = post_tags::table
+                .inner_join(post::table)
+                .filter(post::deleted_at.is_null())
+                .group_by(post_tags::tag_id)
+                .select((post_tags::tag_id, count_distinct(post_tags::post_id), min(post::created), max(post::created)))
+                .load::<(i32, i64, Option<chrono::NaiveDateTime>, Option<chrono::NaiveDateTime>)>(c)?
+                .into_iter()
+                .map(|(tag_id, posts, first, last)| (tag_id, (posts, first, last)))
+                .collect::<HashMap<_, _>>();
+
+            let mut owners: HashMap<i32, Vec<i32>> = HashMap::new();
+            let pairs = user_tags::table
+                .select((user_tags::tag_id, user_tags::user_id))
+                .order(user_tags::user_id.asc())
+                .load::<(i32, i32)>(c)?;
+            for (tag_id, user_id) in pairs {
+                owners.entry(tag_id).or_default().push(user_id);
+            }
+
+            Ok(tags.into_iter().map(|t| {
+                let (posts, first_used, last_used) = usage.remove(&t.id).unwrap_or((0, None, None));
+                TagStats { id: t.id, name: t.name, parent_id: t.parent_id, posts, first_used, last_used, owners: owners.remove(&t.id).unwrap_or_default() }
+            }).collect())
+        }).await
+    }
+
+    //Tags in use, by name, each with a weight from 1 to buckets. The scale is logarithmic so one very popular tag does not flatten the rest.
+    fn tag_cloud(stats: Vec<TagStats>, buckets: u8) -> Vec<Value> {
+        let mut used = stats.into_iter().filter(|s| s.posts > 0).collect::<Vec<TagStats>>();
+        used.sort_by_key(|s| s.name.to_lowercase());
+
+        let least = used.iter().map(|s| s.posts).min().unwrap_or(1) as f64;
+        let most = used.iter().map(|s| s.posts).max().unwrap_or(1) as f64;
+        used.into_iter()
+            .map(|s| {
+                let weight = match most > least {
+                    true => 1 + (((s.posts as f64).ln() - least.ln()) / (most.ln() - least.ln()) * (buckets - 1) as f64).round() as u8,
+                    false => 1,
+                };
+                json!({"id": s.id, "name": s.name, "posts": s.posts, "weight": weight})
+            })
+            .collect()
     }
 
-    #[get("/<id>/posts")]
-    pub async fn get_posts(id: i32, conn: DbConn) -> Result<Json<AResponse>, status::Custom<Json<AResponse>>> {
+    #[get("/stats?<sort>&<limit>&<cloud>&<buckets>", rank = 0)]
+    pub async fn get_stats(sort: Option<String>, limit: Option<usize>, cloud: Option<bool>, buckets: Option<u8>, conn: DbConn) -> Result<Cached<Json<AResponse>>, ApiError> {
+        let buckets = buckets.unwrap_or(5);
+        if !(1..=10).contains(&buckets) {
+            return Err(ApiError::bad_request(Some(String::from("buckets must be between 1 and 10."))));
+        }
+
+        let mut stats = tag_stats(&conn).await?;
+        //Ties keep the name order from the query
+        match sort.as_deref().unwrap_or("-usage") {
+            "usage" => stats.sort_by_key(|s| s.posts),
+            "-usage" => stats.sort_by_key(|s| std::cmp::Reverse(s.posts)),
+            "last_used" => stats.sort_by_key(|s| s.last_used),
+            "-last_used" => stats.sort_by_key(|s| std::cmp::Reverse(s.last_used)),
+            "name" => {},
+            "-name" => stats.reverse(),
+            other => return Err(ApiError::bad_request(Some(format!("Cannot sort by '{}'. Use usage, last_used or name, with - for descending.", other)))),
+        }
+        if let Some(limit) = limit {
+            stats.truncate(limit);
+        }
+
+        match cloud.unwrap_or(false) {
+            true => Ok(Cached::json(AResponse::_200(Some(json!(tag_cloud(stats, buckets)))))),
+            false => Ok(Cached::json(AResponse::_200(Some(json!(stats))))),
+        }
+    }
+
+    #[get("/<id>/children")]
+    pub async fn get_children(id: i32, conn: DbConn) -> Result<Cached<Json<AResponse>>, ApiError> {
+        retrieve_one_tag(id, &conn).await?;
+        let mut q_params = QParams::new_filter(Filters::new_eq(vec![format!("parent={}", id)]));
+        q_params.order = vec![String::from("name")];
+        let tags = parse_and_query(q_params, &conn).await?;
+        Ok(Cached::json(AResponse::_200(Some(json!(tags)))))
+    }
+
+    //From the root down to the direct parent
+    #[get("/<id>/ancestors")]
+    pub async fn get_ancestors(id: i32, conn: DbConn) -> Result<Cached<Json<AResponse>>, ApiError> {
+        retrieve_one_tag(id, &conn).await?;
+        let tags = conn.run(move |c| helper::ancestors(c, id)).await?;
+        Ok(Cached::json(AResponse::_200(Some(json!(tags)))))
+    }
+
+    //Moving a tag takes its whole subtree along. A null parent makes it a root.
+    #[put("/<id>/parent", format="json", data="<target>")]//Put 204 404 412 422
+    pub async fn put_parent(id: i32, conn: DbConn, target: Json<MoveTag>, user: ValidSession, if_match: IfMatch, trail: &Trail) -> Result<status::NoContent, ApiError> {
+        let parent_id = target.into_inner().parent_id;
+        let user_id = user.id;
+        if let Some(parent_id) = parent_id {
+            retrieve_one_tag(parent_id, &conn).await
+                .map_err(|_| ApiError::invalid_input(json!([{"field": "parent_id", "message": format!("No tag with id {}.", parent_id)}])))?;
+        }
+
+        trail.watch(&conn, Resource::Tag, id).await?;
+        conn.run(move |c| {
+            c.transaction(|c| {
+                owned_by(c, id, user_id)?;
+                claim_version(c, id, &if_match)?;
+                if let Some(parent_id) = parent_id {
+                    if helper::lock_ancestors(c, parent_id)?.contains(&id) {
+                        return Err(ApiError::invalid_input(json!([{"field": "parent_id", "message": "A tag cannot be moved below itself or one of its descendants."}])));
+                    }
+                }
+                diesel::update(tag::table.filter(tag::id.eq(id)))
+                    .set(tag::parent_id.eq(parent_id))
+                    .execute(c)?;
+                Ok::<_, ApiError>(())
+            })
+        }).await?;
+        Ok(status::NoContent)
+    }
+
+    #[get("/<id>/posts?<fields>&<include>&<descendants>", rank = 2)]
+    pub async fn get_posts(id: i32, fields: Option<String>, include: Option<String>, descendants: Option<bool>, conn: DbConn) -> Result<Cached<Json<AResponse>>, ApiError> {
+        let listing = crate::post::routes::Listing::parse(fields, include)?;
+
         //Retrieve the target tag
         let target_tag = retrieve_one_tag(id, &conn).await?;
 
-        //Retrieve the post ids that have the specified tag
-        match conn.run(move |c|{
-            let post_ids = match BlogTags::belonging_to(&target_tag).select(post_tags::post_id).distinct().load::<i32>(c) {
-                Ok(post_ids) => post_ids,
-                Err(e) => return Err(status::Custom(Status::InternalServerError, Json(AResponse::error(Some(json!([{"message":  format!("{:?}",e) }])))))),
+        //Retrieve the post ids that have the specified tag, or any tag below it
+        let post_ids = conn.run(move |c|{
+            let tag_ids = match descendants.unwrap_or(false) {
+                true => helper::descendant_ids(c, id)?,
+                false => vec![target_tag[0].id],
             };
-            let q = post_ids.into_iter().map(|id| format!("id={id}")).collect::<Vec<String>>();
-            let q_params = QParams::new_filter(Filters::new_eq(q));
-            Ok(q_params)
-        }).await {
-            Ok(q_params) => match crate::post::routes::post_and_tags(q_params, &conn).await
-            {
-                Ok(posts) =>  Ok(Json(AResponse::_200(Some(json!(posts))))),                
-                Err(e) => return Err(status::Custom(Status::InternalServerError, Json(AResponse::error(Some(json!([{"message":  format!("{:?}",e) }])))))),
+            post_tags::table.filter(post_tags::tag_id.eq_any(tag_ids)).select(post_tags::post_id).distinct().load::<i32>(c)
+        }).await?;
+
+        let q = post_ids.into_iter().map(|id| format!("id={id}")).collect::<Vec<String>>();
+        let q_params = QParams::new_filter(Filters::new_eq(q));
+        if let Some(listing) = listing {
+            let posts = crate::post::routes::post_listing(q_params, &listing, &conn).await?;
+            return Ok(Cached::json(AResponse::_200(Some(json!(posts)))));
+        }
+        let posts = crate::post::routes::post_and_tags(q_params, &conn).await?;
+        Ok(Cached::json(AResponse::_200(Some(json!(posts)))))
+    }
+
+
+    //One entry of POST /tags/bulk, see bulk.rs
+    #[derive(serde::Deserialize, schemars::JsonSchema)]
+    #[serde(tag = "op", rename_all = "snake_case")]
+    pub enum TagOperation {
+        Create { tag: NewTag },
+        Patch { id: i32, tag: NewTag, version: Option<i32> },
+        Delete { id: i32, version: Option<i32> },
+    }
+
+    fn apply(c: &mut MysqlConnection, op: TagOperation, user_id: i32, require_if_match: bool, trail: &Trail) -> Result<Done, ApiError> {
+        match op {
+            TagOperation::Create { tag } => insert_tag(c, &tag, user_id, trail).map(|id| Done::created(Some(id))),
+            TagOperation::Patch { id, tag, version } => {
+                modify_tag(c, id, &tag, user_id, &IfMatch::from_version(version, require_if_match), trail)?;
+                Ok(Done::applied(id))
+            },
+            TagOperation::Delete { id, version } => {
+                let d = trash_tag(c, id, &IfMatch::from_version(version, require_if_match), trail)?;
+                Ok(Done::with(id, d))
             },
-            Err(e) => 
-                Err(status::Custom(Status::InternalServerError, Json(AResponse::error(Some(json!([{"message":  format!("{:?}",e) }])))))),
         }
     }
 
+    #[post("/bulk", format="json", data="<bulk>")]
+    pub async fn bulk(bulk: Keyed<Bulk<TagOperation>>, conn: DbConn, user: ValidSession, env: &State<EnvVariables>, trail: &Trail) -> Result<Outcome, ApiError> {
+        bulk.check(&env.bulk)?;
+        let (user_id, require_if_match, trail) = (user.id, env.require_if_match, trail.clone());
+        conn.run(move |c| {
+            crate::bulk::run(c, bulk.into_inner(), &trail, |c, op, trail| apply(c, op, user_id, require_if_match, trail))
+        }).await
+    }
+
+    //JSON:API representation of tags. The plain routes above are used unless the client asks for application/vnd.api+json.
+    async fn tags_document(tags: Vec<Tag>, includes: &Includes, fields: &Fieldsets, conn: &DbConn) -> Result<(Vec<Value>, Vec<Value>), ApiError> {
+        //Which posts carry each tag
+        let tag_ids = tags.iter().map(|t| t.id).collect::<Vec<i32>>();
+        let pairs = conn.run(move |c| {
+            post_tags::table
+                .filter(post_tags::tag_id.eq_any(tag_ids))
+                .select((post_tags::tag_id, post_tags::post_id))
+                .load::<(i32, i32)>(c)
+        }).await?;
+
+        let mut posts_of: HashMap<i32, Vec<i32>> = HashMap::new();
+        for (tag_id, post_id) in pairs {
+            posts_of.entry(tag_id).or_default().push(post_id);
+        }
+
+        let mut included = Vec::new();
+        if includes.contains("posts") {
+            let mut post_ids = posts_of.values().flatten().copied().collect::<Vec<i32>>();
+            post_ids.sort_unstable();
+            post_ids.dedup();
+            if !post_ids.is_empty() {
+                let q = post_ids.into_iter().map(|id| format!("id={id}")).collect::<Vec<String>>();
+                let mut q_params = QParams::new_filter(Filters::new_eq(q.clone()));
+                q_params.step = Some(q.len() as i64);
+                let posts = crate::post::routes::post_and_tags(q_params, conn).await?;
+                let (posts, _) = crate::post::routes::posts_document(posts, &Includes::parse(None, &[])?, fields, conn).await?;
+                included = posts;
+            }
+        }
+
+        let data = tags.iter()
+            .map(|t| ResourceObject::new("tags", t.id, t)
+                .without("parent_id")
+                .to_one("parent", "tags", t.parent_id)
+                .to_many("posts", "posts", posts_of.remove(&t.id).unwrap_or_default(), Some(format!("/api/tags/{}/posts", t.id)))
+                .into_value(fields))
+            .collect();
+        Ok((data, included))
+    }
+
+    async fn tag_document(id: i32, includes: &Includes, fields: &Fieldsets, conn: &DbConn) -> Result<(Value, i32), ApiError> {
+        let tags = retrieve_one_tag(id, conn).await?;
+        let version = tags[0].version;
+        let (mut data, included) = tags_document(tags, includes, fields, conn).await?;
+        Ok((Document::single(data.remove(0), included), version))
+    }
+
+    #[get("/?<include>&<fields>&<params..>", rank = 1)]
+    pub async fn get_tags_json_api(include: Option<String>, fields: Option<HashMap<String, String>>, params: QParams, _j: JsonApiRequest, conn: DbConn) -> Result<Cached<Document>, ApiError> {
+        let includes = Includes::parse(include, &["posts"])?;
+        let fields = Fieldsets::parse(fields);
+        let tags = parse_and_query(params, &conn).await?;
+        let (data, included) = tags_document(tags, &includes, &fields, &conn).await?;
+        Ok(Cached::document(Document::ok(Document::collection(data, included))))
+    }
+
+    #[get("/<id>?<include>&<fields>", rank = 1)]
+    pub async fn get_json_api(id: i32, include: Option<String>, fields: Option<HashMap<String, String>>, _j: JsonApiRequest, conn: DbConn) -> Result<Cached<Document>, ApiError> {
+        let includes = Includes::parse(include, &["posts"])?;
+        let fields = Fieldsets::parse(fields);
+        let (body, version) = tag_document(id, &includes, &fields, &conn).await?;
+        Ok(Cached::document(Document::ok(body)).versioned(version))
+    }
+
+    #[get("/<id>/posts?<include>&<fields>", rank = 1)]
+    pub async fn get_posts_json_api(id: i32, include: Option<String>, fields: Option<HashMap<String, String>>, _j: JsonApiRequest, conn: DbConn) -> Result<Cached<Document>, ApiError> {
+        let includes = Includes::parse(include, &["tags", "author"])?;
+        let fields = Fieldsets::parse(fields);
+        let target_tag = retrieve_one_tag(id, &conn).await?;
+
+        let post_ids = conn.run(move |c|{
+            BlogTags::belonging_to(&target_tag).select(post_tags::post_id).distinct().load::<i32>(c)
+        }).await?;
+
+        let q = post_ids.into_iter().map(|id| format!("id={id}")).collect::<Vec<String>>();
+        let q_params = QParams::new_filter(Filters::new_eq(q));
+        let posts = crate::post::routes::post_and_tags(q_params, &conn).await?;
+        let (data, included) = crate::post::routes::posts_document(posts, &includes, &fields, &conn).await?;
+        Ok(Cached::document(Document::ok(Document::collection(data, included))))
+    }
+
+    #[post("/", format="application/vnd.api+json", data="<doc>")]
+    pub async fn post_json_api(conn: DbConn, doc: Keyed<WriteDocument<NewTag>>, user: ValidSession, trail: &Trail) -> Result<Document, ApiError> {
+        let doc = doc.into_inner();
+        doc.data.check("tags", None)?;
+
+        let tag_id = create_tag(&conn, doc.data.attributes, &user, trail).await?;
+        let (body, _) = tag_document(tag_id, &Includes::parse(None, &[])?, &Fieldsets::parse(None), &conn).await?;
+        Ok(Document::created(body, uri!("/api/tags/", get(tag_id)).to_string()))
+    }
+
+    #[patch("/<id>", format="application/vnd.api+json", data="<doc>")]
+    pub async fn patch_json_api(id: i32, conn: DbConn, doc: Json<WriteDocument<NewTag>>, user: ValidSession, if_match: IfMatch, trail: &Trail) -> Result<Document, ApiError> {
+        let doc = doc.into_inner();
+        doc.data.check("tags", Some(id))?;
+
+        update_tag(&conn, id, doc.data.attributes, &user, &if_match, trail).await?;
+        let (body, _) = tag_document(id, &Includes::parse(None, &[])?, &Fieldsets::parse(None), &conn).await?;
+        Ok(Document::ok(body))
+    }
 }
 
 pub mod helper {
     use super::*;
+
+    diesel::sql_function!(fn lower(x: diesel::sql_types::Text) -> diesel::sql_types::Text);
+    diesel::sql_function!(fn last_insert_id() -> diesel::sql_types::Unsigned<diesel::sql_types::BigInt>);
+
+    //"  Rust   lang " is stored as "Rust lang". Case is kept for display, lookups ignore it.
+    pub fn normalize_name(name: &str) -> String {
+        name.split_whitespace().collect::<Vec<&str>>().join(" ")
+    }
+
+    pub fn validate_name(name: &str) -> Result<(), ApiError> {
+        //Diesel does not have an error code for invalid input. Manually check.
+        if !(1..=100).contains(&normalize_name(name).len()) {
+            return Err(ApiError::invalid_input(json!([{"field": "name", "message":  "Valid length is 1 to 100 chars."}])));
+        }
+        Ok(())
+    }
+
+    //The tag a name refers to, ignoring case. A tag's own name wins over an alias left behind by a merge or rename.
+    pub fn find_by_name(c: &mut MysqlConnection, name: &str) -> QueryResult<Option<i32>> {
+        let name = normalize_name(name).to_lowercase();
+        let existing = tag::table
+            .filter(lower(tag::name).eq(&name))
+            .filter(tag::deleted_at.is_null())
+            .select(tag::id)
+            .order(tag::id.asc())
+            .first::<i32>(c)
+            .optional()?;
+        match existing {
+            Some(id) => Ok(Some(id)),
+            None => tag_alias::table
+                .inner_join(tag::table)
+                .filter(tag_alias::name.eq(&name))
+                .filter(tag::deleted_at.is_null())
+                .select(tag_alias::tag_id)
+                .first::<i32>(c)
+                .optional(),
+        }
+    }
+
+    //Keep an old name pointing at a tag. An alias that already points elsewhere is left alone.
+    pub fn add_alias(c: &mut MysqlConnection, name: &str, tag_id: i32) -> QueryResult<usize> {
+        diesel::insert_or_ignore_into(tag_alias::table)
+            .values((tag_alias::name.eq(normalize_name(name).to_lowercase()), tag_alias::tag_id.eq(tag_id)))
+            .execute(c)
+    }
+
+    //Create a tag and record who owns it. Run inside a transaction, the caller decides what commits with it.
+    //A name that already exists in any case, or as an alias, reuses that tag instead of adding a duplicate.
+    pub fn insert_owned_tag(c: &mut MysqlConnection, name: &str, user_id: i32) -> QueryResult<i32> {
+        let tag_id = match find_by_name(c, name)? {
+            Some(tag_id) => tag_id,
+            None => {
+                diesel::insert_into(tag::table)
+                    .values(tag::name.eq(normalize_name(name)))
+                    .execute(c)?;
+
+                diesel::select(last_insert_id()).first::<u64>(c)? as i32
+            },
+        };
+    
+        let new_user_tag = NewUserTag { user_id, tag_id: tag_id };
+
+        diesel::insert_or_ignore_into(user_tags::table)
+        .values(&new_user_tag)
+        .execute(c)?;
+        Ok(tag_id)
+    }
+
+    //Parents of a tag, root first. Stops at a repeated id so a cycle written outside the api cannot loop forever.
+    //A parent in the trash ends the chain, the tag shows as a root until the parent is restored or purged.
+    pub fn ancestors(c: &mut MysqlConnection, id: i32) -> QueryResult<Vec<Tag>> {
+        let mut chain: Vec<Tag> = Vec::new();
+        let mut next = tag::table.filter(tag::id.eq(id)).select(tag::parent_id).first::<Option<i32>>(c)?;
+        while let Some(parent_id) = next {
+            if parent_id == id || chain.iter().any(|t| t.id == parent_id) {
+                break;
+            }
+            let parent = match tag::table.filter(tag::id.eq(parent_id)).filter(tag::deleted_at.is_null()).first::<Tag>(c).optional()? {
+                Some(parent) => parent,
+                None => break,
+            };
+            next = parent.parent_id;
+            chain.push(parent);
+        }
+        chain.reverse();
+        Ok(chain)
+    }
+
+    //The tag itself and every tag below it, one level per query
+    pub fn descendant_ids(c: &mut MysqlConnection, id: i32) -> QueryResult<Vec<i32>> {
+        let mut subtree = vec![id];
+        let mut level = vec![id];
+        while !level.is_empty() {
+            level = children(std::mem::take(&mut level))
+                .load::<i32>(c)?
+                .into_iter()
+                .filter(|t| !subtree.contains(t))
+                .collect();
+            subtree.extend(&level);
+        }
+        Ok(subtree)
+    }
+
+    //The tags right below the given ones. A tag in the trash is left out and with it what is below it, as in ancestors.
+    pub fn children(level: Vec<i32>) -> tag::BoxedQuery<'static, diesel::mysql::Mysql, diesel::sql_types::Integer> {
+        tag::table
+            .filter(tag::parent_id.eq_any(level))
+            .filter(tag::deleted_at.is_null())
+            .select(tag::id)
+            .into_boxed()
+    }
+
+    //The tag and every tag above it, read with FOR UPDATE. Two moves that together would make a loop both lock the
+    //chain they would join, so the second waits for the first and then sees its result.
+    pub fn lock_ancestors(c: &mut MysqlConnection, id: i32) -> QueryResult<Vec<i32>> {
+        let mut chain = Vec::new();
+        let mut next = Some(id);
+        while let Some(current) = next.filter(|n| !chain.contains(n)) {
+            chain.push(current);
+            next = tag::table.filter(tag::id.eq(current)).select(tag::parent_id).for_update().first::<Option<i32>>(c)?;
+        }
+        Ok(chain)
+    }
+
+    //Hand the children of a tag that is about to go to its own parent
+    pub fn lift_children(c: &mut MysqlConnection, id: i32) -> QueryResult<usize> {
+        let parent_id = tag::table.filter(tag::id.eq(id)).select(tag::parent_id).first::<Option<i32>>(c)?;
+        diesel::update(tag::table.filter(tag::parent_id.eq(id)))
+            .set(tag::parent_id.eq(parent_id))
+            .execute(c)
+    }
+
     //mysql does not return an id after creating a new entry. This helper function does only that.
     pub async fn get_a_tag_id( conn: &DbConn, name: &String) -> QueryResult<i32> {
         let name = name.clone();
-        conn.run(  |c| tag::table
-                .filter(tag::name.eq(name))
-                .select(tag::id)
-                .first::<i32>(c)).await
+        conn.run(move |c| find_by_name(c, &name)?.ok_or(diesel::result::Error::NotFound)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::helper::children;
+    use diesel::debug_query;
+    use diesel::mysql::Mysql;
+
+    //GET /api/tags/<id>/posts?descendants=true does not reach through a tag in the trash
+    #[test]
+    fn descendants_leave_out_trashed_tags() {
+        let sql = debug_query::<Mysql, _>(&children(vec![1, 2])).to_string();
+        assert!(sql.contains("`tag`.`parent_id` IN (?, ?)"), "{}", sql);
+        assert!(sql.contains("`tag`.`deleted_at` IS NULL"), "{}", sql);
     }
-}
\ No newline at end of file
+}
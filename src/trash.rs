@@ -0,0 +1,258 @@
+use std::time::Duration;
+use rocket::serde::Deserialize;
+use rocket::serde::json::{Json, Value, json};
+use rocket::{Orbit, Rocket};
+use diesel::prelude::*;
+use crate::audit::{self, Resource, Trail};
+use crate::config::DbConn;
+use crate::error::ApiError;
+use crate::models::{AResponse, EnvVariables};
+use crate::schema::{post, post_tags, tag, user};
+
+/*
+DELETE on a post, tag or user sets its deleted_at instead of removing the row. Rows in the trash are left out of
+every listing and lookup, and can be brought back with POST /api/trash/<type>/<id>/restore.
+
+[default.trash]
+retention_days = 30          How long an item stays in the trash before it is removed for good.
+purge_every_minutes = 60     How often the purge job looks for expired items. It also runs once at start up.
+
+A trashed post keeps its tags and media, a trashed tag stays on its posts and keeps its children, they show as
+roots until the tag is back. Purging removes the links the way a delete used to: a purged tag's children move up
+to its parent.
+A trashed post does not hold its title, a trashed user their email or a trashed tag its name, the unique keys
+leave the trash out. A restore is refused with a 409 while a live post, user or tag carries them.
+Sessions a trashed user already has keep working until they expire, the user cannot log in again.
+*/
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(crate = "rocket::serde")]
+pub struct TrashConfig {
+    #[serde(default = "TrashConfig::default_retention")]
+    pub retention_days: i64,
+    #[serde(default = "TrashConfig::default_interval")]
+    pub purge_every_minutes: u64,
+}
+
+impl Default for TrashConfig {
+    fn default() -> Self {
+        TrashConfig {
+            retention_days: TrashConfig::default_retention(),
+            purge_every_minutes: TrashConfig::default_interval(),
+        }
+    }
+}
+
+impl TrashConfig {
+    fn default_retention() -> i64 {
+        30
+    }
+
+    fn default_interval() -> u64 {
+        60
+    }
+}
+
+//deleted_at is set with the database's NOW(), so the cutoff is read from the same clock
+fn cutoff(c: &mut MysqlConnection, retention_days: i64) -> QueryResult<chrono::NaiveDateTime> {
+    Ok(crate::webhook::db_now(c)? - chrono::Duration::days(retention_days))
+}
+
+//Remove every post, tag and user that has been in the trash longer than the retention. Returns how many of each.
+pub fn purge(c: &mut MysqlConnection, retention_days: i64) -> QueryResult<(usize, usize, usize)> {
+    let cutoff = cutoff(c, retention_days)?;
+
+    let posts = post::table.filter(post::deleted_at.lt(cutoff)).select(post::id).load::<i32>(c)?;
+    for id in &posts {
+        let id = *id;
+        let before = audit::snapshot(c, Resource::Post, id)?;
+        c.transaction(|c| {
+            diesel::delete(post_tags::table.filter(post_tags::post_id.eq(id))).execute(c)?;
+            //Series parts and media links go with the post
+            diesel::delete(post::table.filter(post::id.eq(id))).execute(c)?;
+            audit::purged(c, Resource::Post, id, before)
+        })?;
+    }
+
+    let tags = tag::table.filter(tag::deleted_at.lt(cutoff)).select(tag::id).load::<i32>(c)?;
+    for id in &tags {
+        let id = *id;
+        let before = audit::snapshot(c, Resource::Tag, id)?;
+        c.transaction(|c| {
+            diesel::delete(post_tags::table.filter(post_tags::tag_id.eq(id))).execute(c)?;
+            crate::tag::helper::lift_children(c, id)?;
+            //Owners and aliases go with the tag
+            diesel::delete(tag::table.filter(tag::id.eq(id))).execute(c)?;
+            audit::purged(c, Resource::Tag, id, before)
+        })?;
+    }
+
+    let users = user::table.filter(user::deleted_at.lt(cutoff)).select(user::id).load::<i32>(c)?;
+    for id in &users {
+        let id = *id;
+        let before = audit::snapshot(c, Resource::User, id)?;
+        c.transaction(|c| {
+            //Their tag ownerships go with them, their uploads stay without an uploader
+            diesel::delete(user::table.filter(user::id.eq(id))).execute(c)?;
+            audit::purged(c, Resource::User, id, before)
+        })?;
+    }
+
+    Ok((posts.len(), tags.len(), users.len()))
+}
+
+//Started once the server is up, runs until it shuts down
+pub async fn start_purge_job(rocket: &Rocket<Orbit>) {
+    let config = match rocket.state::<EnvVariables>() {
+        Some(env) => env.trash.clone(),
+        None => return,
+    };
+    let pool = match DbConn::pool(rocket) {
+        Some(pool) => pool.clone(),
+        None => {
+            error!("Trash: no database pool, the purge job is not running.");
+            return;
+        },
+    };
+
+    rocket::tokio::spawn(async move {
+        let mut interval = rocket::tokio::time::interval(Duration::from_secs(config.purge_every_minutes.max(1) * 60));
+        loop {
+            interval.tick().await;
+            let conn = match pool.get().await {
+                Some(conn) => conn,
+                None => {
+                    error!("Trash: no database connection, the purge is skipped.");
+                    continue;
+                },
+            };
+            let retention_days = config.retention_days;
+            match conn.run(move |c| purge(c, retention_days)).await {
+                Ok((0, 0, 0)) => (),
+                Ok((posts, tags, users)) => info!("Trash: purged {} posts, {} tags and {} users.", posts, tags, users),
+                Err(e) => error!("Trash: purge failed: {:?}", e),
+            }
+        }
+    });
+}
+
+pub mod routes {
+    use super::*;
+    use crate::auth::Level1;
+
+    fn resource(kind: &str) -> Result<Resource, ApiError> {
+        match kind {
+            "posts" => Ok(Resource::Post),
+            "tags" => Ok(Resource::Tag),
+            "users" => Ok(Resource::User),
+            _ => Err(ApiError::not_found(Some(String::from("The trash holds posts, tags and users.")))),
+        }
+    }
+
+    //When each item will be purged
+    fn item(id: i32, label: (&str, String), deleted_at: Option<chrono::NaiveDateTime>, retention_days: i64) -> Value {
+        let mut v = json!({
+            "id": id,
+            "deleted_at": deleted_at,
+            "purge_after": deleted_at.map(|d| d + chrono::Duration::days(retention_days)),
+        });
+        v[label.0] = json!(label.1);
+        v
+    }
+
+    //Most recently deleted first. ?kind=posts, tags or users lists only that kind.
+    #[get("/?<kind>")]
+    pub async fn get_all(kind: Option<String>, conn: DbConn, env: &rocket::State<EnvVariables>, _x: Level1) -> Result<Json<AResponse>, ApiError> {
+        let only = kind.map(|k| resource(&k)).transpose()?;
+        let retention_days = env.trash.retention_days;
+        let wanted = move |r: Resource| match only {
+            Some(only) => only == r,
+            None => true,
+        };
+
+        let d = conn.run(move |c| {
+            let mut d = json!({});
+            if wanted(Resource::Post) {
+                let posts = post::table
+                    .filter(post::deleted_at.is_not_null())
+                    .order(post::deleted_at.desc())
+                    .select((post::id, post::title, post::deleted_at))
+                    .load::<(i32, String, Option<chrono::NaiveDateTime>)>(c)?;
+                d["posts"] = json!(posts.into_iter().map(|(id, title, at)| item(id, ("title", title), at, retention_days)).collect::<Vec<Value>>());
+            }
+            if wanted(Resource::Tag) {
+                let tags = tag::table
+                    .filter(tag::deleted_at.is_not_null())
+                    .order(tag::deleted_at.desc())
+                    .select((tag::id, tag::name, tag::deleted_at))
+                    .load::<(i32, String, Option<chrono::NaiveDateTime>)>(c)?;
+                d["tags"] = json!(tags.into_iter().map(|(id, name, at)| item(id, ("name", name), at, retention_days)).collect::<Vec<Value>>());
+            }
+            if wanted(Resource::User) {
+                let users = user::table
+                    .filter(user::deleted_at.is_not_null())
+                    .order(user::deleted_at.desc())
+                    .select((user::id, user::email, user::deleted_at))
+                    .load::<(i32, String, Option<chrono::NaiveDateTime>)>(c)?;
+                d["users"] = json!(users.into_iter().map(|(id, email, at)| item(id, ("email", email), at, retention_days)).collect::<Vec<Value>>());
+            }
+            QueryResult::Ok(d)
+        }).await?;
+        Ok(Json(AResponse::_200(Some(d))))
+    }
+
+    //Posts and tags get a new version, an ETag taken before the delete no longer matches
+    #[post("/<kind>/<id>/restore")]//Post 200 404 409
+    pub async fn restore(kind: String, id: i32, conn: DbConn, _x: Level1, trail: &Trail) -> Result<Json<AResponse>, ApiError> {
+        let resource = resource(&kind)?;
+        trail.watch(&conn, resource, id).await?;
+
+        let rows = conn.run(move |c| {
+            c.transaction::<_, ApiError, _>(|c| {
+                let rows = match resource {
+                    Resource::Post => {
+                        let held = post::table.filter(post::id.eq(id)).filter(post::deleted_at.is_not_null()).select((post::title, post::author)).first::<(String, String)>(c).optional()?;
+                        if let Some((title, author)) = held {
+                            let taken = post::table.filter(post::title.eq(&title)).filter(post::author.eq(&author)).filter(post::deleted_at.is_null()).select(post::id).first::<i32>(c).optional()?;
+                            if taken.is_some() {
+                                return Err(ApiError::conflict(Some(format!("{} already has a post titled '{}'. Rename it first.", author, title))));
+                            }
+                        }
+                        diesel::update(post::table.filter(post::id.eq(id)).filter(post::deleted_at.is_not_null()))
+                            .set((post::deleted_at.eq(None::<chrono::NaiveDateTime>), post::version.eq(post::version + 1)))
+                            .execute(c)?
+                    },
+                    Resource::Tag => {
+                        let name = tag::table.filter(tag::id.eq(id)).filter(tag::deleted_at.is_not_null()).select(tag::name).first::<String>(c).optional()?;
+                        if let Some(name) = name {
+                            if crate::tag::helper::find_by_name(c, &name)?.is_some() {
+                                return Err(ApiError::conflict(Some(format!("A tag named '{}' already exists. Rename or merge it first.", name))));
+                            }
+                        }
+                        diesel::update(tag::table.filter(tag::id.eq(id)).filter(tag::deleted_at.is_not_null()))
+                            .set((tag::deleted_at.eq(None::<chrono::NaiveDateTime>), tag::version.eq(tag::version + 1)))
+                            .execute(c)?
+                    },
+                    _ => {
+                        let held = user::table.filter(user::id.eq(id)).filter(user::deleted_at.is_not_null()).select(user::email).first::<String>(c).optional()?;
+                        if let Some(email) = held {
+                            let taken = user::table.filter(user::email.eq(&email)).filter(user::deleted_at.is_null()).select(user::id).first::<i32>(c).optional()?;
+                            if taken.is_some() {
+                                return Err(ApiError::conflict(Some(format!("A user with the email '{}' already exists. Change it first.", email))));
+                            }
+                        }
+                        diesel::update(user::table.filter(user::id.eq(id)).filter(user::deleted_at.is_not_null()))
+                            .set(user::deleted_at.eq(None::<chrono::NaiveDateTime>))
+                            .execute(c)?
+                    },
+                };
+                Ok(rows)
+            })
+        }).await?;
+
+        match rows {
+            0 => Err(ApiError::not_found(Some(format!("No {} with id {} in the trash.", kind, id)))),
+            _ => Ok(Json(AResponse::_200(Some(json!({"type": kind, "id": id, "restored": true}))))),
+        }
+    }
+}
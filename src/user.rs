@@ -1,6 +1,10 @@
-use rocket::serde::json::{Json, Value, json};
+use std::collections::HashMap;
+use rocket::serde::json::{Json, json};
 use diesel::prelude::*;
 use crate::config::DbConn;
+use crate::error::ApiError;
+use crate::idempotency::Keyed;
+use crate::myjsonapi::{Document, Fieldsets, Includes, JsonApiRequest, ResourceObject};
 use crate::models::{NewUser, User, AResponse};
 use crate::schema::{user, role};
 use crate::pw::get_phc;
@@ -8,7 +12,6 @@ use rocket::http::{Cookie, CookieJar, Status};
 use rocket::response::status;
 use rocket::Request;
 //use rocket::form::Form;
-use crate::myjsonapi::{JSONAPIError,};
 use rocket::State;
 use crate::models::EnvVariables;
 //use rocket::response::Redirect;
@@ -17,42 +20,29 @@ use crate::models::EnvVariables;
 
 pub mod routes {
     use crate::{auth::{Level1, ValidSession, StandardUser, AdminUser}, jwt::get_jwt};
+    use crate::audit::{Resource, Trail};
     use super::*;
 
     #[catch(422)]
-    pub fn dup_entry() -> Value {
-        json!(format!("Ensure email is unique and role is valid."))
+    pub fn dup_entry() -> ApiError {
+        ApiError::new(Status::UnprocessableEntity, "UNPROCESSABLE_ENTITY", Some(String::from("Ensure email is unique and role is valid.")))
     }
 
     #[catch(default)]
-    pub fn catch_all(status: Status, _req: &Request) -> Value {
-        let message = &mut JSONAPIError{
-            status: status.code.to_string(), 
-            canonical: String::from(status.reason().unwrap()), 
-            title: String::from(""),
-            detail: String::from("")};
-        
+    pub fn catch_all(status: Status, _req: &Request) -> ApiError {
         match status.code {
-            401 => {
-                message.title = String::from("Session token missing or invalid.");
-                message.detail = String::from("The JWT is not present or is no longer valid.");
-            }
-            _ => {
-                message.title = String::from("A error was generated but it had an unantacipated code.");
-                message.detail = String::from("The back end of the application generated an unexpected non 200 response.");
-            }
+            401 => ApiError::unauthorized(Some(String::from("Session token missing or invalid. The JWT is not present or is no longer valid."))),
+            _ => ApiError::from_status(status),
         }
-        
-        json!({"errors": vec![message]})
     }
 
-    #[derive(serde::Deserialize, Clone)]
+    #[derive(serde::Deserialize, Clone, schemars::JsonSchema)]
     pub struct Login {
         email: String,
         password: String,
     }
 
-    #[derive(serde::Deserialize)]
+    #[derive(serde::Deserialize, schemars::JsonSchema)]
     pub struct CreateNewUser {
         pub email: String,
         pub pass: String,
@@ -62,7 +52,7 @@ pub mod routes {
         pub active: bool,
     }
  
-    #[derive(Debug, FromForm, serde::Deserialize, AsChangeset)]
+    #[derive(Debug, FromForm, serde::Deserialize, AsChangeset, schemars::JsonSchema)]
     #[diesel(table_name = user)]
     pub struct UpdateUser {
         pub email: Option<String>,
@@ -74,7 +64,7 @@ pub mod routes {
         pub active: Option<bool>,
     }
 
-    #[derive(Debug, FromForm, serde::Deserialize, AsChangeset)]
+    #[derive(Debug, FromForm, serde::Deserialize, AsChangeset, schemars::JsonSchema)]
     #[diesel(table_name = user)]
     pub struct UpdateUserNoRole {
         pub email: Option<String>,
@@ -95,7 +85,7 @@ pub mod routes {
         }
     }
 
-    #[derive(Debug, Queryable, Selectable, serde::Serialize)]
+    #[derive(Debug, Queryable, Selectable, serde::Serialize, schemars::JsonSchema)]
     #[diesel(table_name = user)]
     pub struct UserWithoutPHC {
         pub id: i32,
@@ -122,7 +112,7 @@ pub mod routes {
 
 
     #[patch("/", format = "json", data="<updated_user>")]
-    pub async fn update_self(conn: DbConn, user_session: Result<ValidSession, status::Custom<Json<AResponse>>>, mut updated_user: Json<UpdateUserNoRole>) -> Result<Status, status::Custom<Json<AResponse>>> {
+    pub async fn update_self(conn: DbConn, user_session: Result<ValidSession, ApiError>, mut updated_user: Json<UpdateUserNoRole>, trail: &Trail) -> Result<Status, ApiError> {
         // All users can update their data.
 
         //Verify user has a ValidSession
@@ -130,42 +120,45 @@ pub mod routes {
     
         //I don't expect an empty json set. But don't want to return a 500 if they manage to send me one somehow.
         if updated_user.is_all_none() {return Ok(Status::NoContent)};
+        trail.watch(&conn, Resource::User, user.id).await?;
 
         //If a new pw was sent, calculate phc first.
         if updated_user.phc.is_some() {
             let pass = updated_user.phc.clone().unwrap();
             match get_phc(pass) {
                 Ok(user_phc) => updated_user.phc = Some(user_phc),
-                Err(_) => return Err(status::Custom(Status::InternalServerError, Json(AResponse::_500()))), //There was a problem calculating the user's phc.
+                Err(e) => return Err(ApiError::internal_from(e)), //There was a problem calculating the user's phc.
             }
         };
 
         let updated_row_count = match conn.run(move |c| {
             diesel::update(user::table)
             .filter(user::id.eq(user.id))
+            .filter(user::deleted_at.is_null())
             .set(updated_user.into_inner())
             .execute(c)
         }).await {
             Ok(c) => c, //return Ok(status::NoContent),
-            Err(_) => return Err(status::Custom(Status::InternalServerError, Json(AResponse::_500()))), //There was a problem updating the user.
+            Err(e) => return Err(ApiError::internal_from(e)), //There was a problem updating the user.
         };
 
         match updated_row_count {
             0 => return Ok(Status::NotFound),
             1 => return Ok(Status::NoContent),
-            _ => return Err(status::Custom(Status::InternalServerError, Json(AResponse::_500()))), //Needs to be updated to notify client that more than 1 record was updated.
+            _ => return Err(ApiError::internal()), //Needs to be updated to notify client that more than 1 record was updated.
         }
     } 
 
     #[patch("/<id>", format = "json", data="<updated_user>")]
-    pub async fn update_user(id: i32, conn: DbConn, mut updated_user: Json<UpdateUser>, __: AdminUser) -> Result<Status, status::Custom<Json<AResponse>>> {
+    pub async fn update_user(id: i32, conn: DbConn, mut updated_user: Json<UpdateUser>, __: AdminUser, trail: &Trail) -> Result<Status, ApiError> {
         //An admin can update anyone's profile.
+        trail.watch(&conn, Resource::User, id).await?;
         //If a new pw was sent, calculate phc first.
         if updated_user.phc.is_some() {
             let pass = updated_user.phc.clone().unwrap();
             match get_phc(pass) {
                 Ok(user_phc) => updated_user.phc = Some(user_phc),
-                Err(_) => return Err(status::Custom(Status::InternalServerError, Json(AResponse::_500()))), //There was a problem updating the user's pw.
+                Err(e) => return Err(ApiError::internal_from(e)), //There was a problem updating the user's pw.
             }
         };
 
@@ -175,36 +168,37 @@ pub mod routes {
         let updated_row_count = match conn.run(move |c| {
             diesel::update(user::table)
             .filter(user::id.eq(id))
+            .filter(user::deleted_at.is_null())
             .set(updated_user.into_inner())
             .execute(c)
         }).await {
             Ok(c) => c, //return Ok(status::NoContent),
-            Err(_) => return Err(status::Custom(Status::InternalServerError, Json(AResponse::_500()))), //There was a problem updating the user.
+            Err(e) => return Err(ApiError::internal_from(e)), //There was a problem updating the user.
         };
         match updated_row_count {
             0 => return Ok(Status::NotFound),
             1 => return Ok(Status::NoContent),
-            _ => return Err(status::Custom(Status::InternalServerError, Json(AResponse::_500()))), //Needs to be updated to notify client that more than 1 record was updated.
+            _ => return Err(ApiError::internal()), //Needs to be updated to notify client that more than 1 record was updated.
       }
     } 
 
     #[patch("/<_id>", rank = 2)]
-    pub async fn update_user_forbidden(_id: i32, __: StandardUser) -> status::Custom<Json<AResponse>> {
-        status::Custom(Status::Forbidden, Json(AResponse::_403(None)))
+    pub async fn update_user_forbidden(_id: i32, __: StandardUser) -> ApiError {
+        ApiError::forbidden(None)
     }
 
     #[patch("/<_id>", rank = 3)]
-    pub async fn update_user_unauthorized(_id: i32) -> status::Custom<Json<AResponse>> {
-        status::Custom(Status::Forbidden, Json(AResponse::_401(None)))
+    pub async fn update_user_unauthorized(_id: i32) -> ApiError {
+        ApiError::unauthorized(None)
     }
 
-    #[derive(Debug, serde::Deserialize)]
+    #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
     pub struct ConfirmPW {
         password: String,
     }
 
     #[post("/confirm_pw", format = "json", data="<confirm_pw>")]
-    pub async fn confirm_pw(confirm_pw: Json<ConfirmPW>, conn:DbConn, user: ValidSession) -> Result<Status, status::Custom<Json<AResponse>>> {
+    pub async fn confirm_pw(confirm_pw: Keyed<ConfirmPW>, conn:DbConn, user: ValidSession) -> Result<Status, ApiError> {
         //Whatever a user passes in as data is interpreted as a pw value.
         //A user must have a session (ValidSession guard).
         //Using the session user_id, check if pw is valid.
@@ -218,21 +212,22 @@ pub mod routes {
             }).await
             {
                 Ok(u_phc) => u_phc,
-                Err(_) => return Err(status::Custom(Status::InternalServerError, Json(AResponse::_500()))) //User has session but cannot be found in db?!
+                Err(e) => return Err(ApiError::internal_from(e)) //User has session but cannot be found in db?!
                 //Err(_) => return Err(status::Custom(Status::Unauthorized, Json(AResponse::_401(Some(String::from("Provided password did not match user's current pw."))))))
             };
         match crate::pw::verify_password(&confirm_pw.password, &user_phc.unwrap_or_default()) {
-                Ok(_) => {println!("Sending 204");return Ok(Status::NoContent)}, //User provided pw is valid.
-                Err(_) => {println!("Sending 401");return Err(status::Custom(Status::Unauthorized, Json(AResponse::_401(Some(String::from("Existing password was invalid."))))))}, //Provided pw was invalid
+                Ok(_) => return Ok(Status::NoContent), //User provided pw is valid.
+                Err(_) => return Err(ApiError::unauthorized(Some(String::from("Existing password was invalid.")))), //Provided pw was invalid
             }
             
     }
 
-    #[get("/list_of_all_users")]
-    pub async fn list_of_all_users(conn:DbConn, user: AdminUser) -> Result<Json<AResponse>, status::Custom<Json<AResponse>>> {
+    #[get("/list_of_all_users", rank = 1)]
+    pub async fn list_of_all_users(conn:DbConn, user: AdminUser) -> Result<Json<AResponse>, ApiError> {
         match conn.run(move |c: &mut MysqlConnection| {
             user::table
                 .filter(user::id.ne(user.id))
+                .filter(user::deleted_at.is_null())
                 .select(UserWithoutPHC::as_select())
                 .load(c)
             }).await
@@ -247,25 +242,26 @@ pub mod routes {
                 
                 return Ok(Json(AResponse::_200(Some(json!(users)))))
             },
-            Err(_) => return Err(status::Custom(Status::InternalServerError, Json(AResponse::_500()))), //There was a problem retrieving the user.
+            Err(e) => return Err(ApiError::internal_from(e)), //There was a problem retrieving the user.
         };
     }
     
     #[get("/list_of_all_users", rank = 2)]
-    pub async fn list_of_all_users_forbidden(__: StandardUser) -> status::Custom<Json<AResponse>> {
-        status::Custom(Status::Forbidden, Json(AResponse::_403(None)))
+    pub async fn list_of_all_users_forbidden(__: StandardUser) -> ApiError {
+        ApiError::forbidden(None)
     }
 
     #[get("/list_of_all_users", rank = 3)]
-    pub async fn list_of_all_users_unauthorized() -> status::Custom<Json<AResponse>> {
-        status::Custom(Status::Unauthorized, Json(AResponse::_401(None)))
+    pub async fn list_of_all_users_unauthorized() -> ApiError {
+        ApiError::unauthorized(None)
     }
 
-    #[get("/<id>")]
-    pub async fn get_user_by_id(id: i32, conn:DbConn, _user: AdminUser) -> Result<Json<AResponse>, status::Custom<Json<AResponse>>> {
+    #[get("/<id>", rank = 1)]
+    pub async fn get_user_by_id(id: i32, conn:DbConn, _user: AdminUser) -> Result<Json<AResponse>, ApiError> {
         match conn.run(move |c: &mut MysqlConnection| {
             user::table
                 .filter(user::id.eq(id))
+                .filter(user::deleted_at.is_null())
                 .select(UserWithoutPHC::as_select())
                 .first(c)
             }).await
@@ -273,25 +269,27 @@ pub mod routes {
             Ok(entry) => {
                 return Ok(Json(AResponse::_200(Some(json!(entry)))))
             },
-            Err(_) => return Err(status::Custom(Status::InternalServerError, Json(AResponse::_500()))), //There was a problem retrieving the user.
+            Err(diesel::result::Error::NotFound) => return Err(ApiError::not_found(Some(String::from("Could not locate user with provided id.")))),
+            Err(e) => return Err(ApiError::internal_from(e)), //There was a problem retrieving the user.
         };
     }
 
     #[get("/<_id>", rank=4)]
-    pub async fn get_user_by_id_forbidden(_id: i32, _user: ValidSession) -> status::Custom<Json<AResponse>> {
-        status::Custom(Status::Forbidden, Json(AResponse::_403(None)))
+    pub async fn get_user_by_id_forbidden(_id: i32, _user: ValidSession) -> ApiError {
+        ApiError::forbidden(None)
     }
 
     #[get("/<_id>", rank=5)]
-    pub async fn get_user_by_id_unauthorized(_id: i32) -> status::Custom<Json<AResponse>> {
-        status::Custom(Status::Unauthorized, Json(AResponse::_401(None)))
+    pub async fn get_user_by_id_unauthorized(_id: i32) -> ApiError {
+        ApiError::unauthorized(None)
     }
 
-    #[get("/")]
-    pub async fn get_user_admin(conn:DbConn, user: AdminUser) -> Result<Json<AResponse>, status::Custom<Json<AResponse>>> {
+    #[get("/", rank = 1)]
+    pub async fn get_user_admin(conn:DbConn, user: AdminUser) -> Result<Json<AResponse>, ApiError> {
         match conn.run(move |c: &mut MysqlConnection| {
             user::table
                 .filter(user::id.eq(user.id))
+                .filter(user::deleted_at.is_null())
                 .select(UserWithoutPHC::as_select())
                 .first(c)
             }).await
@@ -306,15 +304,17 @@ pub mod routes {
                 
                 return Ok(Json(AResponse::_200(Some(json!(entry)))))
             },
-            Err(_) => return Err(status::Custom(Status::InternalServerError, Json(AResponse::_500()))), //There was a problem retrieving the user.
+            Err(diesel::result::Error::NotFound) => return Err(ApiError::not_found(Some(String::from("Could not locate user with provided id.")))),
+            Err(e) => return Err(ApiError::internal_from(e)), //There was a problem retrieving the user.
         };
     }
 
     #[get("/", rank=2)]
-    pub async fn get_user(conn:DbConn, user: StandardUser) -> Result<Json<AResponse>, status::Custom<Json<AResponse>>> {
+    pub async fn get_user(conn:DbConn, user: StandardUser) -> Result<Json<AResponse>, ApiError> {
         match conn.run(move |c: &mut MysqlConnection| {
             user::table
                 .filter(user::id.eq(user.id))
+                .filter(user::deleted_at.is_null())
                 .select(UserWithoutPHC::as_select())
                 .first(c)
             }).await
@@ -322,17 +322,64 @@ pub mod routes {
             Ok(user) => {                
                 return Ok(Json(AResponse::_200(Some(json!(user)))))
             },
-            Err(_) => return Err(status::Custom(Status::InternalServerError, Json(AResponse::_500()))), //There was a problem retrieving the user.
+            Err(diesel::result::Error::NotFound) => return Err(ApiError::not_found(Some(String::from("Could not locate user with provided id.")))),
+            Err(e) => return Err(ApiError::internal_from(e)), //There was a problem retrieving the user.
         };
     }
 
     #[get("/", rank=3)]
-    pub async fn get_user_unauthorized() -> status::Custom<Json<AResponse>> {
-        status::Custom(Status::Unauthorized, Json(AResponse::_401(None)))
+    pub async fn get_user_unauthorized() -> ApiError {
+        ApiError::unauthorized(None)
+    }
+
+    //JSON:API representation of users. The role becomes a relationship instead of an attribute.
+    fn user_resource(user: &UserWithoutPHC) -> ResourceObject {
+        ResourceObject::new("users", user.id, user)
+            .without("role")
+            .to_one("role", "roles", Some(user.role))
+    }
+
+    async fn load_users(conn: &DbConn, filter_self: Option<i32>, only: Option<i32>) -> Result<Vec<UserWithoutPHC>, ApiError> {
+        conn.run(move |c: &mut MysqlConnection| {
+            let mut query = user::table.filter(user::deleted_at.is_null()).select(UserWithoutPHC::as_select()).into_boxed();
+            if let Some(id) = filter_self {
+                query = query.filter(user::id.ne(id));
+            }
+            if let Some(id) = only {
+                query = query.filter(user::id.eq(id));
+            }
+            query.load(c)
+        }).await.map_err(ApiError::from)
+    }
+
+    async fn user_document(conn: &DbConn, id: i32, include: Option<String>, fields: Option<HashMap<String, String>>) -> Result<Document, ApiError> {
+        Includes::parse(include, &[])?;
+        let fields = Fieldsets::parse(fields);
+        let user = load_users(conn, None, Some(id)).await?.pop().ok_or(ApiError::not_found(None))?;
+        Ok(Document::ok(Document::single(user_resource(&user).into_value(&fields), Vec::new())))
+    }
+
+    #[get("/?<include>&<fields>", rank = 0)]
+    pub async fn get_user_json_api(include: Option<String>, fields: Option<HashMap<String, String>>, _j: JsonApiRequest, conn: DbConn, user: StandardUser) -> Result<Document, ApiError> {
+        user_document(&conn, user.id, include, fields).await
+    }
+
+    #[get("/<id>?<include>&<fields>", rank = 0)]
+    pub async fn get_user_by_id_json_api(id: i32, include: Option<String>, fields: Option<HashMap<String, String>>, _j: JsonApiRequest, conn: DbConn, _user: AdminUser) -> Result<Document, ApiError> {
+        user_document(&conn, id, include, fields).await
+    }
+
+    #[get("/list_of_all_users?<include>&<fields>", rank = 0)]
+    pub async fn list_of_all_users_json_api(include: Option<String>, fields: Option<HashMap<String, String>>, _j: JsonApiRequest, conn: DbConn, user: AdminUser) -> Result<Document, ApiError> {
+        Includes::parse(include, &[])?;
+        let fields = Fieldsets::parse(fields);
+        let users = load_users(&conn, Some(user.id), None).await?;
+        let data = users.iter().map(|u| user_resource(u).into_value(&fields)).collect();
+        Ok(Document::ok(Document::collection(data, Vec::new())))
     }
 
     #[post("/", format = "json", data="<new_user>")]//
-    pub async fn add_user(conn: DbConn, new_user: Json<CreateNewUser>, _x: Level1) -> Result<status::Created<String>, status::Custom<Json<AResponse>>> {
+    pub async fn add_user(conn: DbConn, new_user: Keyed<CreateNewUser>, _x: Level1, trail: &Trail) -> Result<status::Created<String>, ApiError> {
         //TODO check that pass meets minimum criteria (length, uppper, number, etc)
         //TODO verify that email is valid format
 
@@ -351,7 +398,7 @@ pub mod routes {
 
         match get_phc(new_user.pass.clone()) {
             Ok(user_phc) => user.phc = Some(user_phc),
-            Err(_) => return Err(status::Custom(Status::InternalServerError, Json(AResponse::_500()))), //There was a problem creating the phc.
+            Err(e) => return Err(ApiError::internal_from(e)), //There was a problem creating the phc.
         }
 
         /* 
@@ -360,26 +407,32 @@ pub mod routes {
             Err(_) => { println!("here"); return Err(Status::UnprocessableEntity) }
         } */
 
-        match conn.run(|c| {
+        let email = user.email.clone();
+        match conn.run(move |c| {
                     diesel::insert_into(user::table)
                     .values(user)
-                    .execute(c)
+                    .execute(c)?;
+                    user::table.filter(user::email.eq(email)).filter(user::deleted_at.is_null()).select(user::id).first::<i32>(c)
                 }).await {
-            Ok(_) => Ok(status::Created::new(String::new())),
-            Err(e) => Err(status::Custom(Status::UnprocessableEntity, Json(AResponse::_422(
-                Some(e.to_string()), 
-                Some(String::from("INVALID_FIELD")),
-                None)))),
+            Ok(id) => {
+                trail.created(Resource::User, id);
+                Ok(status::Created::new(String::new()))
+            },
+            Err(e) => Err(ApiError::from(e)),
             }
 
     }
 
+    //The user goes to the trash and can no longer log in. See trash.rs for restoring and purging.
     #[delete("/<id>")]
-    pub async fn delete_user(id: i32, conn: DbConn, __: AdminUser) -> Result<Status, status::Custom<Value>> {
+    pub async fn delete_user(id: i32, conn: DbConn, __: AdminUser, trail: &Trail) -> Result<Status, ApiError> {
+        trail.watch(&conn, Resource::User, id).await?;
         match conn.run(move |c| {
-            diesel::delete(user::table
+            diesel::update(user::table
                 .filter(user::id.eq(id))
+                .filter(user::deleted_at.is_null())
             )
+            .set(user::deleted_at.eq(diesel::dsl::now))
             .execute(c)
             }).await
         {
@@ -387,27 +440,25 @@ pub mod routes {
                 match count {
                     1 => return Ok(Status::NoContent),
                     0 => return Ok(Status::NotFound),
-                    _ => return Err(status::Custom(Status::InternalServerError, 
-                        json!(format!("Should have only deleted up to 1 record, but deleted {}!", count)))),
+                    _ => return Err(ApiError::internal_from(format!("Should have only deleted up to 1 record, but deleted {}!", count))),
                 }
             },
-            Err(e) => return Err(status::Custom(Status::InternalServerError, 
-                json!(format!("Diesel returned an error: {}", e))))
+            Err(e) => return Err(ApiError::from(e))
         }
     }
 
     #[delete("/<_id>", rank = 2)]
-    pub async fn delete_user_forbidden(_id: i32, __: StandardUser) -> status::Custom<Json<AResponse>> {
-        status::Custom(Status::Forbidden, Json(AResponse::_403(None)))
+    pub async fn delete_user_forbidden(_id: i32, __: StandardUser) -> ApiError {
+        ApiError::forbidden(None)
     }
 
     #[delete("/<_id>", rank = 3)]
-    pub async fn delete_user_unauthorized(_id: i32) -> status::Custom<Json<AResponse>> {
-        status::Custom(Status::Unauthorized, Json(AResponse::_401(None)))
+    pub async fn delete_user_unauthorized(_id: i32) -> ApiError {
+        ApiError::unauthorized(None)
     }
 
     #[post("/session", format = "json", data="<login>")]
-    pub async fn start_session(conn: DbConn, login: Json<Login>, jar: &CookieJar<'_>, server_env_vars: &State<EnvVariables>) -> Result<Status, status::Custom<Json<AResponse>>> {
+    pub async fn start_session(conn: DbConn, login: Json<Login>, jar: &CookieJar<'_>, server_env_vars: &State<EnvVariables>) -> Result<Status, ApiError> {
         let email_clone = login.email.clone();
         let (user, role) = match //Retrieve a user object and the user objects corresponding user_role
             conn.run( move |conn| {
@@ -415,16 +466,17 @@ pub mod routes {
                 .left_join(role::table)
                 .select((User::as_select(), role::user_role.nullable()))
                 .filter(user::email.eq(email_clone))
+                .filter(user::deleted_at.is_null())
                 .first::<(User, Option<String>)>(conn)
             }).await
         {
             Ok((user, role)) =>       
                 match crate::pw::verify_password(&login.password, &user.phc.clone().unwrap_or_default()) {
                     Ok(_) => (user, role), //provided email and pw are good
-                    Err(_) => return Err(status::Custom(Status::Unauthorized, Json(AResponse::_401(Some(String::from("Provided email or password was invalid.")))))), //Provided pw was invalid
+                    Err(_) => return Err(ApiError::unauthorized(Some(String::from("Provided email or password was invalid.")))), //Provided pw was invalid
                 },
             Err(_) => 
-                return Err(status::Custom(Status::Unauthorized, Json(AResponse::_401(Some(String::from("Provided email or password was invalid.")))))), //Provided email was invalid
+                return Err(ApiError::unauthorized(Some(String::from("Provided email or password was invalid.")))), //Provided email was invalid
         };
 
         match get_jwt(&user, role.unwrap().as_str(), server_env_vars.jwt_secret.as_ref()) {
@@ -439,10 +491,10 @@ pub mod routes {
                     }).await
                 {
                     Ok(_) => return Ok(Status::Ok), // return 200
-                    Err(_) => return Err(status::Custom(Status::InternalServerError, Json(AResponse::_500()))), //There was a problem updating the last access column.
+                    Err(e) => return Err(ApiError::internal_from(e)), //There was a problem updating the last access column.
                 };
             },
-            Err(_) => return Err(status::Custom(Status::InternalServerError, Json(AResponse::_500()))), //There was a problem creating the jwt.
+            Err(e) => return Err(ApiError::internal_from(e)), //There was a problem creating the jwt.
         }
 
     }
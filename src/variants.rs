@@ -0,0 +1,296 @@
+use std::io::{self, Cursor};
+use std::path::{Path, PathBuf};
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::imageops::FilterType;
+use image::metadata::Orientation;
+use image::{DynamicImage, ImageDecoder, ImageReader};
+use rand_core::{OsRng, RngCore};
+use rocket::serde::json::{Value, json};
+use rocket::tokio::fs;
+use crate::media::MediaConfig;
+use crate::models::Media;
+
+/*
+Resized copies of uploaded images for the blog index and srcset.
+
+[default.media]
+variant_widths = [320, 640, 1280]      Widths in pixels. Only these can be requested.
+variant_formats = ["webp", "jpeg"]
+jpeg_quality = 80
+eager_variants = false                 true renders every variant right after the upload, otherwise on first request.
+
+A variant is never wider than the original, widths above it are left out and the original width is offered instead.
+Variants are rendered once and kept in <dir>/variants next to the originals.
+
+Decoding and encoding again drops every metadata block, so variants carry no EXIF. Originals are stripped of EXIF
+when they are uploaded, see strip_metadata.
+*/
+
+//An original that has to be turned upright is saved again at this quality
+const ORIGINAL_QUALITY: u8 = 92;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Webp,
+    Jpeg,
+}
+
+impl Format {
+    pub fn parse(ext: &str) -> Option<Format> {
+        match ext.to_lowercase().as_str() {
+            "webp" => Some(Format::Webp),
+            "jpeg" | "jpg" => Some(Format::Jpeg),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Webp => "webp",
+            Format::Jpeg => "jpeg",
+        }
+    }
+}
+
+//Only raster formats the image crate can decode get variants. GIFs would lose their animation.
+pub fn has_variants(mime_type: &str) -> bool {
+    matches!(mime_type, "image/png" | "image/jpeg" | "image/webp")
+}
+
+//The widths offered for an image of this width, smallest first
+pub fn widths_for(m: &Media, config: &MediaConfig) -> Vec<u32> {
+    let original = match (has_variants(&m.mime_type), m.width) {
+        (true, Some(width)) if width > 0 => width as u32,
+        _ => return Vec::new(),
+    };
+    let mut widths = config.variant_widths.iter().copied().filter(|w| *w > 0 && *w < original).collect::<Vec<u32>>();
+    widths.push(original);
+    widths.sort_unstable();
+    widths.dedup();
+    widths
+}
+
+pub fn formats(config: &MediaConfig) -> Vec<Format> {
+    config.variant_formats.iter().filter_map(|f| Format::parse(f)).collect()
+}
+
+pub fn url(m: &Media, width: u32, format: Format) -> String {
+    format!("/api/media/{}/variants/{}.{}", m.id, width, format.extension())
+}
+
+//{"webp": "/api/media/5/variants/320.webp 320w, /api/media/5/variants/640.webp 640w", ...}, ready for <source srcset>
+pub fn srcset(m: &Media, config: &MediaConfig) -> Option<Value> {
+    let widths = widths_for(m, config);
+    if widths.is_empty() {
+        return None;
+    }
+    let mut sets = serde_json::Map::new();
+    for format in formats(config) {
+        let set = widths.iter().map(|w| format!("{} {}w", url(m, *w, format), w)).collect::<Vec<String>>().join(", ");
+        sets.insert(String::from(format.extension()), json!(set));
+    }
+    Some(Value::Object(sets))
+}
+
+pub fn path(root: &Path, checksum: &str, width: u32, format: Format) -> PathBuf {
+    root.join("variants").join(format!("{}-{}.{}", checksum, width, format.extension()))
+}
+
+//Render the variant unless it is already on disk
+pub async fn ensure(original: PathBuf, dest: PathBuf, width: u32, format: Format, quality: u8) -> io::Result<PathBuf> {
+    if fs::metadata(&dest).await.is_ok() {
+        return Ok(dest);
+    }
+    //A missing original is a NotFound, not a decoding error
+    fs::metadata(&original).await?;
+    if let Some(dir) = dest.parent() {
+        fs::create_dir_all(dir).await?;
+    }
+
+    let bytes = rocket::tokio::task::spawn_blocking(move || render(&original, width, format, quality))
+        .await
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    //Two requests may render the same variant, the rename makes sure neither sees half a file
+    let partial = dest.with_extension(format!("{}.{}", format.extension(), OsRng.next_u32()));
+    fs::write(&partial, bytes).await?;
+    fs::rename(&partial, &dest).await?;
+    Ok(dest)
+}
+
+fn render(original: &Path, width: u32, format: Format, quality: u8) -> image::ImageResult<Vec<u8>> {
+    //Originals are already upright, see strip_metadata
+    let mut img = ImageReader::open(original)?.with_guessed_format()?.decode()?;
+    if width < img.width() {
+        img = img.resize(width, u32::MAX, FilterType::Lanczos3);
+    }
+
+    let mut bytes = Vec::new();
+    match format {
+        Format::Jpeg => img.to_rgb8().write_with_encoder(JpegEncoder::new_with_quality(&mut bytes, quality))?,
+        Format::Webp => img.to_rgba8().write_with_encoder(WebPEncoder::new_lossless(&mut bytes))?,
+    }
+    Ok(bytes)
+}
+
+//Width and height of an uploaded image, None when it cannot be decoded
+pub fn dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    ImageReader::new(Cursor::new(bytes)).with_guessed_format().ok()?.into_dimensions().ok()
+}
+
+//Drop EXIF from an upload. Camera pictures carry GPS positions.
+//The pixels are only touched when the EXIF orientation says the picture is turned, it is then saved upright.
+pub fn strip_metadata(mime_type: &str, bytes: Vec<u8>) -> Vec<u8> {
+    if let Some(upright) = upright(mime_type, &bytes) {
+        return upright;
+    }
+    let stripped = match mime_type {
+        "image/jpeg" => strip_jpeg(&bytes),
+        "image/png" => strip_png(&bytes),
+        "image/webp" => strip_webp(&bytes),
+        _ => None,
+    };
+    //A file that does not parse is kept as sent, decoding it later will fail the same way
+    stripped.unwrap_or(bytes)
+}
+
+fn upright(mime_type: &str, bytes: &[u8]) -> Option<Vec<u8>> {
+    if !has_variants(mime_type) {
+        return None;
+    }
+    let mut decoder = ImageReader::new(Cursor::new(bytes)).with_guessed_format().ok()?.into_decoder().ok()?;
+    let orientation = decoder.orientation().ok()?;
+    if orientation == Orientation::NoTransforms {
+        return None;
+    }
+    let mut img = DynamicImage::from_decoder(decoder).ok()?;
+    img.apply_orientation(orientation);
+
+    let mut out = Vec::new();
+    match mime_type {
+        "image/jpeg" => img.to_rgb8().write_with_encoder(JpegEncoder::new_with_quality(&mut out, ORIGINAL_QUALITY)).ok()?,
+        "image/png" => img.write_with_encoder(PngEncoder::new(&mut out)).ok()?,
+        _ => img.to_rgba8().write_with_encoder(WebPEncoder::new_lossless(&mut out)).ok()?,
+    }
+    Some(out)
+}
+
+//Copy every segment up to the image data except APP1 Exif blocks
+fn strip_jpeg(bytes: &[u8]) -> Option<Vec<u8>> {
+    if !bytes.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+    let mut out = Vec::with_capacity(bytes.len());
+    out.extend_from_slice(&bytes[..2]);
+    let mut i = 2;
+    loop {
+        if *bytes.get(i)? != 0xFF {
+            return None;
+        }
+        let marker = *bytes.get(i + 1)?;
+        //Start of scan, the rest is image data
+        if marker == 0xDA {
+            out.extend_from_slice(&bytes[i..]);
+            return Some(out);
+        }
+        let len = u16::from_be_bytes([*bytes.get(i + 2)?, *bytes.get(i + 3)?]) as usize;
+        if len < 2 {
+            return None;
+        }
+        let segment = bytes.get(i..i + 2 + len)?;
+        if !(marker == 0xE1 && segment[4..].starts_with(b"Exif\0")) {
+            out.extend_from_slice(segment);
+        }
+        i += 2 + len;
+    }
+}
+
+//Copy every chunk except eXIf
+fn strip_png(bytes: &[u8]) -> Option<Vec<u8>> {
+    const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+    if !bytes.starts_with(&SIGNATURE) {
+        return None;
+    }
+    let mut out = Vec::with_capacity(bytes.len());
+    out.extend_from_slice(&SIGNATURE);
+    let mut i = SIGNATURE.len();
+    while i < bytes.len() {
+        let len = u32::from_be_bytes(bytes.get(i..i + 4)?.try_into().ok()?) as usize;
+        //length, type, data and crc
+        let chunk = bytes.get(i..i + 12 + len)?;
+        if &chunk[4..8] != b"eXIf" {
+            out.extend_from_slice(chunk);
+        }
+        i += 12 + len;
+    }
+    Some(out)
+}
+
+//Copy every chunk except EXIF and XMP, and clear their flags in VP8X
+fn strip_webp(bytes: &[u8]) -> Option<Vec<u8>> {
+    if bytes.get(..4)? != b"RIFF" || bytes.get(8..12)? != b"WEBP" {
+        return None;
+    }
+    let mut out = Vec::with_capacity(bytes.len());
+    out.extend_from_slice(&bytes[..12]);
+    let mut i = 12;
+    while i < bytes.len() {
+        let len = u32::from_le_bytes(bytes.get(i + 4..i + 8)?.try_into().ok()?) as usize;
+        //fourcc, size, data and a pad byte when the size is odd, some writers leave it off the last chunk
+        if i + 8 + len > bytes.len() {
+            return None;
+        }
+        let chunk = &bytes[i..(i + 8 + len + len % 2).min(bytes.len())];
+        match &chunk[..4] {
+            b"EXIF" | b"XMP " => (),
+            b"VP8X" => {
+                let flags = out.len() + 8;
+                out.extend_from_slice(chunk);
+                *out.get_mut(flags)? &= !0x0C;
+            },
+            _ => out.extend_from_slice(chunk),
+        }
+        i += chunk.len();
+    }
+    let size = u32::try_from(out.len() - 8).ok()?;
+    out[4..8].copy_from_slice(&size.to_le_bytes());
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(fourcc: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut chunk = fourcc.to_vec();
+        chunk.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        chunk.extend_from_slice(data);
+        if data.len() % 2 == 1 {
+            chunk.push(0);
+        }
+        chunk
+    }
+
+    fn riff(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let body = chunks.concat();
+        let mut file = b"RIFF".to_vec();
+        file.extend_from_slice(&(body.len() as u32 + 4).to_le_bytes());
+        file.extend_from_slice(b"WEBP");
+        file.extend_from_slice(&body);
+        file
+    }
+
+    #[test]
+    fn webp_loses_exif_and_xmp() {
+        //EXIF and XMP flags set, a 1x1 canvas
+        let vp8x = chunk(b"VP8X", &[0x0C, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let image = chunk(b"VP8L", &[0x2F, 0, 0, 0, 0]);
+        let uploaded = riff(&[vp8x, image.clone(), chunk(b"EXIF", b"Exif\0\0GPS"), chunk(b"XMP ", b"<x:xmpmeta/>")]);
+
+        let stripped = strip_webp(&uploaded).unwrap();
+        assert_eq!(stripped, riff(&[chunk(b"VP8X", &[0; 10]), image]));
+    }
+}
@@ -0,0 +1,263 @@
+use chrono::{NaiveDate, TimeZone, Utc};
+use rocket::{Build, Catcher, Data, Request, Response, Rocket, Route};
+use rocket::fairing::{self, Fairing, Info, Kind};
+use rocket::http::Header;
+use rocket::http::uri::Origin;
+use rocket::request::{FromRequest, Outcome};
+use rocket::serde::Deserialize;
+use crate::models::EnvVariables;
+
+/*
+Every route under /api is mounted three times, with the same handlers:
+    /api/v2/...     The current version.
+    /api/v1/...     The first version, kept for the clients already deployed.
+    /api/...        Unversioned, v1 unless the Accept header asks for another version.
+
+An unversioned request picks its version with either of
+    Accept: application/vnd.blog.v2+json
+    Accept: application/json; version=2
+and is then routed as if it had been sent to /api/v2/... An explicit prefix wins over the header, an unknown version
+is ignored. Every answer under /api carries API-Version: <n> and Vary: Accept.
+
+v2 leaves out the v1 routes listed in REMOVED_IN_V2. On v1 they still work and answer with
+    Deprecation: @<unix time>              The day they were deprecated, RFC 9745.
+    Sunset: <http date>                    The day they go away, RFC 8594.
+    Link: </openapi.json>; rel="deprecation"
+
+[default.versions]
+deprecated = "2026-10-19"
+sunset = "2027-04-19"
+
+A change to the shape of a route or of AResponse goes into a new version: the changed handler is mounted on it
+alone, the old one is added to the removed list of the new version.
+*/
+
+//Handlers mounted on v1 only, with what replaces them on v2
+pub const REMOVED_IN_V2: [(&str, &str); 3] = [
+    ("update_role", "PATCH /api/v2/roles/{id}"),
+    ("delete_role", "DELETE /api/v2/roles/{id}"),
+    ("patch_post_tags", "PATCH /api/v2/posts/{id}/tags with a Tags body"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ApiVersion {
+    V1,
+    V2,
+}
+
+impl ApiVersion {
+    pub const ALL: [ApiVersion; 2] = [ApiVersion::V1, ApiVersion::V2];
+    pub const LATEST: ApiVersion = ApiVersion::V2;
+
+    pub fn number(self) -> u8 {
+        match self {
+            ApiVersion::V1 => 1,
+            ApiVersion::V2 => 2,
+        }
+    }
+
+    pub fn prefix(self) -> &'static str {
+        match self {
+            ApiVersion::V1 => "/api/v1",
+            ApiVersion::V2 => "/api/v2",
+        }
+    }
+
+    fn parse(number: &str) -> Option<ApiVersion> {
+        match number.trim() {
+            "1" => Some(ApiVersion::V1),
+            "2" => Some(ApiVersion::V2),
+            _ => None,
+        }
+    }
+
+    //The version named by the path, "/api/v2/posts" is v2 and "/api/posts" none
+    pub fn of_path(path: &str) -> Option<ApiVersion> {
+        let segment = path.strip_prefix("/api/")?.split('/').next()?;
+        ApiVersion::parse(segment.strip_prefix('v')?)
+    }
+
+    //Whether the handler is mounted on this version
+    pub fn serves(self, route: &Route) -> bool {
+        self == ApiVersion::V1 || !removed_in_v2(route)
+    }
+}
+
+//What replaces a v1 only handler on v2
+pub fn successor(route: &Route) -> Option<&'static str> {
+    let name = route.name.as_ref()?;
+    REMOVED_IN_V2.iter().find(|(removed, _)| name.as_ref() == *removed).map(|(_, successor)| *successor)
+}
+
+fn removed_in_v2(route: &Route) -> bool {
+    successor(route).is_some()
+}
+
+pub fn is_api(path: &str) -> bool {
+    path == "/api" || path.starts_with("/api/")
+}
+
+//"/api/v2/posts/3" -> "/api/posts/3"
+pub fn unversioned(path: &str) -> String {
+    match ApiVersion::of_path(path) {
+        Some(version) => format!("/api{}", &path[version.prefix().len()..]),
+        None => path.to_string(),
+    }
+}
+
+//The version asked for in Accept, application/vnd.blog.v2+json or application/json; version=2
+fn requested(request: &Request<'_>) -> Option<ApiVersion> {
+    let accept = request.headers().get_one("Accept")?;
+    accept.split(',').find_map(|media_type| {
+        let mut parts = media_type.split(';').map(str::trim);
+        let essence = parts.next()?.to_lowercase();
+        if let Some(number) = essence.strip_prefix("application/vnd.blog.v").and_then(|v| v.strip_suffix("+json")) {
+            return ApiVersion::parse(number);
+        }
+        parts
+            .filter_map(|p| p.split_once('='))
+            .find(|(k, _)| k.trim().eq_ignore_ascii_case("version"))
+            .and_then(|(_, v)| ApiVersion::parse(v.trim_matches('"')))
+    })
+}
+
+//The version a request is served with, v1 for everything unversioned
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ApiVersion {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(ApiVersion::of_path(request.uri().path().as_str()).unwrap_or(ApiVersion::V1))
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(crate = "rocket::serde")]
+pub struct VersionsConfig {
+    #[serde(default = "VersionsConfig::default_deprecated")]
+    pub deprecated: String,
+    #[serde(default = "VersionsConfig::default_sunset")]
+    pub sunset: String,
+}
+
+impl Default for VersionsConfig {
+    fn default() -> Self {
+        VersionsConfig {
+            deprecated: VersionsConfig::default_deprecated(),
+            sunset: VersionsConfig::default_sunset(),
+        }
+    }
+}
+
+impl VersionsConfig {
+    fn default_deprecated() -> String {
+        String::from("2026-10-19")
+    }
+
+    fn default_sunset() -> String {
+        String::from("2027-04-19")
+    }
+
+    fn date(field: &str, value: &str) -> Result<NaiveDate, String> {
+        NaiveDate::parse_from_str(value, "%Y-%m-%d").map_err(|_| format!("versions.{}: '{}' is not a YYYY-MM-DD date.", field, value))
+    }
+}
+
+pub trait MountVersions {
+    //Mounts the routes under /api<base>, /api/v1<base> and, less REMOVED_IN_V2, /api/v2<base>
+    fn mount_versions(self, base: &str, routes: Vec<Route>) -> Self;
+    fn register_versions(self, base: &str, catchers: Vec<Catcher>) -> Self;
+}
+
+impl MountVersions for Rocket<Build> {
+    fn mount_versions(self, base: &str, routes: Vec<Route>) -> Self {
+        let mut rocket = self.mount(format!("/api{}", base).as_str(), routes.clone());
+        for version in ApiVersion::ALL {
+            let served: Vec<Route> = routes.iter().filter(|r| version.serves(r)).cloned().collect();
+            rocket = rocket.mount(format!("{}{}", version.prefix(), base).as_str(), served);
+        }
+        rocket
+    }
+
+    fn register_versions(self, base: &str, catchers: Vec<Catcher>) -> Self {
+        let mut rocket = self.register(format!("/api{}", base).as_str(), catchers.clone());
+        for version in ApiVersion::ALL {
+            rocket = rocket.register(format!("{}{}", version.prefix(), base).as_str(), catchers.clone());
+        }
+        rocket
+    }
+}
+
+pub struct Versioning {
+    deprecation: String,
+    sunset: String,
+}
+
+#[rocket::async_trait]
+impl Fairing for Versioning {
+    fn info(&self) -> Info {
+        Info {
+            name: "API Versioning",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        let path = request.uri().path().as_str().to_string();
+        if !is_api(&path) || ApiVersion::of_path(&path).is_some() {
+            return;
+        }
+        let Some(version) = requested(request) else { return };
+        let uri = match request.uri().query() {
+            Some(query) => format!("{}{}?{}", version.prefix(), &path["/api".len()..], query.as_str()),
+            None => format!("{}{}", version.prefix(), &path["/api".len()..]),
+        };
+        match Origin::parse_owned(uri) {
+            Ok(uri) => request.set_uri(uri),
+            Err(e) => error!("Versioning: could not route {} to v{}: {}", path, version.number(), e),
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let path = request.uri().path().as_str();
+        if !is_api(path) {
+            return;
+        }
+        let version = ApiVersion::of_path(path).unwrap_or(ApiVersion::V1);
+        response.set_header(Header::new("API-Version", version.number().to_string()));
+        if response.headers().get_one("Vary").is_none() {
+            response.set_header(Header::new("Vary", "Accept"));
+        }
+        if request.route().is_some_and(|route| version == ApiVersion::V1 && removed_in_v2(route)) {
+            response.set_header(Header::new("Deprecation", self.deprecation.clone()));
+            response.set_header(Header::new("Sunset", self.sunset.clone()));
+            response.set_header(Header::new("Link", "</openapi.json>; rel=\"deprecation\""));
+        }
+    }
+}
+
+//Reads [<profile>.versions] and refuses to launch when a date does not parse.
+pub async fn attach_from_config(rocket: Rocket<Build>) -> fairing::Result {
+    let config = match rocket.figment().extract::<EnvVariables>() {
+        Ok(env) => env.versions,
+        Err(e) => {
+            error!("Could not read the versions configuration: {}", e);
+            return Err(rocket);
+        }
+    };
+    let dates = VersionsConfig::date("deprecated", &config.deprecated)
+        .and_then(|d| Ok((d, VersionsConfig::date("sunset", &config.sunset)?)));
+    match dates {
+        Ok((deprecated, sunset)) => {
+            let versioning = Versioning {
+                deprecation: format!("@{}", Utc.from_utc_datetime(&deprecated.and_hms_opt(0, 0, 0).unwrap_or_default()).timestamp()),
+                sunset: sunset.format("%a, %d %b %Y 00:00:00 GMT").to_string(),
+            };
+            Ok(rocket.attach(versioning))
+        },
+        Err(e) => {
+            error!("Invalid versions configuration. {}", e);
+            Err(rocket)
+        }
+    }
+}
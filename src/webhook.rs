@@ -0,0 +1,728 @@
+use std::time::{Duration, Instant};
+use chrono::NaiveDateTime;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use rand_core::{OsRng, RngCore};
+use rocket::serde::Deserialize;
+use rocket::serde::json::{Json, Value, json};
+use rocket::{Orbit, Rocket};
+use diesel::prelude::*;
+use crate::audit::{self, Resource};
+use crate::config::DbConn;
+use crate::error::ApiError;
+use crate::idempotency::{Keyed, NoStore};
+use crate::models::{AResponse, EnvVariables, LastInsertId, Webhook, WebhookAttempt, WebhookDelivery};
+use crate::schema::{post, webhook, webhook_attempt, webhook_delivery};
+
+/*
+Webhooks tell other services, such as the static frontend or a chat bot, when content changes.
+
+POST /api/webhooks {"url": "https://example.com/hook", "events": ["post.published", "tag.deleted"]}
+"*" subscribes to every event. The answer carries the secret, it is not shown again, a retry with the same Idempotency-Key gets the answer without it.
+
+Events come from the audit log, see audit.rs. Whatever it records for a post or a tag is announced:
+    post.created      post.updated      post.deleted      post.restored
+    post.published    a post becomes visible: it is created or changed with a date that has passed, or its date passes
+    tag.created       tag.updated       tag.deleted       tag.restored
+Deleting means moving to the trash. A merged tag is deleted.
+
+Each event becomes one delivery per subscribed webhook, queued in webhook_delivery in the same transaction as the
+audit entry. The body is
+    {"event": "post.updated", "occurred": "2026-10-19T18:00:00", "resource": "posts", "id": 3, "data": {...the post...}}
+sent as a POST with the headers
+    X-Webhook-Event: post.updated
+    X-Webhook-Delivery: 17
+    X-Webhook-Signature: sha256=<hex HMAC-SHA256 of the body, keyed with the secret>
+Receivers should compute the HMAC over the raw body and compare before trusting it.
+
+The dispatcher runs in the background, one delivery after the other. Any 2xx answer counts as delivered. Anything else,
+or no answer within timeout_seconds, is retried after base_delay_seconds, doubling each time up to six hours, until
+max_attempts is reached and the delivery is marked failed. Every attempt is kept in webhook_attempt.
+POST /api/webhooks/<id>/deliveries/<delivery_id>/redeliver queues a delivery again, failed or not.
+
+[default.webhooks]
+poll_seconds = 5
+timeout_seconds = 10
+max_attempts = 8
+base_delay_seconds = 30
+
+POST /api/webhooks/<id>/ping queues a ping event for that webhook alone. Any local HTTP listener stands in for a
+receiver while testing, for example `nc -lk 9000` and a webhook with the url http://localhost:9000/. The tests below
+send to one and check the signature, the body and the backoff after a failed answer.
+
+Times come from the database (db_now), the same clock that dates posts and the trash.
+
+Posts whose date passes are found by the dispatcher, at most poll_seconds late. Posts that come due while the
+server is down are not announced.
+*/
+
+pub const EVENTS: [&str; 9] = [
+    "post.created", "post.published", "post.updated", "post.deleted", "post.restored",
+    "tag.created", "tag.updated", "tag.deleted", "tag.restored",
+];
+
+//How long a delivery may wait between attempts
+const MAX_DELAY_SECONDS: u64 = 6 * 60 * 60;
+//Deliveries sent each time the dispatcher wakes up
+const BATCH: i64 = 20;
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(crate = "rocket::serde")]
+pub struct WebhookConfig {
+    #[serde(default = "WebhookConfig::default_poll")]
+    pub poll_seconds: u64,
+    #[serde(default = "WebhookConfig::default_timeout")]
+    pub timeout_seconds: u64,
+    #[serde(default = "WebhookConfig::default_attempts")]
+    pub max_attempts: i32,
+    #[serde(default = "WebhookConfig::default_delay")]
+    pub base_delay_seconds: u64,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        WebhookConfig {
+            poll_seconds: WebhookConfig::default_poll(),
+            timeout_seconds: WebhookConfig::default_timeout(),
+            max_attempts: WebhookConfig::default_attempts(),
+            base_delay_seconds: WebhookConfig::default_delay(),
+        }
+    }
+}
+
+impl WebhookConfig {
+    fn default_poll() -> u64 {
+        5
+    }
+
+    fn default_timeout() -> u64 {
+        10
+    }
+
+    fn default_attempts() -> i32 {
+        8
+    }
+
+    fn default_delay() -> u64 {
+        30
+    }
+
+    //Wait before the next attempt, after this many have failed
+    fn backoff(&self, failed: i32) -> chrono::Duration {
+        let doubled = self.base_delay_seconds.saturating_mul(1 << (failed - 1).clamp(0, 20));
+        chrono::Duration::seconds(doubled.min(MAX_DELAY_SECONDS) as i64)
+    }
+}
+
+//Every time here comes from the database, the clock that also dates posts and the trash
+pub fn db_now(c: &mut MysqlConnection) -> QueryResult<NaiveDateTime> {
+    diesel::select(diesel::dsl::now).get_result::<NaiveDateTime>(c)
+}
+
+fn subscribed(hook: &Webhook, event: &str) -> bool {
+    hook.events.split(',').any(|e| e == event || e == "*")
+}
+
+pub fn sign(secret: &str, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes a key of any size");
+    mac.update(body.as_bytes());
+    format!("{:x}", mac.finalize().into_bytes())
+}
+
+fn new_secret() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+//A post is published once it has a date that has passed by now and is not in the trash
+pub fn published(state: &Value, now: NaiveDateTime) -> bool {
+    let created = state.get("created").cloned().and_then(|c| serde_json::from_value::<Option<NaiveDateTime>>(c).ok()).flatten();
+    let trashed = state.get("deleted_at").is_some_and(|d| !d.is_null());
+    created.is_some_and(|c| c <= now) && !trashed
+}
+
+fn events_for(resource: Resource, action: &str, before: Option<&Value>, after: Option<&Value>, now: NaiveDateTime) -> Vec<&'static str> {
+    let newly_published = after.is_some_and(|a| published(a, now)) && !before.is_some_and(|b| published(b, now));
+    let mut events = match (resource, action) {
+        (Resource::Post, "create") => vec!["post.created"],
+        (Resource::Post, "update") => vec!["post.updated"],
+        (Resource::Post, "delete") => vec!["post.deleted"],
+        (Resource::Post, "restore") => vec!["post.restored"],
+        (Resource::Tag, "create") => vec!["tag.created"],
+        (Resource::Tag, "update") => vec!["tag.updated"],
+        (Resource::Tag, "delete") => vec!["tag.deleted"],
+        (Resource::Tag, "restore") => vec!["tag.restored"],
+        _ => return Vec::new(),
+    };
+    if resource == Resource::Post && action != "restore" && newly_published {
+        events.push("post.published");
+    }
+    events
+}
+
+//Queue a delivery of the event for every active webhook that wants it
+pub fn enqueue(c: &mut MysqlConnection, event: &str, resource: &str, resource_id: Option<i32>, data: Value) -> QueryResult<usize> {
+    let hooks = webhook::table
+        .filter(webhook::active.eq(true))
+        .load::<Webhook>(c)?
+        .into_iter()
+        .filter(|h| subscribed(h, event))
+        .collect::<Vec<Webhook>>();
+    if hooks.is_empty() {
+        return Ok(0);
+    }
+    let occurred = db_now(c)?;
+    let payload = json!({"event": event, "occurred": occurred, "resource": resource, "id": resource_id, "data": data}).to_string();
+    let rows = hooks.iter()
+        .map(|h| (
+            webhook_delivery::webhook_id.eq(h.id),
+            webhook_delivery::event.eq(event),
+            webhook_delivery::resource_id.eq(resource_id),
+            webhook_delivery::payload.eq(&payload),
+            webhook_delivery::next_attempt.eq(Some(occurred)),
+            webhook_delivery::created.eq(occurred),
+        ))
+        .collect::<Vec<_>>();
+    diesel::insert_into(webhook_delivery::table).values(rows).execute(c)
+}
+
+//Called by the audit log for every change it records, at the database time of the change
+pub fn announce(c: &mut MysqlConnection, resource: Resource, id: i32, action: &str, before: Option<&Value>, after: Option<&Value>, now: NaiveDateTime) -> QueryResult<()> {
+    let data = match after.or(before) {
+        Some(data) => data,
+        None => return Ok(()),
+    };
+    for event in events_for(resource, action, before, after, now) {
+        enqueue(c, event, resource.name(), Some(id), data.clone())?;
+    }
+    Ok(())
+}
+
+//Posts whose date passed between since and until. A post already announced when it was written is skipped.
+fn announce_due_posts(c: &mut MysqlConnection, since: NaiveDateTime, until: NaiveDateTime) -> QueryResult<()> {
+    let due = post::table
+        .filter(post::created.gt(since))
+        .filter(post::created.le(until))
+        .filter(post::deleted_at.is_null())
+        .select((post::id, post::created))
+        .load::<(i32, Option<NaiveDateTime>)>(c)?;
+    for (id, created) in due {
+        let announced = webhook_delivery::table
+            .filter(webhook_delivery::event.eq("post.published"))
+            .filter(webhook_delivery::resource_id.eq(id))
+            .filter(webhook_delivery::created.ge(created.unwrap_or(since)))
+            .count()
+            .get_result::<i64>(c)?;
+        if announced > 0 {
+            continue;
+        }
+        if let Some(data) = audit::snapshot(c, Resource::Post, id)? {
+            enqueue(c, "post.published", Resource::Post.name(), Some(id), data)?;
+        }
+    }
+    Ok(())
+}
+
+fn due(c: &mut MysqlConnection) -> QueryResult<Vec<(WebhookDelivery, Webhook)>> {
+    webhook_delivery::table
+        .inner_join(webhook::table)
+        .filter(webhook_delivery::status.eq("pending"))
+        .filter(webhook_delivery::next_attempt.le(diesel::dsl::now))
+        .filter(webhook::active.eq(true))
+        .order(webhook_delivery::next_attempt.asc())
+        .limit(BATCH)
+        .select((WebhookDelivery::as_select(), Webhook::as_select()))
+        .load(c)
+}
+
+//What came of one attempt
+struct Sent {
+    response_status: Option<i32>,
+    error: Option<String>,
+    duration_ms: i32,
+}
+
+async fn send(client: &reqwest::Client, hook: &Webhook, delivery: &WebhookDelivery) -> Sent {
+    let started = Instant::now();
+    let result = client.post(&hook.url)
+        .header("Content-Type", "application/json")
+        .header("X-Webhook-Event", &delivery.event)
+        .header("X-Webhook-Delivery", delivery.id.to_string())
+        .header("X-Webhook-Signature", format!("sha256={}", sign(&hook.secret, &delivery.payload)))
+        .body(delivery.payload.clone())
+        .send()
+        .await;
+    let duration_ms = started.elapsed().as_millis().min(i32::MAX as u128) as i32;
+    match result {
+        Ok(r) if r.status().is_success() => Sent { response_status: Some(r.status().as_u16() as i32), error: None, duration_ms },
+        Ok(r) => Sent { response_status: Some(r.status().as_u16() as i32), error: Some(format!("Answered {}.", r.status())), duration_ms },
+        Err(e) => Sent { response_status: None, error: Some(e.to_string().chars().take(255).collect()), duration_ms },
+    }
+}
+
+//Where a delivery stands after an attempt: its status, when to try again and when it was delivered
+fn after_attempt(attempts: i32, failed: bool, attempted: NaiveDateTime, config: &WebhookConfig) -> (&'static str, Option<NaiveDateTime>, Option<NaiveDateTime>) {
+    match failed {
+        false => ("delivered", None, Some(attempted)),
+        true if attempts >= config.max_attempts => ("failed", None, None),
+        true => ("pending", Some(attempted + config.backoff(attempts)), None),
+    }
+}
+
+fn record(c: &mut MysqlConnection, delivery: &WebhookDelivery, sent: Sent, config: &WebhookConfig) -> QueryResult<()> {
+    c.transaction(|c| {
+        let attempted = db_now(c)?;
+        diesel::insert_into(webhook_attempt::table)
+            .values((
+                webhook_attempt::delivery_id.eq(delivery.id),
+                webhook_attempt::attempted.eq(attempted),
+                webhook_attempt::response_status.eq(sent.response_status),
+                webhook_attempt::error.eq(&sent.error),
+                webhook_attempt::duration_ms.eq(sent.duration_ms),
+            ))
+            .execute(c)?;
+
+        let attempts = delivery.attempts + 1;
+        let (status, next_attempt, delivered) = after_attempt(attempts, sent.error.is_some(), attempted, config);
+        diesel::update(webhook_delivery::table.filter(webhook_delivery::id.eq(delivery.id)))
+            .set((
+                webhook_delivery::status.eq(status),
+                webhook_delivery::attempts.eq(attempts),
+                webhook_delivery::next_attempt.eq(next_attempt),
+                webhook_delivery::delivered.eq(delivered),
+            ))
+            .execute(c)?;
+        Ok(())
+    })
+}
+
+//Started once the server is up, runs until it shuts down
+pub async fn start_dispatcher(rocket: &Rocket<Orbit>) {
+    let config = match rocket.state::<EnvVariables>() {
+        Some(env) => env.webhooks.clone(),
+        None => return,
+    };
+    let pool = match DbConn::pool(rocket) {
+        Some(pool) => pool.clone(),
+        None => {
+            error!("Webhooks: no database pool, nothing will be delivered.");
+            return;
+        },
+    };
+    let client = match reqwest::Client::builder().timeout(Duration::from_secs(config.timeout_seconds)).build() {
+        Ok(client) => client,
+        Err(e) => {
+            error!("Webhooks: could not build the http client, nothing will be delivered: {:?}", e);
+            return;
+        },
+    };
+
+    rocket::tokio::spawn(async move {
+        let mut interval = rocket::tokio::time::interval(Duration::from_secs(config.poll_seconds.max(1)));
+        //The first round only learns the time, posts that came due before start up are not announced
+        let mut since: Option<NaiveDateTime> = None;
+        loop {
+            interval.tick().await;
+            let conn = match pool.get().await {
+                Some(conn) => conn,
+                None => {
+                    error!("Webhooks: no database connection, deliveries wait for the next round.");
+                    continue;
+                },
+            };
+
+            let announced = conn.run(move |c| {
+                let until = db_now(c)?;
+                announce_due_posts(c, since.unwrap_or(until), until)?;
+                Ok::<_, diesel::result::Error>(until)
+            }).await;
+            match announced {
+                Ok(until) => since = Some(until),
+                Err(e) => error!("Webhooks: could not look for posts that came due: {:?}", e),
+            }
+
+            let due = match conn.run(due).await {
+                Ok(due) => due,
+                Err(e) => {
+                    error!("Webhooks: could not load due deliveries: {:?}", e);
+                    continue;
+                },
+            };
+            for (delivery, hook) in due {
+                let sent = send(&client, &hook, &delivery).await;
+                let config = config.clone();
+                if let Err(e) = conn.run(move |c| record(c, &delivery, sent, &config)).await {
+                    error!("Webhooks: could not record an attempt: {:?}", e);
+                }
+            }
+        }
+    });
+}
+
+pub mod routes {
+    use super::*;
+    use rocket::response::status;
+    use crate::auth::Level1;
+    use diesel::result::Error::NotFound;
+
+    #[derive(Debug, Deserialize, schemars::JsonSchema)]
+    #[serde(crate = "rocket::serde")]
+    pub struct NewWebhook {
+        pub url: String,
+        pub events: Vec<String>,
+        //A random one is made when none is sent
+        pub secret: Option<String>,
+        pub active: Option<bool>,
+    }
+
+    #[derive(Debug, Deserialize, schemars::JsonSchema)]
+    #[serde(crate = "rocket::serde")]
+    pub struct UpdateWebhook {
+        pub url: Option<String>,
+        pub events: Option<Vec<String>>,
+        pub secret: Option<String>,
+        pub active: Option<bool>,
+    }
+
+    #[derive(AsChangeset)]
+    #[diesel(table_name = webhook)]
+    struct WebhookChanges {
+        url: Option<String>,
+        events: Option<String>,
+        secret: Option<String>,
+        active: Option<bool>,
+    }
+
+    fn not_found() -> ApiError {
+        ApiError::not_found(Some(String::from("Could not locate webhook with provided id.")))
+    }
+
+    fn delivery_not_found() -> ApiError {
+        ApiError::not_found(Some(String::from("Could not locate delivery with provided id.")))
+    }
+
+    fn webhook_json(hook: &Webhook) -> Value {
+        let mut v = json!(hook);
+        v["events"] = json!(hook.events.split(',').collect::<Vec<&str>>());
+        v
+    }
+
+    fn delivery_json(delivery: &WebhookDelivery) -> Value {
+        let mut v = json!(delivery);
+        v["payload"] = serde_json::from_str::<Value>(&delivery.payload).unwrap_or(Value::Null);
+        v
+    }
+
+    fn validate_url(url: &str, errors: &mut Vec<Value>) {
+        let lower = url.to_lowercase();
+        if !(lower.starts_with("http://") || lower.starts_with("https://")) || !(1..=255).contains(&url.len()) {
+            errors.push(json!({"field": "url", "message": "An http or https url of up to 255 chars."}));
+        }
+    }
+
+    fn validate_events(events: &[String], errors: &mut Vec<Value>) -> String {
+        if events.is_empty() {
+            errors.push(json!({"field": "events", "message": "Subscribe to at least one event."}));
+        }
+        for e in events {
+            if e != "*" && !EVENTS.contains(&e.as_str()) {
+                errors.push(json!({"field": "events", "message": format!("Unknown event '{}'. Known events: {}.", e, EVENTS.join(", "))}));
+            }
+        }
+        let mut events = events.to_vec();
+        events.sort();
+        events.dedup();
+        let joined = events.join(",");
+        if joined.len() > 255 {
+            errors.push(json!({"field": "events", "message": "Too many events, subscribe to \"*\" instead."}));
+        }
+        joined
+    }
+
+    fn validate_secret(secret: &str, errors: &mut Vec<Value>) {
+        if !(16..=64).contains(&secret.len()) {
+            errors.push(json!({"field": "secret", "message": "Valid length is 16 to 64 chars."}));
+        }
+    }
+
+    #[get("/")]
+    pub async fn get_all(conn: DbConn, _x: Level1) -> Result<Json<AResponse>, ApiError> {
+        let hooks = conn.run(|c| webhook::table.order(webhook::id.asc()).load::<Webhook>(c)).await?;
+        Ok(Json(AResponse::_200(Some(json!(hooks.iter().map(webhook_json).collect::<Vec<Value>>())))))
+    }
+
+    #[get("/<id>")]
+    pub async fn get(id: i32, conn: DbConn, _x: Level1) -> Result<Json<AResponse>, ApiError> {
+        let hook = conn.run(move |c| webhook::table.filter(webhook::id.eq(id)).first::<Webhook>(c)).await
+            .map_err(|e| match e {
+                NotFound => not_found(),
+                e => ApiError::from(e),
+            })?;
+        Ok(Json(AResponse::_200(Some(webhook_json(&hook)))))
+    }
+
+    //The answer is the only place the secret shows up, it is not kept for an Idempotency-Key
+    #[post("/", format="json", data="<new_hook>")]//Post 201 422
+    pub async fn post_(conn: DbConn, new_hook: Keyed<NewWebhook>, _x: Level1) -> Result<NoStore<status::Created<String>>, ApiError> {
+        let new_hook = new_hook.into_inner();
+        let mut errors = Vec::new();
+        validate_url(&new_hook.url, &mut errors);
+        let events = validate_events(&new_hook.events, &mut errors);
+        if let Some(secret) = &new_hook.secret {
+            validate_secret(secret, &mut errors);
+        }
+        if !errors.is_empty() {
+            return Err(ApiError::invalid_input(json!(errors)));
+        }
+
+        let secret = new_hook.secret.unwrap_or_else(new_secret);
+        let (url, active) = (new_hook.url, new_hook.active.unwrap_or(true));
+        let hook = conn.run(move |c| {
+            c.transaction(|c| {
+                diesel::insert_into(webhook::table)
+                    .values((webhook::url.eq(url), webhook::secret.eq(secret), webhook::events.eq(events), webhook::active.eq(active)))
+                    .execute(c)?;
+                let id = diesel::sql_query("SELECT LAST_INSERT_ID() AS id").get_result::<LastInsertId>(c)?.id as i32;
+                webhook::table.filter(webhook::id.eq(id)).first::<Webhook>(c)
+            })
+        }).await?;
+
+        let uri = uri!("/api/webhooks/", get(hook.id)).to_string();
+        let mut d = webhook_json(&hook);
+        d["secret"] = json!(hook.secret);
+        let mut response = AResponse::_201(Some(uri.clone()));
+        response.data = Some(d);
+        Ok(NoStore(status::Created::new(uri).body(json!(response).to_string())))
+    }
+
+    #[patch("/<id>", format="json", data="<changes>")]//Patch 204 404 422
+    pub async fn patch(id: i32, conn: DbConn, changes: Json<UpdateWebhook>, _x: Level1) -> Result<status::NoContent, ApiError> {
+        let changes = changes.into_inner();
+        let mut errors = Vec::new();
+        if let Some(url) = &changes.url {
+            validate_url(url, &mut errors);
+        }
+        let events = changes.events.as_ref().map(|e| validate_events(e, &mut errors));
+        if let Some(secret) = &changes.secret {
+            validate_secret(secret, &mut errors);
+        }
+        if !errors.is_empty() {
+            return Err(ApiError::invalid_input(json!(errors)));
+        }
+
+        let changes = WebhookChanges { url: changes.url, events, secret: changes.secret, active: changes.active };
+        let found = conn.run(move |c| {
+            let found = webhook::table.filter(webhook::id.eq(id)).count().get_result::<i64>(c)?;
+            if found > 0 && (changes.url.is_some() || changes.events.is_some() || changes.secret.is_some() || changes.active.is_some()) {
+                diesel::update(webhook::table.filter(webhook::id.eq(id))).set(&changes).execute(c)?;
+            }
+            QueryResult::Ok(found)
+        }).await?;
+        match found {
+            0 => Err(not_found()),
+            _ => Ok(status::NoContent),
+        }
+    }
+
+    //Its deliveries and their attempts go with it
+    #[delete("/<id>")]//Delete 204 404
+    pub async fn delete(id: i32, conn: DbConn, _x: Level1) -> Result<status::NoContent, ApiError> {
+        let rows = conn.run(move |c| diesel::delete(webhook::table.filter(webhook::id.eq(id))).execute(c)).await?;
+        match rows {
+            0 => Err(not_found()),
+            _ => Ok(status::NoContent),
+        }
+    }
+
+    //Queue a ping for this webhook alone. Like every delivery it waits while the webhook is not active.
+    #[post("/<id>/ping")]//Post 202 404
+    pub async fn ping(id: i32, conn: DbConn, _x: Level1) -> Result<status::Accepted<Json<AResponse>>, ApiError> {
+        let delivery_id = conn.run(move |c| {
+            c.transaction(|c| {
+                webhook::table.filter(webhook::id.eq(id)).select(webhook::id).first::<i32>(c)?;
+                let occurred = db_now(c)?;
+                let payload = json!({"event": "ping", "occurred": occurred, "resource": "webhooks", "id": id, "data": {"id": id}}).to_string();
+                diesel::insert_into(webhook_delivery::table)
+                    .values((
+                        webhook_delivery::webhook_id.eq(id),
+                        webhook_delivery::event.eq("ping"),
+                        webhook_delivery::resource_id.eq(Some(id)),
+                        webhook_delivery::payload.eq(payload),
+                        webhook_delivery::next_attempt.eq(Some(occurred)),
+                        webhook_delivery::created.eq(occurred),
+                    ))
+                    .execute(c)?;
+                diesel::sql_query("SELECT LAST_INSERT_ID() AS id").get_result::<LastInsertId>(c)
+            })
+        }).await.map_err(|e| match e {
+            NotFound => not_found(),
+            e => ApiError::from(e),
+        })?.id;
+
+        let uri = uri!("/api/webhooks/", get_delivery(id, delivery_id)).to_string();
+        Ok(status::Accepted(Some(Json(AResponse::_200(Some(json!({"delivery": delivery_id, "location": uri})))))))
+    }
+
+    //Newest first. ?status=pending, delivered or failed narrows the list.
+    #[get("/<id>/deliveries?<status>&<start>&<step>")]
+    pub async fn get_deliveries(id: i32, status: Option<String>, start: Option<i64>, step: Option<i64>, conn: DbConn, _x: Level1) -> Result<Json<AResponse>, ApiError> {
+        let deliveries = conn.run(move |c| {
+            webhook::table.filter(webhook::id.eq(id)).select(webhook::id).first::<i32>(c)?;
+            let mut query = webhook_delivery::table.filter(webhook_delivery::webhook_id.eq(id)).into_boxed();
+            if let Some(status) = status {
+                query = query.filter(webhook_delivery::status.eq(status));
+            }
+            query
+                .order(webhook_delivery::id.desc())
+                .offset(start.unwrap_or(0))
+                .limit(step.unwrap_or(100))
+                .load::<WebhookDelivery>(c)
+        }).await.map_err(|e| match e {
+            NotFound => not_found(),
+            e => ApiError::from(e),
+        })?;
+        Ok(Json(AResponse::_200(Some(json!(deliveries.iter().map(delivery_json).collect::<Vec<Value>>())))))
+    }
+
+    //One delivery with every attempt made for it, oldest first
+    #[get("/<id>/deliveries/<delivery_id>")]
+    pub async fn get_delivery(id: i32, delivery_id: i64, conn: DbConn, _x: Level1) -> Result<Json<AResponse>, ApiError> {
+        let (delivery, attempts) = conn.run(move |c| {
+            let delivery = webhook_delivery::table
+                .filter(webhook_delivery::id.eq(delivery_id))
+                .filter(webhook_delivery::webhook_id.eq(id))
+                .first::<WebhookDelivery>(c)?;
+            let attempts = webhook_attempt::table
+                .filter(webhook_attempt::delivery_id.eq(delivery_id))
+                .order(webhook_attempt::id.asc())
+                .load::<WebhookAttempt>(c)?;
+            QueryResult::Ok((delivery, attempts))
+        }).await.map_err(|e| match e {
+            NotFound => delivery_not_found(),
+            e => ApiError::from(e),
+        })?;
+
+        let mut d = delivery_json(&delivery);
+        d["attempts_made"] = json!(attempts);
+        Ok(Json(AResponse::_200(Some(d))))
+    }
+
+    //Queue the delivery again as it was first sent, with a fresh set of attempts. Earlier attempts are kept.
+    #[post("/<id>/deliveries/<delivery_id>/redeliver")]//Post 202 404
+    pub async fn redeliver(id: i32, delivery_id: i64, conn: DbConn, _x: Level1) -> Result<status::Accepted<Json<AResponse>>, ApiError> {
+        let rows = conn.run(move |c| {
+            diesel::update(webhook_delivery::table
+                .filter(webhook_delivery::id.eq(delivery_id))
+                .filter(webhook_delivery::webhook_id.eq(id)))
+                .set((
+                    webhook_delivery::status.eq("pending"),
+                    webhook_delivery::attempts.eq(0),
+                    webhook_delivery::next_attempt.eq(diesel::dsl::now.nullable()),
+                    webhook_delivery::delivered.eq(None::<NaiveDateTime>),
+                ))
+                .execute(c)
+        }).await?;
+        match rows {
+            0 => Err(delivery_not_found()),
+            _ => Ok(status::Accepted(Some(Json(AResponse::_200(Some(json!({"delivery": delivery_id, "status": "pending"}))))))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use rocket::tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use rocket::tokio::net::TcpListener;
+    use rocket::tokio::task::JoinHandle;
+
+    //A receiver on a free local port. It answers one request with the status line and hands back what it got.
+    async fn stand_in(status: &'static str) -> (String, JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let received = rocket::tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 4096];
+            loop {
+                let n = socket.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+                let text = String::from_utf8_lossy(&request).to_string();
+                let complete = text.split_once("\r\n\r\n").is_some_and(|(head, body)| {
+                    let length = head.lines()
+                        .find_map(|l| l.to_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse::<usize>().unwrap_or(0)))
+                        .unwrap_or(0);
+                    body.len() >= length
+                });
+                if complete || n == 0 {
+                    break;
+                }
+            }
+            socket.write_all(format!("HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status).as_bytes()).await.unwrap();
+            String::from_utf8_lossy(&request).to_string()
+        });
+        (url, received)
+    }
+
+    fn at() -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2026, 10, 19).unwrap().and_hms_opt(18, 0, 0).unwrap()
+    }
+
+    fn fixture(url: String) -> (Webhook, WebhookDelivery) {
+        let hook = Webhook { id: 1, url, secret: String::from("s3cret"), events: String::from("*"), active: true, created: at() };
+        let delivery = WebhookDelivery {
+            id: 17,
+            webhook_id: 1,
+            event: String::from("post.updated"),
+            resource_id: Some(3),
+            payload: json!({"event": "post.updated", "occurred": at(), "resource": "posts", "id": 3, "data": {"id": 3}}).to_string(),
+            status: String::from("pending"),
+            attempts: 0,
+            next_attempt: Some(at()),
+            created: at(),
+            delivered: None,
+        };
+        (hook, delivery)
+    }
+
+    fn client() -> reqwest::Client {
+        reqwest::Client::builder().timeout(Duration::from_secs(5)).build().unwrap()
+    }
+
+    #[rocket::async_test]
+    async fn sends_signed_body() {
+        let (url, received) = stand_in("200 OK").await;
+        let (hook, delivery) = fixture(url);
+        let sent = send(&client(), &hook, &delivery).await;
+        assert_eq!(sent.response_status, Some(200));
+        assert!(sent.error.is_none());
+
+        let request = received.await.unwrap();
+        let head = request.split("\r\n\r\n").next().unwrap().to_lowercase();
+        assert!(head.starts_with("post / http/1.1"));
+        assert!(head.contains(&format!("x-webhook-signature: sha256={}", sign(&hook.secret, &delivery.payload))));
+        assert!(head.contains("x-webhook-event: post.updated"));
+        assert!(head.contains("x-webhook-delivery: 17"));
+        assert!(request.ends_with(&delivery.payload));
+    }
+
+    #[rocket::async_test]
+    async fn retries_failed_answers_with_backoff() {
+        let (url, received) = stand_in("500 Internal Server Error").await;
+        let (hook, delivery) = fixture(url);
+        let sent = send(&client(), &hook, &delivery).await;
+        received.await.unwrap();
+        assert_eq!(sent.response_status, Some(500));
+        assert!(sent.error.is_some());
+
+        let config = WebhookConfig { max_attempts: 4, base_delay_seconds: 30, ..Default::default() };
+        let seconds = |s| Some(at() + chrono::Duration::seconds(s));
+        assert_eq!(after_attempt(1, true, at(), &config), ("pending", seconds(30), None));
+        assert_eq!(after_attempt(2, true, at(), &config), ("pending", seconds(60), None));
+        assert_eq!(after_attempt(3, true, at(), &config), ("pending", seconds(120), None));
+        assert_eq!(after_attempt(4, true, at(), &config), ("failed", None, None));
+        assert_eq!(after_attempt(2, false, at(), &config), ("delivered", None, Some(at())));
+    }
+}